@@ -1,3 +1,13 @@
+export type ImageEdit =
+    | { op: 'rotate', degrees: number }
+    | { op: 'flip', direction: 'horizontal' | 'vertical' }
+    | { op: 'crop', x: number, y: number, width: number, height: number }
+    | { op: 'brightness', factor: number }
+    | { op: 'contrast', factor: number }
+    | { op: 'grayscale' }
+    | { op: 'sharpen' }
+    | { op: 'blur' };
+
 export interface Image {
     id: number,
     name: string,
@@ -10,6 +20,10 @@ export interface Image {
     folderId: number,
     metadataId: number,
     slug: string,
+    focalPoint?: { x: number, y: number } | null,
+    placeholder?: { blurhash: string, lqip: string, dominantColor: string } | null,
+    edits?: ImageEdit[],
+    contentHash?: string | null,
     createdOn: string,
     createdBy: number,
     modifiedOn: string,
@@ -0,0 +1,19 @@
+export interface ImageMetadata {
+    imageId: number,
+    cameraMake?: string | null,
+    cameraModel?: string | null,
+    lens?: string | null,
+    captureDate?: string | null,
+    gpsLatitude?: number | null,
+    gpsLongitude?: number | null,
+    gpsAltitude?: number | null,
+    copyright?: string | null,
+    creator?: string | null,
+    caption?: string | null,
+    keywords: string[],
+    usageTerms?: string | null,
+    webStatement?: string | null,
+    rightsMarked?: boolean | null,
+}
+
+export default ImageMetadata;
@@ -7,6 +7,20 @@ export interface Rendition {
     slug: string,
     isPublished: boolean,
     encoding: string,
+    fit?: 'fit' | 'cover' | 'exact' | 'crop',
+    gravity?: string,
+    crop?: { x: number, y: number, width: number, height: number } | null,
+    quality?: number | null,
+    progressive?: boolean | null,
+    stripMetadata?: boolean | null,
+    watermark?: {
+        enabled: boolean,
+        asset: string,
+        gravity: string,
+        opacity: number,
+        scale: number,
+        tile: boolean,
+    } | null,
     createdOn: string,
     createdBy: number,
     modifiedOn: string,
@@ -3,7 +3,6 @@ export interface UploadImage {
     name: string,
     title: string,
     slug: string,
-    encoding: string,
     projectId: number,
     folderId: number,
 }
@@ -121,7 +121,6 @@ const NewImage = () => {
                 name: file.name || '',
                 title,
                 slug,
-                encoding: 'JPG',
                 projectId: store.currentProject.id,
                 folderId: store.currentFolder.id,
             };
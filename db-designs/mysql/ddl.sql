@@ -97,13 +97,44 @@ CREATE TABLE IMAGE (
     PROJECT_ID SMALLINT UNSIGNED,
     FOLDER_ID SMALLINT UNSIGNED DEFAULT NULL,
     SLUG VARCHAR(128),
+    FOCAL_X FLOAT DEFAULT NULL,
+    FOCAL_Y FLOAT DEFAULT NULL,
+    BLURHASH VARCHAR(64) DEFAULT NULL,
+    LQIP TEXT DEFAULT NULL,
+    DOMINANT_COLOR CHAR(7) DEFAULT NULL,
+    EDITS TEXT DEFAULT NULL,
+    CONTENT_HASH CHAR(64) DEFAULT NULL,
+    PERCEPTUAL_HASH BIGINT UNSIGNED DEFAULT NULL,
     CREATED_BY SMALLINT UNSIGNED DEFAULT 0,
     MODIFIED_BY SMALLINT UNSIGNED DEFAULT 0,
     CREATED_ON DATETIME DEFAULT NOW(),
     MODIFIED_ON DATETIME DEFAULT NOW(),
 
     PRIMARY KEY (ID),
-    UNIQUE (FOLDER_ID, SLUG)
+    UNIQUE (FOLDER_ID, SLUG),
+    INDEX (CONTENT_HASH)
+);
+
+CREATE TABLE IMAGE_METADATA (
+    IMAGE_ID INT UNSIGNED,
+    CAMERA_MAKE VARCHAR(128) DEFAULT NULL,
+    CAMERA_MODEL VARCHAR(128) DEFAULT NULL,
+    LENS VARCHAR(128) DEFAULT NULL,
+    CAPTURE_DATE DATETIME DEFAULT NULL,
+    GPS_LATITUDE DOUBLE DEFAULT NULL,
+    GPS_LONGITUDE DOUBLE DEFAULT NULL,
+    GPS_ALTITUDE DOUBLE DEFAULT NULL,
+    COPYRIGHT VARCHAR(512) DEFAULT NULL,
+    CREATOR VARCHAR(256) DEFAULT NULL,
+    CAPTION TEXT DEFAULT NULL,
+    KEYWORDS TEXT DEFAULT NULL,
+    USAGE_TERMS TEXT DEFAULT NULL,
+    WEB_STATEMENT VARCHAR(1000) DEFAULT NULL,
+    RIGHTS_MARKED BOOLEAN DEFAULT NULL,
+
+    PRIMARY KEY (IMAGE_ID),
+    INDEX (CAPTURE_DATE),
+    FOREIGN KEY (IMAGE_ID) REFERENCES IMAGE(ID) ON DELETE CASCADE
 );
 
 CREATE TABLE IMAGE_RENDITION (
@@ -115,6 +146,16 @@ CREATE TABLE IMAGE_RENDITION (
     TARGET_DEVICE VARCHAR(16) DEFAULT NULL,
     SLUG VARCHAR(256),
     PUBLISHED BOOLEAN DEFAULT FALSE,
+    FIT VARCHAR(8) DEFAULT 'fit',
+    GRAVITY VARCHAR(10) DEFAULT 'center',
+    CROP_X SMALLINT UNSIGNED DEFAULT NULL,
+    CROP_Y SMALLINT UNSIGNED DEFAULT NULL,
+    CROP_WIDTH SMALLINT UNSIGNED DEFAULT NULL,
+    CROP_HEIGHT SMALLINT UNSIGNED DEFAULT NULL,
+    QUALITY TINYINT UNSIGNED DEFAULT NULL,
+    PROGRESSIVE BOOLEAN DEFAULT NULL,
+    STRIP_METADATA BOOLEAN DEFAULT NULL,
+    WATERMARK TEXT DEFAULT NULL,
     CREATED_BY SMALLINT UNSIGNED DEFAULT 0,
     MODIFIED_BY SMALLINT UNSIGNED DEFAULT 0,
     CREATED_ON DATETIME DEFAULT NOW(),
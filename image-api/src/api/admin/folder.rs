@@ -8,6 +8,7 @@ use crate::{
     server::db::DBError, auth::AuthMiddleware, server::config::ServerConfig,
     repository::Repository,
     model::folder::Folder, api::service::remove::remove_folders,
+    storage::Storages,
 };
 
 #[get("/api/admin/folder/{folder_id}/")]
@@ -94,7 +95,7 @@ pub async fn update_folder (
 #[delete("/api/admin/folder")]
 pub async fn remove_folder (
     repo: Data<dyn Repository + Sync + Send>, req: HttpRequest,
-    _: AuthMiddleware, conf: Data<ServerConfig>
+    _: AuthMiddleware, conf: Data<ServerConfig>, storage: Data<Storages>,
 ) -> HttpResponse {
     let qs = QString::from(req.query_string());
 
@@ -115,6 +116,7 @@ pub async fn remove_folder (
         &mut folder_ids,
         conf.rendition_cache_dir.clone(),
         conf.upload_dir.clone(),
+        &storage,
     ) {
         Ok (_) => {
             if folder_ids.len() > 1 {
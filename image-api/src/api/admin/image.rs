@@ -1,16 +1,37 @@
+use std::collections::HashMap;
+
 use actix_web::{
-    web::{ Json, block, Data }, HttpResponse, HttpRequest, post, get, put, delete,
+    web::{ block, Json, Data }, HttpResponse, HttpRequest, post, get, put, delete,
 };
-use std::fs::{ read, rename };
-use serde::Serialize;
+use serde::{ Serialize, Deserialize };
 use qstring::QString;
-use chrono::Utc;
+use chrono::{ NaiveDate, NaiveDateTime };
 use log::{ debug, error };
 
 use crate::{
     server::db::DBError, auth::AuthMiddleware, server::config::ServerConfig,
-    repository::Repository, model::{ image::Image, upload_image::UploadImage },
-    api::{ admin::SuccessResponse, service::remove::remove_images, },
+    repository::Repository,
+    model::{
+        image::{ Image, FocalPoint }, upload_image::UploadImage,
+        image_metadata::ImageMetadata,
+        edit::{ EditOperation, validate_edits },
+    },
+    api::{
+        admin::SuccessResponse,
+        service::{
+            remove::{
+                remove_images, remove_crop_rendition_files, remove_rendition_cache,
+            },
+            edit::original_dimensions,
+            stream::serve_file, cache::ContentVersion,
+            placeholder::save_placeholder,
+            content::original_path,
+            sniff::UploadError,
+            ingest::{ ingest_upload, IngestError },
+            similarity::{ cluster, DEFAULT_THRESHOLD, MAX_THRESHOLD },
+        },
+    },
+    storage::Storages, server_state::ServerState,
 };
 
 #[derive(Serialize)]
@@ -18,30 +39,90 @@ pub struct ImageResponse {
     images: Vec<Image>
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FocalPointRequest {
+    /// `null` removes the focal point.
+    focal_point: Option<FocalPoint>,
+}
+
+#[derive(Deserialize)]
+pub struct EditsRequest {
+    edits: Vec<EditOperation>,
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ImageSaveResponse<'a> {
     success: bool,
     message: &'a str,
     image_id: Option<u32>,
+    /// IDs of the near-duplicates of the saved image.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    similar_images: Vec<u32>,
+    /// Why the upload was rejected.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<UploadError>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateCluster {
+    images: Vec<Image>,
+    /// Largest Hamming distance between the hashes of two of the images.
+    max_distance: u32,
+}
+
+#[derive(Serialize)]
+pub struct DuplicatesResponse {
+    success: bool,
+    message: String,
+    clusters: Vec<DuplicateCluster>,
+}
+
+/// Parses a `YYYY-MM-DD` capture date filter, `end_of_day` selects the last
+/// second of the day (for inclusive upper bounds).
+fn parse_capture_date(date: Option<&str>, end_of_day: bool)
+    -> Result<Option<NaiveDateTime>, ()> {
+    match date {
+        Some(d) => match NaiveDate::parse_from_str(d, "%Y-%m-%d") {
+            Ok(date) => Ok(
+                if end_of_day {
+                    date.and_hms_opt(23, 59, 59)
+                } else {
+                    date.and_hms_opt(0, 0, 0)
+                }
+            ),
+            Err(_) => Err(()),
+        },
+
+        None => Ok(None),
+    }
 }
 
+/// Returns images in a project.
+///
+/// ## URL Parameters
+/// - `captured-from` - (Optional) Only images captured on or after the date
+///   (`YYYY-MM-DD`).
+/// - `captured-to` - (Optional) Only images captured on or before the date.
 #[get("/api/admin/project/{project_id}/images")]
 pub async fn get_images_in_project(
     repo: Data<dyn Repository + Sync + Send>,
     req: HttpRequest,
     _: AuthMiddleware
 ) -> HttpResponse {
-    let project_id: String = req.match_info().get("project_id")
-        .unwrap().parse().unwrap();
+    let project_id: u32 = match req.match_info().get("project_id")
+        .unwrap_or("").parse() {
+        Ok(id) => id,
+        Err(_) => { return HttpResponse::BadRequest().body("Invalid project id"); }
+    };
     debug!("Fetching images for project: {}", project_id);
-    let images_wrapped;
+    let mut images_wrapped;
 
     match repo.get_image_repo() {
         Ok(mut img_repo) => {
-            images_wrapped = img_repo.get_all_from_project(
-                project_id.parse::<u32>().unwrap()
-            );
+            images_wrapped = img_repo.get_all_from_project(project_id);
         }
 
         Err(_) => {
@@ -51,6 +132,51 @@ pub async fn get_images_in_project(
     }
 
 
+    let qs = QString::from(req.query_string());
+    let captured_from = qs.get("captured-from");
+    let captured_to = qs.get("captured-to");
+
+    if captured_from.is_some() || captured_to.is_some() {
+        let from: Option<NaiveDateTime>;
+        let to: Option<NaiveDateTime>;
+
+        match (
+            parse_capture_date(captured_from, false),
+            parse_capture_date(captured_to, true),
+        ) {
+            (Ok(f), Ok(t)) => { from = f; to = t; }
+            _ => {
+                return HttpResponse::BadRequest()
+                    .body("Capture dates must be in the YYYY-MM-DD format");
+            }
+        }
+
+        let captured_ids: Vec<u32>;
+
+        match repo.get_image_metadata_repo() {
+            Ok(mut meta_repo) => {
+                match meta_repo.get_image_ids_captured_between(
+                    project_id, from, to
+                ) {
+                    Ok(ids) => { captured_ids = ids; }
+                    Err(_) => {
+                        return HttpResponse::InternalServerError()
+                            .body("Some internal error occured!");
+                    }
+                }
+            }
+
+            Err(_) => {
+                return HttpResponse::InternalServerError()
+                    .body("Some internal error occured!");
+            }
+        }
+
+        images_wrapped = images_wrapped.map(|images| images.into_iter()
+            .filter(|i| captured_ids.contains(&i.id))
+            .collect());
+    }
+
     match images_wrapped {
         Ok (images) => {
             HttpResponse::Ok().json(ImageResponse {images})
@@ -113,91 +239,57 @@ pub async fn add_image(
     repo: Data<dyn Repository + Sync + Send>,
     req_image: Json<UploadImage>,
     _: AuthMiddleware,
-    conf: Data<ServerConfig>
+    conf: Data<ServerConfig>,
+    storage: Data<Storages>,
+    state: Data<ServerState>,
 ) -> HttpResponse {
     debug!("Got request for upload id: {}", req_image.upload_id);
 
-    let mut image = Image {
-        id: 0,
-        name: req_image.name.clone(),
-        title: req_image.title.clone(),
-        slug: req_image.slug.clone(),
-        encoding: req_image.encoding,
-        height: 0,
-        width: 0,
-        is_published: true,
-        project_id: req_image.project_id,
-        folder_id: req_image.folder_id,
-        created_on: Utc::now(),
-        created_by: 0,
-        modified_on: Utc::now(),
-        modified_by: 0,
-    };
-
-    // Get image height and width from file
-    let source_file_path: String = format!(
-        "temp/{}{}",
-        req_image.upload_id,
-        image.encoding.extension()
-    );
-
-    debug!("source file path: {}", source_file_path);
-
-    let raster_img = raster::open(source_file_path.as_str()).unwrap();
-
-    image.height = raster_img.height as u16;
-    image.width = raster_img.width as u16;
+    let details = req_image.into_inner();
 
-    // Add image to the db
-    match repo.get_image_repo() {
-        Ok(mut img_repo) => {
-            match img_repo.add(image.clone()) {
-                Ok (id) => {
-                    // Finally, change temp image path
-                    let dest_file_path = format!(
-                        "{}/{}{}",
-                        conf.upload_dir,
-                        id,                         // id of image after add transaction committed
-                        image.encoding.extension()
-                    );
-
-                    match rename(source_file_path, dest_file_path) {
-                        Ok (_) => HttpResponse::Ok().json(ImageSaveResponse {
-                            success: true,
-                            message: "Image Saved",
-                            image_id: Some(id)
-                        }),
-
-                        Err (e) => {
-                            error!(
-                                "An I/O error occured while adding an image: {}", e
-                            );
-
-                            return HttpResponse::InternalServerError().json(
-                                ImageSaveResponse {
-                                    success: false,
-                                    message:
-                                        "There was some problem. Please try again.",
-                                    image_id: None
-                            });
-                        }
-                    }
-                }
-
-                Err (_s) => HttpResponse::InternalServerError().json(
-                    ImageSaveResponse {
-                        success: false,
-                        message: "There was some problem. Please try again.",
-                        image_id: None
-                })
-            }
+    // Hashing, storing and decoding the upload block.
+    match block(move || ingest_upload(&repo, &conf, &storage, &state, &details)).await
+        .unwrap_or(Err(IngestError::InternalError)) {
+        Ok(ingested) => HttpResponse::Ok().json(ImageSaveResponse {
+            success: true,
+            message: if ingested.similar_images.is_empty() {
+                "Image Saved"
+            } else {
+                "Image Saved, but similar images exist"
+            },
+            image_id: Some(ingested.id),
+            similar_images: ingested.similar_images,
+            error: None,
+        }),
+
+        Err(IngestError::NotFound) => HttpResponse::NotFound().json(ImageSaveResponse {
+            success: false,
+            message: "The upload doesn't exist",
+            image_id: None,
+            similar_images: vec![],
+            error: None,
+        }),
+
+        Err(IngestError::Rejected(rejection)) => {
+            let message = rejection.to_string();
+
+            HttpResponse::build(rejection.status_code()).json(ImageSaveResponse {
+                success: false,
+                message: &message,
+                image_id: None,
+                similar_images: vec![],
+                error: Some(rejection.to_error()),
+            })
         }
 
-        Err(_) => HttpResponse::InternalServerError().json(ImageSaveResponse {
-            success: false,
-            message: "There was some problem. Please try again.",
-            image_id: None
-        })
+        Err(IngestError::InternalError) => HttpResponse::InternalServerError().json(
+            ImageSaveResponse {
+                success: false,
+                message: "There was some problem. Please try again.",
+                image_id: None,
+                similar_images: vec![],
+                error: None,
+        }),
     }
 }
 
@@ -209,7 +301,7 @@ pub async fn add_image(
 #[delete("/api/admin/image")]
 pub async fn remove_image(
     repo: Data<dyn Repository + Sync + Send>, req: HttpRequest, _: AuthMiddleware,
-    conf: Data<ServerConfig>,
+    conf: Data<ServerConfig>, storage: Data<Storages>,
 ) -> HttpResponse {
     let qs = QString::from(req.query_string());
 
@@ -226,7 +318,8 @@ pub async fn remove_image(
     }
 
     match remove_images(
-        &repo, &image_ids, conf.rendition_cache_dir.clone(), conf.upload_dir.clone()
+        &repo, &image_ids, conf.rendition_cache_dir.clone(), conf.upload_dir.clone(),
+        &storage,
     ) {
         Ok (_) => {
             if image_ids.len() > 1 {
@@ -256,7 +349,8 @@ pub async fn get_image_file(
     repo: Data<dyn Repository + Sync + Send>,
     req: HttpRequest,
     _: AuthMiddleware,
-    conf: Data<ServerConfig>
+    conf: Data<ServerConfig>,
+    storage: Data<Storages>,
 ) -> HttpResponse {
     let image_id:u32 = req.match_info().get("image_id").unwrap().parse()
         .unwrap();
@@ -265,20 +359,30 @@ pub async fn get_image_file(
         Ok(mut img_repo) => {
             match img_repo.get(image_id) {
                 Ok (image) => {
-                    let image_file_path = format!(
-                        "{}/{}{}",
-                        conf.upload_dir,
-                        image.id,
-                        image.encoding.extension()
-                    );
-
-                    let image_file = block(
-                        move || read(String::from(image_file_path))
-                    ).await.unwrap().expect("Error whie downloading!");
-
-                    HttpResponse::Ok()
-                        .content_type(image.encoding.mime_type())
-                        .body(image_file)
+                    let image_file_path = original_path(&conf.upload_dir, &image);
+
+                    let fetch_path = image_file_path.clone();
+
+                    if let Ok(Err(e)) = block(
+                        move || storage.fetch_original(&fetch_path)
+                    ).await {
+                        error!("Error while fetching image file: {}", e);
+                    }
+
+                    // Originals are never replaced, edits are applied to
+                    // the renditions.
+                    let version = ContentVersion {
+                        key: format!("image-{}", image.id),
+                        modified_on: image.created_on,
+                    };
+
+                    serve_file(
+                        &req,
+                        image_file_path,
+                        image.encoding.mime_type(),
+                        "private, no-cache",
+                        Some(&version),
+                    ).await
                 }
 
                 Err (e) => {
@@ -322,3 +426,346 @@ pub async fn update(
     }
 }
 
+/// Returns the metadata (EXIF, IPTC and XMP) extracted from the image file.
+#[get("/api/admin/image/{image_id}/metadata")]
+pub async fn get_image_metadata(
+    repo: Data<dyn Repository + Sync + Send>,
+    req: HttpRequest,
+    _: AuthMiddleware
+) -> HttpResponse {
+    let image_id: u32;
+
+    match req.match_info().get("image_id").unwrap_or("").parse::<u32>() {
+        Ok(id) => { image_id = id; }
+        Err(_) => { return HttpResponse::BadRequest().body("BAD REQUEST"); }
+    }
+
+    match repo.get_image_metadata_repo() {
+        Ok(mut meta_repo) => {
+            match meta_repo.get(image_id) {
+                Ok(metadata) => HttpResponse::Ok().json(metadata),
+
+                // Images without any embedded metadata.
+                Err(DBError::NotFound) => HttpResponse::Ok().json(
+                    ImageMetadata { image_id, ..Default::default() }
+                ),
+
+                Err(_) => HttpResponse::InternalServerError()
+                    .body("Internal Server Error"),
+            }
+        }
+
+        Err(_) => HttpResponse::InternalServerError()
+            .body("Internal Server Error")
+    }
+}
+
+/// Sets the focal point of an image and removes the cached renditions that
+/// crop the image, so that they are re-generated around the new focal point.
+#[put("/api/admin/image/{image_id}/focal-point")]
+pub async fn set_focal_point(
+    repo: Data<dyn Repository + Sync + Send>,
+    req: HttpRequest,
+    focal_req: Json<FocalPointRequest>,
+    _: AuthMiddleware,
+    conf: Data<ServerConfig>,
+    storage: Data<Storages>,
+) -> HttpResponse {
+    let image_id: u32;
+
+    match req.match_info().get("image_id").unwrap_or("").parse::<u32>() {
+        Ok(id) => { image_id = id; }
+        Err(_) => { return HttpResponse::BadRequest().body("BAD REQUEST"); }
+    }
+
+    if let Some(focal_point) = focal_req.focal_point {
+        if !focal_point.is_valid() {
+            return HttpResponse::BadRequest().json(SuccessResponse::new(
+                false,
+                String::from("Focal point coordinates must be between 0 and 1"),
+            ));
+        }
+    }
+
+    let mut img_repo;
+
+    match repo.get_image_repo() {
+        Ok(i_repo) => { img_repo = i_repo; }
+        Err(e) => {
+            error!("Error while getting image repo: {}", e);
+
+            return HttpResponse::InternalServerError().json(
+                SuccessResponse::new(
+                    false,
+                    String::from("Some internal server error occurred."),
+                )
+            );
+        }
+    }
+
+    let mut image: Image;
+
+    match img_repo.get(image_id) {
+        Ok(img) => { image = img; }
+        Err(DBError::NotFound) => {
+            return HttpResponse::NotFound().json(SuccessResponse::new(
+                false, String::from("Image not found"),
+            ));
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(
+                SuccessResponse::new(
+                    false,
+                    String::from("Some internal server error occurred."),
+                )
+            );
+        }
+    }
+
+    if image.focal_point == focal_req.focal_point {
+        return HttpResponse::Ok().json(SuccessResponse::new(
+            true, String::from("Focal point unchanged"),
+        ));
+    }
+
+    match img_repo.set_focal_point(image_id, focal_req.focal_point) {
+        Ok(_) => {}
+        Err(msg) => {
+            return HttpResponse::InternalServerError()
+                .json(SuccessResponse::new(false, msg));
+        }
+    }
+
+    image.focal_point = focal_req.focal_point;
+
+    // The focal point is saved, renditions that could not be removed are
+    // reported in the message.
+    let message = match block(move || remove_crop_rendition_files(
+        &repo, &conf.rendition_cache_dir, &image, &storage
+    )).await {
+        Ok(Ok(_)) => String::from("Focal point updated"),
+        Ok(Err(msg)) => format!("Focal point updated but {}", msg.to_lowercase()),
+        Err(_) => String::from(
+            "Focal point updated but the cached renditions could not be removed"
+        ),
+    };
+
+    HttpResponse::Ok().json(SuccessResponse::new(true, message))
+}
+
+/// Replaces the edit stack of an image. The edits are applied to the original
+/// image, in order, whenever its renditions are generated.
+#[put("/api/admin/image/{image_id}/edits")]
+pub async fn set_edits(
+    repo: Data<dyn Repository + Sync + Send>,
+    req: HttpRequest,
+    edits_req: Json<EditsRequest>,
+    _: AuthMiddleware,
+    conf: Data<ServerConfig>,
+    storage: Data<Storages>,
+) -> HttpResponse {
+    match req.match_info().get("image_id").unwrap_or("").parse::<u32>() {
+        Ok(image_id) => update_edits(
+            repo, conf, storage, image_id, edits_req.into_inner().edits
+        ).await,
+
+        Err(_) => HttpResponse::BadRequest().body("BAD REQUEST"),
+    }
+}
+
+/// Removes all the edits of an image, reverting it to the original.
+#[delete("/api/admin/image/{image_id}/edits")]
+pub async fn revert_edits(
+    repo: Data<dyn Repository + Sync + Send>,
+    req: HttpRequest,
+    _: AuthMiddleware,
+    conf: Data<ServerConfig>,
+    storage: Data<Storages>,
+) -> HttpResponse {
+    match req.match_info().get("image_id").unwrap_or("").parse::<u32>() {
+        Ok(image_id) => update_edits(repo, conf, storage, image_id, vec![]).await,
+        Err(_) => HttpResponse::BadRequest().body("BAD REQUEST"),
+    }
+}
+
+/// Why an edit stack was not saved.
+enum EditsError {
+    NotFound,
+    Invalid(String),
+    InternalError(String),
+}
+
+/// Validates and saves an edit stack, then removes the image's cached
+/// renditions and regenerates its placeholders.
+async fn update_edits(
+    repo: Data<dyn Repository + Sync + Send>, conf: Data<ServerConfig>,
+    storage: Data<Storages>, image_id: u32, edits: Vec<EditOperation>,
+) -> HttpResponse {
+    // Fetching and decoding the original and removing the cached renditions
+    // block.
+    match block(move || save_edits(&repo, &conf, &storage, image_id, edits)).await {
+        Ok(Ok(msg)) => HttpResponse::Ok().json(SuccessResponse::new(true, msg)),
+
+        Ok(Err(EditsError::NotFound)) => HttpResponse::NotFound().json(
+            SuccessResponse::new(false, String::from("Image not found"))
+        ),
+
+        Ok(Err(EditsError::Invalid(msg))) => HttpResponse::BadRequest()
+            .json(SuccessResponse::new(false, msg)),
+
+        Ok(Err(EditsError::InternalError(msg))) => HttpResponse::InternalServerError()
+            .json(SuccessResponse::new(false, msg)),
+
+        Err(_) => HttpResponse::InternalServerError().json(SuccessResponse::new(
+            false, String::from("Some internal server error occurred."),
+        )),
+    }
+}
+
+/// Returns the message of the response. Edits that were saved but whose
+/// stale renditions could not be removed are reported in the message.
+fn save_edits(
+    repo: &Data<dyn Repository + Sync + Send>, conf: &ServerConfig,
+    storage: &Storages, image_id: u32, edits: Vec<EditOperation>,
+) -> Result<String, EditsError> {
+    let internal_error = || {
+        EditsError::InternalError(String::from("Some internal server error occurred."))
+    };
+
+    let mut img_repo = repo.get_image_repo().map_err(|e| {
+        error!("Error while getting image repo: {}", e);
+        internal_error()
+    })?;
+
+    let mut image: Image = match img_repo.get(image_id) {
+        Ok(img) => img,
+        Err(DBError::NotFound) => { return Err(EditsError::NotFound); }
+        Err(_) => { return Err(internal_error()); }
+    };
+
+    if image.edits == edits {
+        return Ok(String::from("Edits unchanged"));
+    }
+
+    let src_file_path = original_path(&conf.upload_dir, &image);
+
+    if let Err(e) = storage.fetch_original(&src_file_path) {
+        error!("Error while fetching image file: {}", e);
+    }
+
+    let (width, height) = match original_dimensions(&src_file_path) {
+        Ok((w, h)) => validate_edits(&edits, w, h)
+            .map_err(|error_msgs| EditsError::Invalid(error_msgs.join(", ")))?,
+
+        Err(_) => {
+            return Err(EditsError::InternalError(
+                String::from("Unable to read the image file.")
+            ));
+        }
+    };
+
+    img_repo.set_edits(image_id, &edits, width, height)
+        .map_err(EditsError::InternalError)?;
+
+    image.edits = edits;
+
+    save_placeholder(repo, image_id, &src_file_path, &image.edits);
+
+    match remove_rendition_cache(repo, &conf.rendition_cache_dir, &image, storage) {
+        Ok(_) => Ok(String::from("Edits updated")),
+        Err(msg) => Ok(format!("Edits updated but {}", msg.to_lowercase())),
+    }
+}
+
+/// Lists the clusters of near-duplicate images (by the Hamming distance of
+/// their perceptual hashes). Older images are left out until their hashes
+/// are computed (in the background, at start up).
+///
+/// ## URL parameters:
+/// - `project-id` - Limits the search to a project. When omitted, the images
+///   of all the projects are clustered together, a cluster may span
+///   projects.
+/// - `threshold` - Highest distance between near-duplicates (0 - 32),
+///   defaults to 10.
+#[get("/api/admin/image-duplicates")]
+pub async fn get_duplicates(
+    repo: Data<dyn Repository + Sync + Send>,
+    req: HttpRequest,
+    _: AuthMiddleware,
+) -> HttpResponse {
+    let qs = QString::from(req.query_string());
+
+    let project_id: Option<u32> = match qs.get("project-id").map(|p| p.parse()) {
+        Some(Ok(p)) => Some(p),
+        Some(Err(_)) => {
+            return HttpResponse::BadRequest().json(DuplicatesResponse {
+                success: false,
+                message: String::from("Invalid project id"),
+                clusters: vec![],
+            });
+        }
+        None => None,
+    };
+
+    let threshold: u32 = match qs.get("threshold").map(|t| t.parse::<u32>()) {
+        Some(Ok(t)) if t <= MAX_THRESHOLD => t,
+        Some(_) => {
+            return HttpResponse::BadRequest().json(DuplicatesResponse {
+                success: false,
+                message: format!(
+                    "Threshold must be a number between 0 and {}", MAX_THRESHOLD
+                ),
+                clusters: vec![],
+            });
+        }
+        None => DEFAULT_THRESHOLD,
+    };
+
+    match block(move || find_duplicates(&repo, project_id, threshold)).await {
+        Ok(Ok(clusters)) => HttpResponse::Ok().json(DuplicatesResponse {
+            success: true,
+            message: format!("Found {} cluster(s)", clusters.len()),
+            clusters,
+        }),
+
+        _ => HttpResponse::InternalServerError().json(DuplicatesResponse {
+            success: false,
+            message: String::from("Some internal server error occurred."),
+            clusters: vec![],
+        }),
+    }
+}
+
+fn find_duplicates(
+    repo: &Data<dyn Repository + Sync + Send>, project_id: Option<u32>,
+    threshold: u32,
+) -> Result<Vec<DuplicateCluster>, ()> {
+    let mut img_repo = repo.get_image_repo().map_err(|e| {
+        error!("Error while getting image repo: {}", e);
+    })?;
+
+    let hashes: Vec<(u32, u64)> = img_repo.get_perceptual_hashes(project_id)
+        .map_err(|_| ())?
+        .into_iter()
+        .filter_map(|(image_id, hash)| Some((image_id, hash?)))
+        .collect();
+
+    let found = cluster(&hashes, threshold);
+
+    let ids: Vec<u32> = found.iter().flat_map(|(ids, _)| ids.iter().copied()).collect();
+
+    let mut images: HashMap<u32, Image> = match img_repo.get_all_by_ids(&ids) {
+        Ok(images) => images.into_iter().map(|image| (image.id, image)).collect(),
+        Err(DBError::NotFound) => HashMap::new(),
+        Err(_) => { return Err(()); }
+    };
+
+    Ok(found.into_iter()
+        .map(|(image_ids, max_distance)| DuplicateCluster {
+            images: image_ids.iter().filter_map(|id| images.remove(id)).collect(),
+            max_distance,
+        })
+        // Images removed meanwhile
+        .filter(|c| c.images.len() > 1)
+        .collect())
+}
@@ -0,0 +1,180 @@
+use std::{ io::Write, fs::{ File, remove_file } };
+
+use actix_multipart::Multipart;
+use actix_web::{ HttpResponse, HttpRequest, post, web::{ block, Data } };
+use futures::{ StreamExt, TryStreamExt };
+use serde::Serialize;
+use qstring::QString;
+use uuid::Uuid;
+use log::error;
+
+use crate::{
+    api::service::{
+        import::{ check_target, Importer, ImportEntry, ImportStatus },
+        sniff::UPLOAD_DIR,
+    },
+    auth::AuthMiddleware, repository::Repository, server::config::ServerConfig,
+    server_state::ServerState, storage::Storages,
+};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportResponse {
+    success: bool,
+    message: String,
+    imported: usize,
+    skipped: usize,
+    failed: usize,
+    entries: Vec<ImportEntry>,
+}
+
+impl ImportResponse {
+    fn error(message: String) -> Self {
+        Self {
+            success: false,
+            message,
+            imported: 0,
+            skipped: 0,
+            failed: 0,
+            entries: vec![],
+        }
+    }
+}
+
+/// Imports a ZIP or tar (optionally gzipped) archive, the `payload` field of
+/// the multipart body. Directories are imported as folders and files as
+/// images, the response lists what happened to each of them.
+///
+/// ## URL parameters:
+/// - `project-id` - Required. The project to import into.
+/// - `folder-id` - Optional. The folder to import into, the project's root
+///   when omitted.
+#[post("/api/admin/import")]
+pub async fn import_archive(
+    mut payload: Multipart, req: HttpRequest, _: AuthMiddleware,
+    repo: Data<dyn Repository + Sync + Send>, conf: Data<ServerConfig>,
+    storage: Data<Storages>, state: Data<ServerState>,
+) -> HttpResponse {
+    let qs = QString::from(req.query_string());
+
+    let project_id: u32 = match qs.get("project-id").map(|p| p.parse()) {
+        Some(Ok(p)) => p,
+        _ => {
+            return HttpResponse::BadRequest().json(ImportResponse::error(
+                String::from("Request missing a valid \"project-id\" parameter."),
+            ));
+        }
+    };
+
+    let folder_id: u32 = match qs.get("folder-id").map(|f| f.parse()) {
+        Some(Ok(f)) => f,
+        Some(Err(_)) => {
+            return HttpResponse::BadRequest().json(ImportResponse::error(
+                String::from("Invalid \"folder-id\" parameter."),
+            ));
+        }
+        None => 0,
+    };
+
+    if let Err(msg) = check_target(&repo, project_id, folder_id) {
+        return HttpResponse::NotFound().json(ImportResponse::error(msg));
+    }
+
+    let max_size = conf.upload.max_archive_size;
+
+    while let Ok(Some(mut field)) = payload.try_next().await {
+        let cd = field.content_disposition();
+
+        if cd.get_name() != Some("payload") { continue; }
+
+        let archive_path = format!("{}/{}.archive", UPLOAD_DIR, Uuid::new_v4());
+        let fname = archive_path.clone();
+
+        let mut file = match block(move || File::create(fname)).await {
+            Ok(Ok(f)) => f,
+            _ => {
+                error!("Error while creating {}", archive_path);
+
+                return HttpResponse::InternalServerError().json(ImportResponse::error(
+                    String::from("Some error occured while uploading..."),
+                ));
+            }
+        };
+
+        let mut received: u64 = 0;
+
+        while let Some(chunk) = field.next().await {
+            let data = match chunk {
+                Ok(d) => d,
+                Err(_) => {
+                    let _ = remove_file(&archive_path);
+
+                    return HttpResponse::BadRequest().json(ImportResponse::error(
+                        String::from("The archive couldn't be received"),
+                    ));
+                }
+            };
+
+            received += data.len() as u64;
+
+            if received > max_size {
+                let _ = remove_file(&archive_path);
+
+                return HttpResponse::PayloadTooLarge().json(ImportResponse::error(
+                    format!("The archive is larger than {} bytes", max_size),
+                ));
+            }
+
+            file = match block(move || file.write_all(&data).map(|_| file)).await {
+                Ok(Ok(f)) => f,
+                _ => {
+                    let _ = remove_file(&archive_path);
+
+                    return HttpResponse::InternalServerError().json(ImportResponse::error(
+                        String::from("Some error occured while uploading..."),
+                    ));
+                }
+            };
+        }
+
+        drop(file);
+
+        let result = block(move || {
+            let mut importer = Importer::new(
+                &repo, &conf, &storage, &state, project_id, folder_id,
+            );
+
+            let result = importer.import_archive(&archive_path);
+            let _ = remove_file(&archive_path);
+
+            result.map(|_| ImportResponse {
+                success: importer.count(ImportStatus::Failed) == 0,
+                message: if importer.count(ImportStatus::Failed) == 0 {
+                    String::from("Archive imported")
+                } else {
+                    String::from("Some entries couldn't be imported")
+                },
+                imported: importer.count(ImportStatus::Imported),
+                skipped: importer.count(ImportStatus::Skipped),
+                failed: importer.count(ImportStatus::Failed),
+                entries: importer.entries,
+            })
+        }).await;
+
+        return match result {
+            Ok(Ok(response)) => HttpResponse::Ok().json(response),
+
+            Ok(Err(msg)) => {
+                HttpResponse::UnprocessableEntity().json(ImportResponse::error(msg))
+            }
+
+            Err(_) => HttpResponse::InternalServerError().json(ImportResponse::error(
+                String::from("Some internal server error occurred."),
+            )),
+        };
+    }
+
+    HttpResponse::BadRequest().json(ImportResponse::error(
+        String::from("Request missing a \"payload\" field."),
+    ))
+}
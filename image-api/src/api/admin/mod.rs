@@ -5,6 +5,9 @@ pub mod project;
 pub mod rendition;
 pub mod role;
 pub mod folder;
+pub mod signed_url;
+pub mod upload;
+pub mod import;
 
 use actix_web::{ HttpResponse, HttpRequest, get, web::Data };
 use serde::Serialize;
@@ -12,6 +12,7 @@ use crate::{
     },
     auth::AuthMiddleware, server::{db::DBError, config::ServerConfig}, repository::Repository,
     model::{ user::User, project::{ Project, validate_project } },
+    storage::Storages,
 };
 
 #[derive(Serialize)]
@@ -120,6 +121,7 @@ pub async fn get_projects(
 pub async fn remove_project(
     repo: Data<dyn Repository + Sync + Send>,
     config: Data<ServerConfig>,
+    storage: Data<Storages>,
     _: AuthMiddleware,
     req: HttpRequest,
 ) -> HttpResponse {
@@ -183,7 +185,8 @@ pub async fn remove_project(
                                 repo.clone(),
                                 &mut folder_ids,
                                 config.rendition_cache_dir.clone(),
-                                config.upload_dir.clone()
+                                config.upload_dir.clone(),
+                                &storage,
                             ) {
                                 Ok(_) => {}
                                 Err(msg) => { e_msgs.push(msg); }
@@ -229,7 +232,8 @@ pub async fn remove_project(
                                 &repo,
                                 &image_ids,
                                 config.rendition_cache_dir.clone(),
-                                config.upload_dir.clone()
+                                config.upload_dir.clone(),
+                                &storage,
                             ) {
                                 Ok(_) => {}
                                 Err(msg) => { e_msgs.push(msg); }
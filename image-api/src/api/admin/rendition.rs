@@ -1,20 +1,27 @@
-use std::rc::Rc;
+use std::{ fs::remove_file, rc::Rc };
 
 use actix_web::{
-    HttpResponse, HttpRequest, get, post, delete, web::{ Json, Data }
+    HttpResponse, HttpRequest, get, post, delete, web::{ Json, Data, block }
 };
 use serde::{ Serialize, Deserialize };
 use raster::Image as RasterImage;
 use qstring::QString;
+use uuid::Uuid;
 use log::{ debug, error };
 
 use crate::{
     api::service::{
-        path::{ resize_and_save_rendition, get_image_path },
-        remove::remove_rendition_file,
+        path::{ resize_and_save_rendition, get_image_path, cache_rendition_file },
+        encode::EncodeOptions, edit::open_edited,
+        remove::remove_rendition_file, stream::serve_file,
+        content::original_path, watermark::load_watermark,
     },
     server::db::DBError, auth::AuthMiddleware, server::config::ServerConfig,
-    repository::Repository, model::{ rendition::Rendition, image::Image },
+    repository::Repository,
+    model::{
+        rendition::{ Rendition, validate_rendition }, image::Image,
+    },
+    storage::Storages,
 };
 
 #[derive(Serialize)]
@@ -149,13 +156,132 @@ pub async fn get_rendition(
     }
 }
 
+/// Generates a rendition (including its watermark) and returns the file, so
+/// that it can be reviewed before the rendition gets published.
+///
+/// The preview is generated on every request and is never cached.
+#[get("/api/admin/rendition/{rendition_id}/preview")]
+pub async fn preview_rendition(
+    repo: Data<dyn Repository + Sync + Send>,
+    req: HttpRequest,
+    _: AuthMiddleware,
+    conf: Data<ServerConfig>,
+    storage: Data<Storages>,
+) -> HttpResponse {
+    let rendition: Rendition;
+    let image: Image;
+
+    match req.match_info().get("rendition_id").unwrap_or("").parse::<u32>() {
+        Ok(rendition_id) => {
+            match repo.get_rendition_repo() {
+                Ok(mut ren_repo) => {
+                    match ren_repo.get(rendition_id) {
+                        Ok(r) => { rendition = r; }
+                        Err(DBError::NotFound) => {
+                            return HttpResponse::NotFound().body("Not Found");
+                        }
+                        Err(e) => {
+                            error!("Error while fetching rendition: {}", e);
+                            return HttpResponse::InternalServerError()
+                                .body("Internal Server Error");
+                        }
+                    }
+                }
+
+                Err(e) => {
+                    error!("Error while getting rendition repository: {}", e);
+                    return HttpResponse::InternalServerError()
+                        .body("Internal Server Error");
+                }
+            }
+        }
+
+        Err(_) => { return HttpResponse::BadRequest().body("BAD REQUEST"); }
+    }
+
+    match repo.get_image_repo() {
+        Ok(mut img_repo) => {
+            match img_repo.get(rendition.image_id) {
+                Ok(i) => { image = i; }
+                Err(_) => {
+                    return HttpResponse::InternalServerError()
+                        .body("Internal Server Error");
+                }
+            }
+        }
+
+        Err(e) => {
+            error!("Error while getting image repository: {}", e);
+            return HttpResponse::InternalServerError()
+                .body("Internal Server Error");
+        }
+    }
+
+    let project_config;
+
+    match get_image_path(&repo, &image) {
+        Ok(image_path) => {
+            project_config = conf.get_project_config(
+                image_path.split('/').next().unwrap_or("")
+            );
+        }
+
+        Err(_) => {
+            return HttpResponse::InternalServerError()
+                .body("Internal Server Error");
+        }
+    }
+
+    let src_file_path = original_path(&conf.upload_dir, &image);
+
+    // Unique per request, concurrent previews of a rendition don't share
+    // the file.
+    let preview_file_path = format!(
+        "temp/preview-{}{}", Uuid::new_v4(), rendition.encoding.extension()
+    );
+
+    let dest = preview_file_path.clone();
+    let mime_type = rendition.encoding.mime_type();
+
+    let resp = match block(move || {
+        if storage.fetch_original(&src_file_path).is_err() {
+            return Err(());
+        }
+
+        let watermark = load_watermark(
+            &repo, &conf.upload_dir, &storage, rendition.watermark.as_ref(),
+            project_config.watermark.as_ref(),
+        )?;
+
+        cache_rendition_file(
+            &src_file_path, &dest, &rendition, &image, &project_config,
+            watermark.as_ref(),
+        )
+    }).await {
+        Ok(Ok(_)) => {
+            serve_file(
+                &req, preview_file_path.clone(), mime_type, "private, no-store", None,
+            ).await
+        }
+
+        _ => HttpResponse::InternalServerError()
+            .body("Error while generating the preview")
+    };
+
+    // The response streams from the open file, which outlives its path.
+    let _ = remove_file(&preview_file_path);
+
+    resp
+}
+
 /// Creates multiple renditions for a single image.
 #[post("/api/admin/renditions")]
 pub async fn set_rendition(
     repository: Data<dyn Repository + Sync + Send>,
     req: Json<RenditionRequest>,
     _: AuthMiddleware,
-    conf: Data<ServerConfig>
+    conf: Data<ServerConfig>,
+    storage: Data<Storages>,
 ) -> HttpResponse {
     let mut unsuccessful_renditions: Vec<UnsuccessfulRendition> = vec![];
     let mut internal_error: bool = false;
@@ -236,16 +362,17 @@ pub async fn set_rendition(
             "{}/{}", conf.rendition_cache_dir, image_path
         );
 
-        if req.eager {
-            let src_file_path = format!(
-                "{}/{}{}", conf.upload_dir, image.id, image.encoding.extension()
-            );
+        let src_file_path = original_path(&conf.upload_dir, &image);
 
-            match raster::open(src_file_path.as_str()) {
+        // The image path starts with the project slug.
+        let project_config = conf.get_project_config(
+            image_path.split('/').next().unwrap_or("")
+        );
+
+        if req.eager && storage.fetch_original(&src_file_path).is_ok() {
+            match open_edited(src_file_path.as_str(), &image.edits) {
                 Ok (r_img) => { image_raster_option = Some(Rc::new(r_img)); }
-                Err (_) => {
-                    error!("Error loading image file: {}", src_file_path);
-                }
+                Err (_) => {}
             }
         }
 
@@ -257,6 +384,36 @@ pub async fn set_rendition(
                 rendition_to_add.width = image.width;
             }
 
+            let (valid, error_msgs) = validate_rendition(
+                &rendition_to_add, &image
+            );
+
+            if !valid {
+                unsuccessful_renditions.push(UnsuccessfulRendition {
+                    id: rendition.id,
+                    message: error_msgs.join(", "),
+                });
+
+                continue;
+            }
+
+            let watermark_image_id = rendition_to_add.watermark.as_ref()
+                .map_or(0, |w| w.image_id);
+
+            if watermark_image_id != 0 && repository.get_image_repo()
+                .map(|mut img_repo| img_repo.get(watermark_image_id).is_err())
+                .unwrap_or(true) {
+                unsuccessful_renditions.push(UnsuccessfulRendition {
+                    id: rendition.id,
+                    message: format!(
+                        "Watermark image having id \"{}\" not found",
+                        watermark_image_id
+                    ),
+                });
+
+                continue;
+            }
+
             match repository.get_rendition_repo() {
                 Ok(mut ren_repo) => {
                     match ren_repo.add(rendition_to_add.clone()) {
@@ -279,13 +436,23 @@ pub async fn set_rendition(
                                     rendition_to_add.height,
                                 );
 
-                                match resize_and_save_rendition(
+                                match load_watermark(
+                                    &repository, &conf.upload_dir, &storage,
+                                    rendition_to_add.watermark.as_ref(),
+                                    project_config.watermark.as_ref(),
+                                ).and_then(|watermark| resize_and_save_rendition(
                                     Rc::make_mut(&mut r_img),
                                     dest_path,
-                                    rendition_to_add.width,
-                                    rendition_to_add.height
-                                ) {
-                                    Ok(_) => {},
+                                    &rendition_to_add,
+                                    image.focal_point,
+                                    &EncodeOptions::for_rendition(
+                                        &rendition_to_add,
+                                        &project_config.output,
+                                        &src_file_path
+                                    ),
+                                    watermark.as_ref(),
+                                )) {
+                                    Ok(_) => { storage.store_rendition(dest_path); },
                                     Err(_) => {},
                                 };
                             }
@@ -354,7 +521,8 @@ pub async fn delete_rendition(
     repository: Data<dyn Repository + Sync + Send>,
     req: HttpRequest,
     _: AuthMiddleware,
-    conf: Data<ServerConfig>
+    conf: Data<ServerConfig>,
+    storage: Data<Storages>,
 ) -> HttpResponse {
     if let Some(rid) = req.match_info().get("rendition_id") {
         let mut ren_repo;
@@ -409,7 +577,8 @@ pub async fn delete_rendition(
                         }
 
                         if remove_rendition_file(
-                            &conf.rendition_cache_dir, &image_path, &rendition
+                            &conf.rendition_cache_dir, &image_path, &rendition,
+                            &storage,
                         ) {
                             return HttpResponse::Ok().json(StandardResponse {
                                 success: true,
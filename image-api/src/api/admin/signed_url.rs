@@ -0,0 +1,143 @@
+use actix_web::{ HttpResponse, post, web::{ Json, Data } };
+use serde::{ Serialize, Deserialize };
+use chrono::{ DateTime, Utc, TimeZone };
+use qstring::QString;
+use log::{ debug, error };
+
+use crate::{
+    api::service::{
+        path::{ split_path, get_rendition_from_path_segments },
+        signing::{ sign, url_message, signed_params, MAX_EXPIRES_IN },
+    },
+    auth::AuthMiddleware, server::config::ServerConfig, repository::Repository,
+    model::{ error::ErrorType, transform::TransformParams },
+};
+
+/// Validity of a signed URL when none is requested (1 day).
+const DEFAULT_EXPIRES_IN: i64 = 24 * 60 * 60;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedUrlRequest {
+    /// Public image path, optionally with transformation parameters.
+    /// e.g.: `my-project/folder/hero.jpg?w=640&h=360`
+    path: String,
+
+    /// Validity of the URL in seconds.
+    expires_in: Option<i64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedUrlResponse {
+    success: bool,
+    message: String,
+    url: Option<String>,
+    expires_on: Option<DateTime<Utc>>,
+}
+
+impl SignedUrlResponse {
+    fn error(message: &str) -> Self {
+        Self {
+            success: false,
+            message: String::from(message),
+            url: None,
+            expires_on: None,
+        }
+    }
+}
+
+/// Creates a signed, expiring URL for an image path.
+///
+/// Signed URLs give temporary access to unpublished renditions and renditions
+/// of projects that restrict their users, e.g. for sharing images for review.
+#[post("/api/admin/signed-url")]
+pub async fn create_signed_url(
+    repo: Data<dyn Repository + Sync + Send>,
+    req: Json<SignedUrlRequest>,
+    conf: Data<ServerConfig>,
+    _: AuthMiddleware,
+) -> HttpResponse {
+    let expires_in = req.expires_in.unwrap_or(DEFAULT_EXPIRES_IN);
+
+    if expires_in <= 0 || expires_in > MAX_EXPIRES_IN {
+        return HttpResponse::BadRequest().json(SignedUrlResponse::error(
+            format!(
+                "expiresIn must be between 1 and {} seconds", MAX_EXPIRES_IN
+            ).as_str()
+        ));
+    }
+
+    let (path, query_string) = match req.path.split_once('?') {
+        Some((p, q)) => (p, q),
+        None => (req.path.as_str(), ""),
+    };
+
+    let path = path.trim_start_matches("/api/image/");
+
+    if path.is_empty() {
+        return HttpResponse::BadRequest()
+            .json(SignedUrlResponse::error("No path supplied"));
+    }
+
+    let params: Option<TransformParams>;
+
+    match TransformParams::from_query(query_string) {
+        Ok(p) => { params = p; }
+        Err(msg) => {
+            return HttpResponse::BadRequest()
+                .json(SignedUrlResponse::error(msg.as_str()));
+        }
+    }
+
+    let path_segments = split_path(path);
+
+    if let Err(e) = get_rendition_from_path_segments(&repo, &path_segments) {
+        return match e.error_type {
+            ErrorType::NotFound => HttpResponse::NotFound()
+                .json(SignedUrlResponse::error("Image not found")),
+
+            ErrorType::InternalError => {
+                error!("Error while resolving path: {}", path);
+
+                HttpResponse::InternalServerError()
+                    .json(SignedUrlResponse::error("Some error occured"))
+            }
+        };
+    }
+
+    let expiry = Utc::now().timestamp() + expires_in;
+    let normalized_path = path_segments.join("/");
+    let signed_query = signed_params(params.as_ref(), query_string);
+
+    let signature = sign(
+        &conf.url_signing_secret,
+        &url_message(&normalized_path, signed_query.as_deref(), Some(expiry)),
+    );
+
+    if signature.is_empty() {
+        return HttpResponse::InternalServerError()
+            .json(SignedUrlResponse::error("Could not sign URL"));
+    }
+
+    // Keep the transformation parameters and drop any older signature.
+    let mut query = QString::new(
+        QString::from(query_string).into_pairs().into_iter()
+            .filter(|(k, _)| k != "exp" && k != "sig")
+            .collect::<Vec<(String, String)>>()
+    );
+
+    query.add_pair(("exp", expiry.to_string()));
+    query.add_pair(("sig", signature));
+
+    let url = format!("/api/image/{}?{}", normalized_path, query);
+
+    debug!("Signed URL created: {}", url);
+
+    HttpResponse::Ok().json(SignedUrlResponse {
+        success: true,
+        message: String::from("Signed URL created"),
+        url: Some(url),
+        expires_on: Utc.timestamp_opt(expiry, 0).single(),
+    })
+}
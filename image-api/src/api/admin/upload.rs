@@ -0,0 +1,60 @@
+use actix_web::{ HttpResponse, HttpRequest, get, delete, web::{ block, Data } };
+use serde::Serialize;
+use chrono::{ DateTime, Utc };
+
+use crate::{
+    api::{ admin::SuccessResponse, service::cleanup::cancel_upload as cancel },
+    auth::AuthMiddleware, server::config::ServerConfig, server_state::ServerState,
+};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadStatsResponse {
+    success: bool,
+    /// Number of accepted uploads that no image was created from yet.
+    pending_count: usize,
+    /// Total size of the pending uploads, in bytes.
+    pending_bytes: u64,
+    oldest_uploaded_on: Option<DateTime<Utc>>,
+    /// Hours after which pending uploads are removed.
+    pending_ttl_hours: u32,
+}
+
+/// Returns the statistics of the pending uploads.
+#[get("/api/admin/upload-stats")]
+pub async fn get_upload_stats(
+    state: Data<ServerState>, conf: Data<ServerConfig>, _: AuthMiddleware,
+) -> HttpResponse {
+    let (pending_count, pending_bytes, oldest_uploaded_on) =
+        state.get_pending_upload_stats();
+
+    HttpResponse::Ok().json(UploadStatsResponse {
+        success: true,
+        pending_count,
+        pending_bytes,
+        oldest_uploaded_on,
+        pending_ttl_hours: conf.upload.pending_ttl_hours,
+    })
+}
+
+/// Cancels an upload (complete or not), removing its files.
+#[delete("/api/admin/upload/{upload_id}")]
+pub async fn cancel_upload(
+    req: HttpRequest, state: Data<ServerState>, _: AuthMiddleware,
+) -> HttpResponse {
+    let upload_id = req.match_info().get("upload_id").unwrap_or("").to_string();
+
+    match block(move || cancel(&state, &upload_id)).await {
+        Ok(true) => HttpResponse::Ok().json(SuccessResponse::new(
+            true, String::from("Upload cancelled"),
+        )),
+
+        Ok(false) => HttpResponse::NotFound().json(SuccessResponse::new(
+            false, String::from("The upload doesn't exist"),
+        )),
+
+        Err(_) => HttpResponse::InternalServerError().json(SuccessResponse::new(
+            false, String::from("Some internal server error occurred."),
+        )),
+    }
+}
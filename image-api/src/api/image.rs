@@ -1,21 +1,54 @@
-use std::{ io::Write, fs::{ File, read } };
+use std::{ io::Write, fs::{ File, remove_file } };
 
 use actix_multipart::Multipart;
-use actix_web::{ get, post, web::{ block, Data }, HttpResponse, HttpRequest };
+use actix_web::{
+    get, post, web::{ block, Data }, HttpResponse, HttpRequest,
+    http::header::{ self, HeaderValue },
+};
 use futures::{StreamExt, TryStreamExt};
 use uuid::Uuid;
 use serde::Serialize;
+use qstring::QString;
 use log::{ debug, error };
 
 use crate::{
     api::service::path::{
         get_rendition_from_path_segments, split_path, rendition_cache_path,
-        generate_dest_rendition_path, cache_rendition_file,
+        generate_dest_rendition_path, cache_rendition_file, get_image_path,
+        generate_transform_path, transform_and_save_image,
+        get_image_from_path_segments,
+    },
+    api::service::{
+        stream::serve_file, publish::is_public, cache::ContentVersion,
+        signing::{ verify_url, signed_params, SignatureStatus },
+        negotiate::{ negotiate_encoding, is_negotiable, cache_variant },
+        placeholder::save_placeholder,
+        manifest::build_manifest,
+        content::original_path,
+        watermark::load_watermark,
+        sniff::{
+            accept_upload, upload_path, UploadError, UploadRejection, UPLOAD_DIR,
+        },
+        orientation::{ read_orientation, swaps_dimensions },
+        cleanup::add_pending_upload,
+        device::{ select_rendition_path, ACCEPT_CH, VARY },
+        density::{
+            requested_density, density_variant_path, scale_rendition,
+            CONTENT_DPR,
+        },
+    },
+    repository::Repository,
+    model::{
+        error::ErrorType, encoding::Encoding, transform::TransformParams,
+        image::{ Image, Placeholder }, watermark::Watermark, rendition::Rendition,
     },
-    repository::Repository, model::{ error::ErrorType, encoding::Encoding },
     server::db::DBError, auth::AuthMiddleware, server::config::ServerConfig,
+    storage::Storages, server_state::ServerState,
 };
 
+/// `Cache-Control` header sent with the responses for signed URLs.
+const SIGNED_CACHE_CONTROL: &str = "private, no-cache";
+
 #[derive(Serialize)]
 pub struct ImageJson {
     slug: String,
@@ -28,6 +61,48 @@ pub struct ImageUploadResponse {
     success: bool,
     upload_id: String,
     message: String,
+    /// Encoding detected from the file's content.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<Encoding>,
+    /// Dimensions the image is displayed with.
+    width: u32,
+    height: u32,
+    /// Size in bytes.
+    size: u64,
+    /// Why the upload was rejected.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<UploadError>,
+}
+
+impl ImageUploadResponse {
+    fn error(message: String) -> Self {
+        ImageUploadResponse {
+            success: false,
+            upload_id: String::from(""),
+            message,
+            encoding: None,
+            width: 0,
+            height: 0,
+            size: 0,
+            error: None,
+        }
+    }
+
+    fn rejected(rejection: &UploadRejection) -> Self {
+        ImageUploadResponse {
+            error: Some(rejection.to_error()),
+            ..Self::error(rejection.to_string())
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImagePlaceholderResponse {
+    width: u16,
+    height: u16,
+    #[serde(flatten)]
+    placeholder: Placeholder,
 }
 
 fn not_found_response() -> HttpResponse {
@@ -42,9 +117,23 @@ fn error_response(msg: &str) -> HttpResponse {
     HttpResponse::BadRequest().body(String::from(msg))
 }
 
+fn forbidden_response(msg: &str) -> HttpResponse {
+    HttpResponse::Forbidden().body(String::from(msg))
+}
+
+/// Receives an image upload, the `payload` field of the multipart body.
+///
+/// ## URL parameters:
+/// - `project` - Slug of the project the image is uploaded to, to apply its
+///   upload limits (`add_image` applies them in any case).
 #[post("/api/image")]
-pub async fn upload(mut payload: Multipart, _: AuthMiddleware)
-    -> HttpResponse {
+pub async fn upload(
+    mut payload: Multipart, req: HttpRequest, _: AuthMiddleware,
+    config: Data<ServerConfig>, state: Data<ServerState>,
+) -> HttpResponse {
+    let qs = QString::from(req.query_string());
+    let limits = config.get_upload_limits(qs.get("project"));
+
     // iterate over multipart stream
     while let Ok(Some(mut field)) = payload.try_next().await {
         let cd = field.content_disposition();
@@ -60,52 +149,200 @@ pub async fn upload(mut payload: Multipart, _: AuthMiddleware)
 
         if cd_name.eq("payload") {
             let uuid: String = Uuid::new_v4().to_string();
-            // "image-uploads/{}.jpg" for final path
-            let fname: String = format!("temp/{}.jpg", uuid);
+            // Kept aside until its type is known from the content
+            let fname: String = format!("{}/{}.part", UPLOAD_DIR, uuid);
+            let part_path = fname.clone();
 
             let mut file = block(move || File::create(fname)).await
                 .unwrap()
                 .expect("error");
 
+            let mut received: u64 = 0;
+
             while let Some(chunk) = field.next().await {
                 let data = chunk.unwrap();
+
+                received += data.len() as u64;
+
+                // Oversized uploads are rejected without receiving the rest.
+                if received > limits.max_size {
+                    let _ = remove_file(&part_path);
+                    let rejection = UploadRejection::TooLarge(limits.max_size);
+
+                    return HttpResponse::build(rejection.status_code())
+                        .json(ImageUploadResponse::rejected(&rejection));
+                }
+
                 file = block(move || file.write_all(&data).map(|_| file)).await
                     .unwrap()
                     .expect("error");
             }
 
-            return HttpResponse::Ok().json(ImageUploadResponse {
-                success: true,
-                upload_id: uuid,
-                message: String::from(""),
-            });
+            let upload_id = uuid.clone();
+
+            return match block(
+                move || accept_upload(&part_path, &upload_id, &limits)
+            ).await {
+                Ok(Ok(uploaded)) => {
+                    add_pending_upload(&state, &uuid, uploaded.size);
+
+                    let path = upload_path(&uuid, uploaded.encoding);
+
+                    let (width, height) = if swaps_dimensions(read_orientation(&path)) {
+                        (uploaded.height, uploaded.width)
+                    } else {
+                        (uploaded.width, uploaded.height)
+                    };
+
+                    HttpResponse::Ok().json(ImageUploadResponse {
+                        success: true,
+                        upload_id: uuid,
+                        message: String::from(""),
+                        encoding: Some(uploaded.encoding),
+                        width,
+                        height,
+                        size: uploaded.size,
+                        error: None,
+                    })
+                }
+
+                Ok(Err(rejection)) => {
+                    HttpResponse::build(rejection.status_code())
+                        .json(ImageUploadResponse::rejected(&rejection))
+                }
+
+                Err(_) => HttpResponse::InternalServerError().json(
+                    ImageUploadResponse::error(String::from(
+                        "Some error occured while uploading..."
+                    ))
+                ),
+            };
         }
     }
 
-    HttpResponse::InternalServerError().json(ImageUploadResponse {
-        success: false,
-        upload_id: String::from(""),
-        message: String::from("Some error occured while uploading..."),
-    })
+    HttpResponse::InternalServerError().json(ImageUploadResponse::error(
+        String::from("Some error occured while uploading...")
+    ))
 }
 
-#[get("/api/image/{path:[/\\.\\-+a-zA-Z0-9\\(\\)]+(\\.\\w{2,5})?$}")]
+#[get("/api/image/{path:[/\\.\\-+@a-zA-Z0-9\\(\\)]+(\\.\\w{2,5})?$}")]
 pub async fn download(
     repo: Data<dyn Repository + Sync + Send>, req: HttpRequest,
-    config: Data<ServerConfig>,
+    config: Data<ServerConfig>, storage: Data<Storages>,
 ) -> HttpResponse {
     if let Some(path) = req.match_info().get("path") {
         debug!("Requested Path: \"{}\"", path);
 
+        let params: Option<TransformParams>;
+
+        match TransformParams::from_query(req.query_string()) {
+            Ok(p) => { params = p; }
+            Err(msg) => { return error_response(msg.as_str()); }
+        }
+
+        // Signed URLs are verified before any cache lookup.
+        let path_segments = split_path(path);
+        let signed_query = signed_params(params.as_ref(), req.query_string());
+
+        let signed: bool = match verify_url(
+            &config.url_signing_secret,
+            &path_segments.join("/"),
+            signed_query.as_deref(),
+            req.query_string(),
+        ) {
+            SignatureStatus::Valid => true,
+            SignatureStatus::Absent => false,
+            SignatureStatus::Invalid => {
+                return forbidden_response("Invalid signature");
+            }
+            SignatureStatus::Expired => {
+                return forbidden_response("URL has expired");
+            }
+        };
+
+        // Unsigned URLs can only access published renditions.
+        if !signed {
+            match is_public(&repo, &path_segments) {
+                Ok(true) => {}
+                Ok(false) => { return not_found_response(); }
+                Err(e) => {
+                    match e.error_type {
+                        ErrorType::NotFound => { return not_found_response(); }
+                        ErrorType::InternalError => { return error_response(""); }
+                    }
+                }
+            }
+        }
+
+        let cache_control = if signed {
+            String::from(SIGNED_CACHE_CONTROL)
+        } else {
+            config.get_cache_control(path_segments[0])
+        };
+
+        if let Some(p) = params {
+            return download_transformed(
+                &repo, &req, &config, &storage, path, p, signed, &cache_control
+            ).await;
+        }
+
+        // The image's default rendition is replaced with the rendition that
+        // fits the requesting device.
+        let device_path: Option<String> =
+            if config.get_project_config(path_segments[0]).device_selection {
+                select_rendition_path(&repo, &req, &path_segments)
+            } else {
+                None
+            };
+
+        let path: &str = device_path.as_deref().unwrap_or(path);
+        let path_segments = split_path(path);
+
+        // Density variants, `hero@2x.jpg` or `hero.jpg?dpr=2`, are cached by
+        // their effective density.
+        let density: Option<(String, f32)>;
+
+        match requested_density(&req, &path_segments) {
+            Ok(Some(d)) => {
+                match density_variant_path(&repo, &path_segments, d) {
+                    Ok(variant) => { density = Some(variant); }
+                    Err(e) => {
+                        match e.error_type {
+                            ErrorType::NotFound => { return not_found_response(); }
+                            ErrorType::InternalError => { return error_response(""); }
+                        }
+                    }
+                }
+            }
+
+            Ok(None) => { density = None; }
+            Err(msg) => { return error_response(msg.as_str()); }
+        }
+
+        let path: &str = density.as_ref().map(|(p, _)| p.as_str()).unwrap_or(path);
+        let path_segments = split_path(path);
+
         let mut dest_file_path: String = generate_dest_rendition_path(
             &config.rendition_cache_dir, path
         );
 
         debug!("dest_file_path: {}", dest_file_path);
 
-        let mime_type: String;
+        let encoding: Encoding;
+        let version: Option<ContentVersion>;
+
+        // Local copies are revalidated once they're older than
+        // `storage.revalidateSeconds`, another node may have replaced or
+        // deleted the rendition.
+        let cache_lookup_path = dest_file_path.clone();
+        let cache_storage = storage.clone();
 
-        match rendition_cache_path(&dest_file_path) {
+        let cached_path = block(move || {
+            rendition_cache_path(&cache_lookup_path)
+                .filter(|p| cache_storage.fetch_rendition(p))
+        }).await.unwrap_or(None);
+
+        match cached_path {
             Some(p) => {
                 debug!("--> Found in rendition cache!");
 
@@ -114,24 +351,25 @@ pub async fn download(
                     dest_file_path = p;
                 }
 
-                mime_type = Encoding::from(dest_file_path.as_str())
-                    .mime_type();
+                encoding = Encoding::from(dest_file_path.as_str());
+                version = find_rendition_version(&repo, &path_segments);
             }
 
             None => {
-                let path_segments = split_path(path);
-
                 match get_rendition_from_path_segments(&repo, &path_segments) {
-                    Ok(rendition) => {
+                    Ok(mut rendition) => {
                         match repo.get_image_repo() {
                             Ok(mut img_repo) => {
                                 match img_repo.get(rendition.image_id) {
                                     Ok(image_data) => {
-                                        let source_file_path = format!(
-                                            "{}/{}{}",
-                                            config.upload_dir,
-                                            image_data.id,
-                                            image_data.encoding.extension()
+                                        if let Some((_, d)) = density {
+                                            scale_rendition(
+                                                &mut rendition, &image_data, d
+                                            );
+                                        }
+
+                                        let source_file_path = original_path(
+                                            &config.upload_dir, &image_data
                                         );
 
                                         debug!(
@@ -160,16 +398,46 @@ pub async fn download(
                                             dest_file_path.push_str(ext_str);
                                         }
 
-                                        mime_type = rendition.encoding.mime_type();
+                                        encoding = rendition.encoding;
+                                        version = Some(rendition_version(
+                                            &rendition, &image_data
+                                        ));
+
+                                        // Another node may have generated
+                                        // the rendition already.
+                                        let dest = dest_file_path.clone();
+                                        let project_config = config
+                                            .get_project_config(path_segments[0]);
+                                        let storage = storage.clone();
+                                        let repo = repo.clone();
+                                        let upload_dir = config.upload_dir.clone();
+
+                                        match block(move || -> Result<(), ()> {
+                                            if storage.fetch_rendition(&dest) {
+                                                return Ok(());
+                                            }
+
+                                            storage.fetch_original(&source_file_path)
+                                                .map_err(|_| ())?;
+
+                                            let watermark = load_watermark(
+                                                &repo, &upload_dir, &storage,
+                                                rendition.watermark.as_ref(),
+                                                project_config.watermark.as_ref(),
+                                            )?;
+
+                                            cache_rendition_file(
+                                                &source_file_path, &dest,
+                                                &rendition, &image_data,
+                                                &project_config, watermark.as_ref(),
+                                            )?;
 
-                                        match cache_rendition_file(
-                                            &source_file_path,
-                                            &dest_file_path,
-                                            rendition.width,
-                                            rendition.height
-                                            ) {
-                                            Ok(_) => {}
-                                            Err(_) => { return error_response(""); }
+                                            storage.store_rendition(&dest);
+
+                                            Ok(())
+                                        }).await {
+                                            Ok(Ok(_)) => {}
+                                            _ => { return error_response(""); }
                                         }
                                     }
 
@@ -206,13 +474,451 @@ pub async fn download(
             }
         }
 
-        let image_file = block(move || read(dest_file_path)).await
-            .unwrap().expect("Error while downloading!");
+        let mut resp = serve_negotiated(
+            &req, &config, path_segments[0], dest_file_path, encoding,
+            &cache_control, version.as_ref(),
+        ).await;
 
-        return HttpResponse::Ok().content_type(mime_type).body(image_file);
+        if let Some((_, d)) = density {
+            if let Ok(value) = HeaderValue::from_str(&d.to_string()) {
+                resp.headers_mut().insert(
+                    header::HeaderName::from_static(CONTENT_DPR), value
+                );
+            }
+        }
+
+        if device_path.is_some() {
+            let vary = match resp.headers().get(header::VARY)
+                .and_then(|v| v.to_str().ok()) {
+                Some(v) => format!("{}, {}", v, VARY),
+                None => String::from(VARY),
+            };
+
+            if let Ok(value) = HeaderValue::from_str(&vary) {
+                resp.headers_mut().insert(header::VARY, value);
+            }
+
+            resp.headers_mut().insert(
+                header::HeaderName::from_static("accept-ch"),
+                HeaderValue::from_static(ACCEPT_CH),
+            );
+        }
+
+        return resp;
 
     }
 
     not_found_response()
 }
 
+/// Serves an image transformed according to the transformation parameters
+/// supplied in the query string (see `TransformParams`).
+///
+/// Transformed images are cached in the rendition cache directory, keyed by
+/// the normalized parameters.
+///
+/// `signed` tells whether the request's URL carries a valid signature.
+async fn download_transformed(
+    repo: &Data<dyn Repository + Sync + Send>, req: &HttpRequest,
+    config: &Data<ServerConfig>, storage: &Data<Storages>, path: &str,
+    params: TransformParams, signed: bool, cache_control: &str,
+) -> HttpResponse {
+    let path_segments = split_path(path);
+    let project_config = config.get_project_config(path_segments[0]);
+    let transform_config = project_config.transform;
+
+    if !transform_config.enabled {
+        return forbidden_response(
+            "Image transformations are disabled for this project"
+        );
+    }
+
+    if !signed {
+        if transform_config.require_signature {
+            return forbidden_response("Invalid or missing signature");
+        }
+
+        if !transform_config.allowed_sizes.is_empty()
+            && !transform_config.allowed_sizes.contains(&params.size()) {
+            return forbidden_response(
+                format!("Size {} is not allowed", params.size()).as_str()
+            );
+        }
+    }
+
+    let rendition;
+
+    match get_rendition_from_path_segments(repo, &path_segments) {
+        Ok(r) => { rendition = r; }
+        Err(e) => {
+            match e.error_type {
+                ErrorType::NotFound => { return not_found_response(); }
+                ErrorType::InternalError => { return error_response(""); }
+            }
+        }
+    }
+
+    let image;
+
+    match repo.get_image_repo() {
+        Ok(mut img_repo) => {
+            match img_repo.get(rendition.image_id) {
+                Ok(img) => { image = img; }
+                Err(DBError::NotFound) => { return not_found_response(); }
+                Err(_) => { return error_response(""); }
+            }
+        }
+
+        Err(e) => {
+            error!("Error while getting image repository: {}", e);
+            return error_response("");
+        }
+    }
+
+    let image_path: String;
+
+    match get_image_path(repo, &image) {
+        Ok(i_path) => { image_path = i_path; }
+        Err(_) => { return error_response(""); }
+    }
+
+    let explicit_encoding = params.encoding.is_some();
+    let encoding = params.encoding.unwrap_or(rendition.encoding);
+
+    // Transformations of watermarked renditions are watermarked as well.
+    let watermark = Watermark::resolve(
+        rendition.watermark.as_ref(), project_config.watermark.as_ref()
+    );
+
+    let dest_file_path = generate_transform_path(
+        &config.rendition_cache_dir, &image_path, &params, encoding,
+        watermark.as_ref().map(|_| rendition.slug.as_str()),
+    );
+
+    let source_file_path = original_path(&config.upload_dir, &image);
+    let dest = dest_file_path.clone();
+    let version = rendition_version(&rendition, &image);
+
+    let output = project_config.output.clone();
+    let (edits, focal_point) = (image.edits.clone(), image.focal_point);
+    let storage = storage.clone();
+    let repo = repo.clone();
+    let upload_dir = config.upload_dir.clone();
+
+    match block(move || {
+        if storage.fetch_rendition(&dest) {
+            return Ok(());
+        }
+
+        debug!("Transforming {} into {}", source_file_path, dest);
+
+        if storage.fetch_original(&source_file_path).is_err() {
+            return Err(());
+        }
+
+        let watermark = match &watermark {
+            Some(w) => load_watermark(&repo, &upload_dir, &storage, Some(w), None)?,
+            None => None,
+        };
+
+        transform_and_save_image(
+            &source_file_path, &dest, &params, encoding, &output,
+            watermark.as_ref(), &edits, focal_point,
+        )?;
+
+        storage.store_rendition(&dest);
+
+        Ok(())
+    }).await {
+        Ok(Ok(_)) => {}
+        _ => { return error_response(""); }
+    }
+
+    if explicit_encoding {
+        // An explicitly requested format is never negotiated.
+        return serve_file(
+            req, dest_file_path, encoding.mime_type(), cache_control, Some(&version)
+        ).await;
+    }
+
+    serve_negotiated(
+        req, config, path_segments[0], dest_file_path, encoding, cache_control,
+        Some(&version),
+    ).await
+}
+
+/// Returns the version of the files generated from a rendition, which
+/// change along with the rendition and its image (edits, focal point).
+fn rendition_version(rendition: &Rendition, image: &Image) -> ContentVersion {
+    ContentVersion {
+        key: format!("rendition-{}", rendition.id),
+        modified_on: rendition.modified_on.max(image.modified_on),
+    }
+}
+
+/// Returns the version of the rendition a path points to, `None` if it
+/// can't be read (the validators are then derived from the file).
+fn find_rendition_version(
+    repo: &Data<dyn Repository + Sync + Send>, path_segments: &Vec<&str>,
+) -> Option<ContentVersion> {
+    let rendition = get_rendition_from_path_segments(repo, path_segments).ok()?;
+    let image = repo.get_image_repo().ok()?.get(rendition.image_id).ok()?;
+
+    Some(rendition_version(&rendition, &image))
+}
+
+/// Serves a cached rendition file, replacing it with a WebP/AVIF variant when
+/// the client accepts one and the project has format negotiation enabled.
+///
+/// Negotiable responses carry the `Vary: Accept` header so that shared caches
+/// keep the variants apart.
+async fn serve_negotiated(
+    req: &HttpRequest, config: &Data<ServerConfig>, project_slug: &str,
+    file_path: String, encoding: Encoding, cache_control: &str,
+    version: Option<&ContentVersion>,
+) -> HttpResponse {
+    if !is_negotiable(encoding)
+        || !config.get_project_config(project_slug).negotiate_format {
+        return serve_file(
+            req, file_path, encoding.mime_type(), cache_control, version
+        ).await;
+    }
+
+    let mut served_path = file_path.clone();
+    let mut served_encoding = encoding;
+
+    if let Some(negotiated) = negotiate_encoding(req, encoding) {
+        debug!("--> Negotiated encoding: {}", negotiated.mime_type());
+
+        match block(move || cache_variant(&file_path, negotiated)).await {
+            Ok(Ok(variant_path)) => {
+                served_path = variant_path;
+                served_encoding = negotiated;
+            }
+
+            // Fall back to the original rendition.
+            _ => { error!("Error while creating negotiated variant"); }
+        }
+    }
+
+    let mut resp = serve_file(
+        req, served_path, served_encoding.mime_type(), cache_control, version
+    ).await;
+
+    resp.headers_mut().insert(header::VARY, HeaderValue::from_static("Accept"));
+
+    resp
+}
+
+/// Returns the dimensions and the placeholders (BlurHash, LQIP and dominant
+/// color) of the image a rendition path points to.
+///
+/// Follows the same access rules as `download`: unpublished renditions and
+/// images are hidden.
+#[get("/api/image-placeholder/{path:[/\\.\\-+@a-zA-Z0-9\\(\\)]+(\\.\\w{2,5})?$}")]
+pub async fn get_placeholder(
+    repo: Data<dyn Repository + Sync + Send>, req: HttpRequest,
+    config: Data<ServerConfig>, storage: Data<Storages>,
+) -> HttpResponse {
+    let path = req.match_info().get("path").unwrap_or("");
+
+    if path.is_empty() {
+        return not_found_response();
+    }
+
+    let path_segments = split_path(path);
+
+    match is_public(&repo, &path_segments) {
+        Ok(true) => {}
+        Ok(false) => { return not_found_response(); }
+        Err(e) => {
+            match e.error_type {
+                ErrorType::NotFound => { return not_found_response(); }
+                ErrorType::InternalError => { return error_response(""); }
+            }
+        }
+    }
+
+    let image;
+
+    match get_rendition_from_path_segments(&repo, &path_segments) {
+        Ok(rendition) => {
+            match repo.get_image_repo() {
+                Ok(mut img_repo) => {
+                    match img_repo.get(rendition.image_id) {
+                        Ok(img) => { image = img; }
+                        Err(DBError::NotFound) => { return not_found_response(); }
+                        Err(_) => { return error_response(""); }
+                    }
+                }
+
+                Err(e) => {
+                    error!("Error while getting image repository: {}", e);
+                    return error_response("");
+                }
+            }
+        }
+
+        Err(e) => {
+            match e.error_type {
+                ErrorType::NotFound => { return not_found_response(); }
+                ErrorType::InternalError => { return error_response(""); }
+            }
+        }
+    }
+
+    // Images added before the placeholders were introduced get them on their
+    // first request.
+    let placeholder = match image.placeholder {
+        Some(p) => p,
+        None => {
+            let source_file_path = original_path(&config.upload_dir, &image);
+            let (repo, storage) = (repo.clone(), storage.clone());
+            let (image_id, edits) = (image.id, image.edits.clone());
+
+            match block(move || {
+                storage.fetch_original(&source_file_path).ok()?;
+                save_placeholder(&repo, image_id, &source_file_path, &edits)
+            }).await {
+                Ok(Some(p)) => p,
+                _ => { return error_response(""); }
+            }
+        }
+    };
+
+    HttpResponse::Ok()
+        .insert_header((
+            header::CACHE_CONTROL,
+            config.get_cache_control(path_segments[0]),
+        ))
+        .json(ImagePlaceholderResponse {
+            width: image.width,
+            height: image.height,
+            placeholder,
+        })
+}
+
+/// Returns the published renditions of an image with their dimensions, mime
+/// types and URLs, along with ready-made `srcset` and `sizes` attributes
+/// grouped by target device.
+///
+/// The path is the path of the image (or of one of its renditions). URLs are
+/// absolute, based on the configured `publicUrl`.
+///
+/// ## URL Parameters
+/// - `picture` - (Optional) `true` includes a `<picture>` HTML snippet.
+#[get("/api/image-manifest/{path:[/\\.\\-+a-zA-Z0-9\\(\\)]+(\\.\\w{2,5})?$}")]
+pub async fn get_manifest(
+    repo: Data<dyn Repository + Sync + Send>, req: HttpRequest,
+    config: Data<ServerConfig>,
+) -> HttpResponse {
+    let path = req.match_info().get("path").unwrap_or("");
+
+    if path.is_empty() {
+        return not_found_response();
+    }
+
+    let path_segments = split_path(path);
+    let image;
+
+    match get_image_from_path_segments(&repo, &path_segments) {
+        Ok(img) => { image = img; }
+        Err(e) => {
+            match e.error_type {
+                ErrorType::NotFound => { return not_found_response(); }
+                ErrorType::InternalError => { return error_response(""); }
+            }
+        }
+    }
+
+    if !image.is_published {
+        return not_found_response();
+    }
+
+    match repo.get_project_repo() {
+        Ok(mut proj_repo) => {
+            match proj_repo.get(image.project_id) {
+                Ok(project) if project.restrict_users => {
+                    return not_found_response();
+                }
+                Ok(_) => {}
+                Err(_) => { return error_response(""); }
+            }
+        }
+
+        Err(e) => {
+            error!("Error while getting project repository: {}", e);
+            return error_response("");
+        }
+    }
+
+    let renditions;
+
+    match repo.get_rendition_repo() {
+        Ok(mut ren_repo) => {
+            match ren_repo.get_all_from_image(image.id) {
+                Ok(r) => { renditions = r; }
+                Err(DBError::NotFound) => { renditions = vec![]; }
+                Err(_) => { return error_response(""); }
+            }
+        }
+
+        Err(e) => {
+            error!("Error while getting rendition repository: {}", e);
+            return error_response("");
+        }
+    }
+
+    let image_path;
+
+    match get_image_path(&repo, &image) {
+        Ok(p) => { image_path = p; }
+        Err(_) => { return error_response(""); }
+    }
+
+    let image_url = format!(
+        "{}/api/image/{}", config.get_public_url(), image_path
+    );
+
+    let picture = QString::from(req.query_string()).get("picture")
+        .map(|p| p == "true")
+        .unwrap_or(false);
+
+    let project_config = config.get_project_config(path_segments[0]);
+
+    HttpResponse::Ok()
+        .insert_header((
+            header::CACHE_CONTROL,
+            config.get_cache_control(path_segments[0]),
+        ))
+        .json(build_manifest(
+            &image, &image_url, &renditions, &project_config.media_queries,
+            picture,
+        ))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{ App, http::StatusCode, test };
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn routes_placeholders_of_density_variants() {
+        let app = test::init_service(App::new().service(get_placeholder)).await;
+
+        // The app data is missing, matched requests fail before the lookup.
+        for (path, status) in [
+            ("project/folder/image/hero.jpg", StatusCode::INTERNAL_SERVER_ERROR),
+            ("project/folder/image/hero@2x.jpg", StatusCode::INTERNAL_SERVER_ERROR),
+            ("project/folder/image@1.5x", StatusCode::INTERNAL_SERVER_ERROR),
+            ("project/folder/image/hero$.jpg", StatusCode::NOT_FOUND),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(&format!("/api/image-placeholder/{}", path))
+                .to_request();
+
+            assert_eq!(test::call_service(&app, req).await.status(), status, "{}", path);
+        }
+    }
+}
@@ -1,4 +1,5 @@
 pub mod image;
+pub mod upload;
 pub mod admin;
 pub mod service;
 
@@ -0,0 +1,537 @@
+//! Archive reading service
+//!
+//! Reads the entries of ZIP, tar and gzipped tar archives, for the bulk
+//! imports. Only what the imports need is supported: stored and deflated
+//! ZIP entries (no ZIP64 or encryption), and regular files and directories
+//! of tar archives (with GNU and PAX long names).
+
+use std::{
+    fs::File,
+    io::{ self, BufReader, Read, Seek, SeekFrom },
+};
+
+use flate2::read::{ DeflateDecoder, GzDecoder };
+
+/// ZIP readers look for the end of the central directory in the last
+/// 64 KiB (plus the record itself) of a file.
+const ZIP_TRAILER_LEN: u64 = 65536 + 22;
+
+const TAR_BLOCK_LEN: u64 = 512;
+
+/// Largest GNU long name or PAX extended header read into memory.
+const MAX_TAR_HEADER_DATA_LEN: u64 = 64 * 1024;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+pub struct ArchiveEntry {
+    /// Path of the entry in the archive, as stored.
+    pub path: String,
+    pub is_dir: bool,
+    /// Uncompressed size in bytes.
+    pub size: u64,
+}
+
+/// Detects the format of an archive from its first bytes.
+pub fn detect_format(header: &[u8]) -> Option<ArchiveFormat> {
+    if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06") {
+        return Some(ArchiveFormat::Zip);
+    }
+
+    if header.starts_with(&[ 0x1F, 0x8B ]) {
+        return Some(ArchiveFormat::TarGz);
+    }
+
+    if header.len() >= 262 && &header[257..262] == b"ustar" {
+        return Some(ArchiveFormat::Tar);
+    }
+
+    None
+}
+
+/// Reads an archive, calling `visit` with each entry and a reader of its
+/// content, or the reason the content can't be read.
+///
+/// Returns an error if the archive can't be read at all.
+pub fn read_archive<F>(path: &str, mut visit: F) -> Result<(), String>
+    where F: FnMut(&ArchiveEntry, Result<&mut dyn Read, String>) {
+    let mut file = File::open(path)
+        .map_err(|e| format!("Error while opening the archive: {}", e))?;
+
+    let mut header: Vec<u8> = Vec::with_capacity(TAR_BLOCK_LEN as usize);
+    (&mut file).take(TAR_BLOCK_LEN).read_to_end(&mut header).map_err(io_error)?;
+    file.seek(SeekFrom::Start(0)).map_err(io_error)?;
+
+    match detect_format(&header) {
+        Some(ArchiveFormat::Zip) => read_zip(&mut file, &mut visit),
+        Some(ArchiveFormat::Tar) => read_tar(&mut BufReader::new(file), &mut visit),
+        Some(ArchiveFormat::TarGz) => {
+            read_tar(&mut GzDecoder::new(BufReader::new(file)), &mut visit)
+        }
+        None => Err(String::from("The file is not a ZIP or tar archive")),
+    }
+}
+
+fn io_error(e: io::Error) -> String {
+    format!("Error while reading the archive: {}", e)
+}
+
+fn read_u16(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([ data[pos], data[pos + 1] ])
+}
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([ data[pos], data[pos + 1], data[pos + 2], data[pos + 3] ])
+}
+
+/// A file entry of the ZIP central directory.
+struct ZipEntry {
+    entry: ArchiveEntry,
+    encrypted: bool,
+    method: u16,
+    compressed_size: u64,
+    local_header_offset: u64,
+}
+
+fn read_zip<F>(file: &mut File, visit: &mut F) -> Result<(), String>
+    where F: FnMut(&ArchiveEntry, Result<&mut dyn Read, String>) {
+    let size = file.metadata().map_err(io_error)?.len();
+    let mut trailer: Vec<u8> = Vec::new();
+
+    file.seek(SeekFrom::Start(size.saturating_sub(ZIP_TRAILER_LEN)))
+        .and_then(|_| file.read_to_end(&mut trailer))
+        .map_err(io_error)?;
+
+    let end = trailer.windows(4).rposition(|w| w == b"PK\x05\x06")
+        .filter(|pos| pos + 22 <= trailer.len())
+        .ok_or_else(|| String::from("The ZIP archive is damaged or truncated"))?;
+
+    let entry_count = read_u16(&trailer, end + 10);
+    let directory_size = read_u32(&trailer, end + 12);
+    let directory_offset = read_u32(&trailer, end + 16);
+
+    if entry_count == 0xFFFF || directory_offset == 0xFFFFFFFF {
+        return Err(String::from("ZIP64 archives are not supported"));
+    }
+
+    if directory_offset as u64 + directory_size as u64 > size {
+        return Err(String::from("The ZIP archive is damaged or truncated"));
+    }
+
+    let mut directory: Vec<u8> = Vec::with_capacity(directory_size as usize);
+
+    file.seek(SeekFrom::Start(directory_offset as u64))
+        .and_then(|_| (&mut *file).take(directory_size as u64).read_to_end(&mut directory))
+        .map_err(io_error)?;
+
+    let mut entries: Vec<ZipEntry> = Vec::with_capacity(entry_count as usize);
+    let mut pos = 0;
+
+    for _ in 0..entry_count {
+        if pos + 46 > directory.len() || &directory[pos..pos + 4] != b"PK\x01\x02" {
+            return Err(String::from("The ZIP archive is damaged or truncated"));
+        }
+
+        let name_len = read_u16(&directory, pos + 28) as usize;
+        let extra_len = read_u16(&directory, pos + 30) as usize;
+        let comment_len = read_u16(&directory, pos + 32) as usize;
+
+        if pos + 46 + name_len > directory.len() {
+            return Err(String::from("The ZIP archive is damaged or truncated"));
+        }
+
+        let path = String::from_utf8_lossy(&directory[pos + 46..pos + 46 + name_len])
+            .to_string();
+
+        entries.push(ZipEntry {
+            entry: ArchiveEntry {
+                is_dir: path.ends_with('/'),
+                path,
+                size: read_u32(&directory, pos + 24) as u64,
+            },
+            encrypted: read_u16(&directory, pos + 8) & 1 == 1,
+            method: read_u16(&directory, pos + 10),
+            compressed_size: read_u32(&directory, pos + 20) as u64,
+            local_header_offset: read_u32(&directory, pos + 42) as u64,
+        });
+
+        pos += 46 + name_len + extra_len + comment_len;
+    }
+
+    for zip_entry in entries.iter() {
+        let entry = &zip_entry.entry;
+
+        if entry.is_dir {
+            visit(entry, Ok(&mut io::empty()));
+            continue;
+        }
+
+        if zip_entry.encrypted {
+            visit(entry, Err(String::from("Encrypted entries are not supported")));
+            continue;
+        }
+
+        // The local header's name and extra field may differ from the
+        // central directory's.
+        let mut local_header = [0u8; 30];
+
+        file.seek(SeekFrom::Start(zip_entry.local_header_offset))
+            .and_then(|_| file.read_exact(&mut local_header))
+            .map_err(io_error)?;
+
+        if &local_header[0..4] != b"PK\x03\x04" {
+            visit(entry, Err(String::from("The entry is damaged")));
+            continue;
+        }
+
+        let data_offset = zip_entry.local_header_offset + 30
+            + read_u16(&local_header, 26) as u64 + read_u16(&local_header, 28) as u64;
+
+        file.seek(SeekFrom::Start(data_offset)).map_err(io_error)?;
+
+        let compressed = (&mut *file).take(zip_entry.compressed_size);
+
+        match zip_entry.method {
+            0 => visit(entry, Ok(&mut compressed.take(entry.size))),
+
+            // The declared size bounds the output, whatever the compressed
+            // data expands to.
+            8 => visit(entry, Ok(&mut DeflateDecoder::new(compressed).take(entry.size))),
+
+            method => visit(entry, Err(format!(
+                "Compression method {} is not supported", method
+            ))),
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the text of a NUL terminated header field.
+fn header_text(field: &[u8]) -> String {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+
+    String::from_utf8_lossy(&field[..end]).to_string()
+}
+
+fn parse_octal(field: &[u8]) -> Option<u64> {
+    let text = header_text(field);
+    let text = text.trim();
+
+    if text.is_empty() { return Some(0); }
+
+    u64::from_str_radix(text, 8).ok()
+}
+
+/// Skips `len` bytes of the reader.
+fn skip<R: Read>(reader: &mut R, len: u64) -> Result<(), String> {
+    io::copy(&mut reader.by_ref().take(len), &mut io::sink()).map(|_| ()).map_err(io_error)
+}
+
+fn read_tar<R: Read, F>(reader: &mut R, visit: &mut F) -> Result<(), String>
+    where F: FnMut(&ArchiveEntry, Result<&mut dyn Read, String>) {
+    // Name of the next entry, from a GNU long name or PAX header.
+    let mut long_name: Option<String> = None;
+
+    loop {
+        let mut header = [0u8; TAR_BLOCK_LEN as usize];
+
+        match reader.read_exact(&mut header) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => { break; }
+            Err(e) => { return Err(io_error(e)); }
+        }
+
+        // The archive ends with empty blocks.
+        if header.iter().all(|b| *b == 0) { break; }
+
+        let checksum = parse_octal(&header[148..156]);
+        let sum: u64 = header.iter().enumerate()
+            .map(|(i, b)| if (148..156).contains(&i) { b' ' as u64 } else { *b as u64 })
+            .sum();
+
+        if checksum != Some(sum) {
+            return Err(String::from("The tar archive is damaged"));
+        }
+
+        let size = parse_octal(&header[124..136])
+            .ok_or_else(|| String::from("The tar archive is damaged"))?;
+        let padding = (TAR_BLOCK_LEN - size % TAR_BLOCK_LEN) % TAR_BLOCK_LEN;
+
+        let path = match long_name.take() {
+            Some(name) => name,
+            None => {
+                let name = header_text(&header[0..100]);
+                let prefix = if &header[257..262] == b"ustar" {
+                    header_text(&header[345..500])
+                } else {
+                    String::new()
+                };
+
+                if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) }
+            }
+        };
+
+        match header[156] {
+            // GNU long name, or PAX extended header of the next entry.
+            b'L' | b'x' => {
+                if size > MAX_TAR_HEADER_DATA_LEN {
+                    return Err(format!(
+                        "Extended tar header too large ({} bytes)", size
+                    ));
+                }
+
+                let mut data: Vec<u8> = Vec::with_capacity(size as usize);
+                reader.by_ref().take(size).read_to_end(&mut data).map_err(io_error)?;
+
+                long_name = if header[156] == b'L' {
+                    Some(header_text(&data))
+                } else {
+                    pax_path(&data)
+                };
+            }
+
+            // PAX global header
+            b'g' => { skip(reader, size)?; }
+
+            b'0' | 0 | b'5' => {
+                let entry = ArchiveEntry {
+                    is_dir: header[156] == b'5' || path.ends_with('/'),
+                    path,
+                    size,
+                };
+
+                let mut content = reader.by_ref().take(size);
+                visit(&entry, Ok(&mut content));
+
+                // Whatever the visitor didn't read.
+                let remaining = content.limit();
+                skip(reader, remaining)?;
+            }
+
+            _ => {
+                let entry = ArchiveEntry { path, is_dir: false, size };
+                visit(&entry, Err(String::from("Links and special files are not supported")));
+
+                skip(reader, size)?;
+            }
+        }
+
+        skip(reader, padding)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the `path` record of a PAX extended header
+/// (`"{length} {key}={value}\n"` records).
+fn pax_path(data: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(data);
+
+    text.lines()
+        .filter_map(|record| record.split_once(' ').map(|(_, kv)| kv))
+        .filter_map(|kv| kv.split_once('='))
+        .find(|(key, _)| *key == "path")
+        .map(|(_, value)| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ fs, io::Write };
+
+    use flate2::{ write::DeflateEncoder, Compression };
+    use uuid::Uuid;
+
+    use super::*;
+
+    /// Entries as visited: the path, whether it's a directory, and the
+    /// content or the error.
+    type Visited = Vec<(String, bool, Result<Vec<u8>, String>)>;
+
+    fn collect(entry: &ArchiveEntry, content: Result<&mut dyn Read, String>,
+        visited: &mut Visited) {
+        let content = content.map(|reader| {
+            let mut data: Vec<u8> = Vec::new();
+            reader.read_to_end(&mut data).unwrap();
+            data
+        });
+
+        visited.push((entry.path.clone(), entry.is_dir, content));
+    }
+
+    fn tar_header(name: &str, typeflag: u8, size: usize) -> Vec<u8> {
+        let mut header = vec![0u8; TAR_BLOCK_LEN as usize];
+
+        header[0..name.len()].copy_from_slice(name.as_bytes());
+        header[124..135].copy_from_slice(format!("{:011o}", size).as_bytes());
+        header[156] = typeflag;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[148..156].copy_from_slice(b"        ");
+
+        let sum: u64 = header.iter().map(|b| *b as u64).sum();
+        header[148..155].copy_from_slice(format!("{:06o}\0", sum).as_bytes());
+
+        header
+    }
+
+    fn tar_entry(tar: &mut Vec<u8>, name: &str, typeflag: u8, data: &[u8]) {
+        tar.extend(tar_header(name, typeflag, data.len()));
+        tar.extend(data);
+        tar.resize(tar.len() + (512 - data.len() % 512) % 512, 0);
+    }
+
+    fn read(tar: &[u8]) -> Result<Visited, String> {
+        let mut visited: Visited = vec![];
+
+        read_tar(&mut &tar[..], &mut |entry, content| collect(entry, content, &mut visited))?;
+
+        Ok(visited)
+    }
+
+    #[test]
+    fn reads_tar_entries_with_long_names() {
+        let long_name = format!("{}/photo.jpg", "a".repeat(120));
+
+        let mut tar: Vec<u8> = vec![];
+        tar_entry(&mut tar, "photos/", b'5', b"");
+        tar_entry(&mut tar, "photos/one.jpg", b'0', b"one");
+        tar_entry(&mut tar, "././@LongLink", b'L', format!("{}\0", long_name).as_bytes());
+        tar_entry(&mut tar, "truncated", b'0', &[ 7; 600 ]);
+        tar_entry(&mut tar, "PaxHeaders/two", b'x', b"20 path=pax/two.png\n");
+        tar_entry(&mut tar, "two.png", b'0', b"two");
+        tar_entry(&mut tar, "link", b'2', b"");
+        tar.extend([ 0; 1024 ]);
+
+        let visited = read(&tar).unwrap();
+
+        assert_eq!(visited.len(), 5);
+        assert_eq!(visited[0], (String::from("photos/"), true, Ok(vec![])));
+        assert_eq!(visited[1], (String::from("photos/one.jpg"), false, Ok(b"one".to_vec())));
+        assert_eq!(visited[2], (long_name, false, Ok(vec![ 7; 600 ])));
+        assert_eq!(visited[3], (String::from("pax/two.png"), false, Ok(b"two".to_vec())));
+        assert!(visited[4].2.is_err());
+    }
+
+    #[test]
+    fn rejects_damaged_tar_archives() {
+        let mut tar: Vec<u8> = vec![];
+        tar_entry(&mut tar, "one.jpg", b'0', b"one");
+        tar[0] = b'x';
+
+        assert!(read(&tar).is_err());
+
+        let mut tar: Vec<u8> = vec![];
+        tar_entry(&mut tar, "././@LongLink", b'L', &[ b'a'; MAX_TAR_HEADER_DATA_LEN as usize + 1 ]);
+
+        assert!(read(&tar).is_err());
+    }
+
+    #[test]
+    fn reads_the_pax_path_record() {
+        assert_eq!(
+            pax_path(b"30 mtime=1700000000.123456789\n23 path=dir/a b=c.jpg\n"),
+            Some(String::from("dir/a b=c.jpg"))
+        );
+        assert_eq!(pax_path(b"30 mtime=1700000000.123456789\n"), None);
+        assert_eq!(pax_path(b""), None);
+    }
+
+    /// Builds a ZIP archive of `(name, method, data)` entries, deflating the
+    /// data of the method 8 entries.
+    fn zip(entries: &[(&str, u16, &[u8])]) -> Vec<u8> {
+        let mut zip: Vec<u8> = vec![];
+        let mut directory: Vec<u8> = vec![];
+
+        for (name, method, data) in entries {
+            let stored = match method {
+                8 => {
+                    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                    encoder.write_all(data).unwrap();
+                    encoder.finish().unwrap()
+                }
+                _ => data.to_vec(),
+            };
+
+            let mut fields: Vec<u8> = vec![];
+            fields.extend(method.to_le_bytes());
+            fields.extend([ 0; 8 ]);
+            fields.extend((stored.len() as u32).to_le_bytes());
+            fields.extend((data.len() as u32).to_le_bytes());
+            fields.extend((name.len() as u16).to_le_bytes());
+            fields.extend([ 0; 2 ]);
+
+            directory.extend(b"PK\x01\x02\x14\0\x14\0\0\0");
+            directory.extend(&fields);
+            directory.extend([ 0; 10 ]);
+            directory.extend((zip.len() as u32).to_le_bytes());
+            directory.extend(name.as_bytes());
+
+            zip.extend(b"PK\x03\x04\x14\0\0\0");
+            zip.extend(&fields);
+            zip.extend(name.as_bytes());
+            zip.extend(&stored);
+        }
+
+        let directory_offset = zip.len() as u32;
+
+        zip.extend(&directory);
+        zip.extend(b"PK\x05\x06\0\0\0\0");
+        zip.extend((entries.len() as u16).to_le_bytes());
+        zip.extend((entries.len() as u16).to_le_bytes());
+        zip.extend((directory.len() as u32).to_le_bytes());
+        zip.extend(directory_offset.to_le_bytes());
+        zip.extend([ 0; 2 ]);
+
+        zip
+    }
+
+    fn read_zip_file(data: &[u8]) -> Result<Visited, String> {
+        let path = std::env::temp_dir().join(format!("archive-test-{}.zip", Uuid::new_v4()));
+        fs::write(&path, data).unwrap();
+
+        let mut visited: Visited = vec![];
+        let result = read_zip(
+            &mut File::open(&path).unwrap(),
+            &mut |entry, content| collect(entry, content, &mut visited),
+        );
+
+        fs::remove_file(&path).unwrap();
+
+        result.map(|_| visited)
+    }
+
+    #[test]
+    fn reads_stored_and_deflated_zip_entries() {
+        let text = "photo ".repeat(100);
+
+        let visited = read_zip_file(&zip(&[
+            ("photos/", 0, b""),
+            ("photos/one.jpg", 0, b"one"),
+            ("photos/two.txt", 8, text.as_bytes()),
+            ("three.bz2", 12, b"three"),
+        ])).unwrap();
+
+        assert_eq!(visited.len(), 4);
+        assert_eq!(visited[0], (String::from("photos/"), true, Ok(vec![])));
+        assert_eq!(visited[1], (String::from("photos/one.jpg"), false, Ok(b"one".to_vec())));
+        assert_eq!(visited[2], (String::from("photos/two.txt"), false, Ok(text.into_bytes())));
+        assert!(visited[3].2.is_err());
+    }
+
+    #[test]
+    fn rejects_damaged_zip_archives() {
+        let mut data = zip(&[ ("one.jpg", 0, b"one") ]);
+
+        assert!(read_zip_file(&data[..data.len() - 10]).is_err());
+
+        // Central directory past the end of the file
+        let len = data.len();
+        data[len - 6..len - 2].copy_from_slice(&(len as u32).to_le_bytes());
+
+        assert!(read_zip_file(&data).is_err());
+    }
+}
@@ -0,0 +1,184 @@
+//! HTTP caching service
+//!
+//! Generates cache validators (`ETag` and `Last-Modified`) for files served
+//! from the rendition cache and evaluates conditional requests against them.
+
+use std::{ fs::metadata, path::Path, time::{ SystemTime, UNIX_EPOCH } };
+
+use actix_web::{
+    HttpRequest, HttpResponse, HttpResponseBuilder,
+    http::header::{
+        self, Header, EntityTag, HttpDate, ETag, LastModified, IfNoneMatch,
+        IfModifiedSince,
+    },
+};
+use chrono::{ DateTime, Utc };
+use sha2::{ Digest, Sha256 };
+use log::{ debug, error };
+
+/// The database records a served file is generated from. Every node
+/// generates its own copy of the file, the validators derived from the
+/// records are the same on all of them.
+pub struct ContentVersion {
+    /// Identifies the records, e.g. `rendition-12`.
+    pub key: String,
+    /// Last modification of the records.
+    pub modified_on: DateTime<Utc>,
+}
+
+/// Validators of a file that is about to be served.
+pub struct CacheValidators {
+    pub etag: EntityTag,
+    pub last_modified: Option<HttpDate>,
+}
+
+/// Returns a strong entity tag from the hash of `parts`.
+fn hashed_etag(parts: &[&str]) -> EntityTag {
+    let mut hasher = Sha256::new();
+
+    for part in parts {
+        hasher.update(part.as_bytes());
+        hasher.update(b"\n");
+    }
+
+    EntityTag::new_strong(hex::encode(&hasher.finalize()[..16]))
+}
+
+impl CacheValidators {
+    /// Generates the validators of the file at `path`, generated from
+    /// `version`. The file name tells the variants (densities and formats)
+    /// of the same records apart.
+    pub fn from_version(version: &ContentVersion, path: &str) -> Self {
+        let file_name = Path::new(path).file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        Self {
+            etag: hashed_etag(&[
+                &version.key, &file_name, &version.modified_on.timestamp().to_string(),
+            ]),
+            last_modified: Some(HttpDate::from(SystemTime::from(version.modified_on))),
+        }
+    }
+
+    /// Generates the validators for the file at `path`, for the files that
+    /// aren't generated from database records.
+    ///
+    /// The entity tag is derived from the file's path, size and modification
+    /// time, so it changes whenever the file is replaced.
+    ///
+    /// Returns `None` if the file metadata could not be read.
+    pub fn from_file(path: &str) -> Option<Self> {
+        match metadata(path) {
+            Ok(meta) => {
+                let modified: Option<SystemTime> = meta.modified().ok();
+                let modified_nanos = modified
+                    .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+                    .map(|since_epoch| since_epoch.as_nanos().to_string())
+                    .unwrap_or_default();
+
+                Some(Self {
+                    etag: hashed_etag(&[ path, &meta.len().to_string(), &modified_nanos ]),
+                    last_modified: modified.map(HttpDate::from),
+                })
+            }
+
+            Err(e) => {
+                error!("Error while reading metadata of {}: {}", path, e);
+
+                None
+            }
+        }
+    }
+
+    /// Returns `true` when the client's cached copy (as described by the
+    /// `If-None-Match` and `If-Modified-Since` headers) is still fresh.
+    ///
+    /// `If-Modified-Since` is ignored when `If-None-Match` is present.
+    pub fn is_not_modified(&self, req: &HttpRequest) -> bool {
+        if req.headers().contains_key(header::IF_NONE_MATCH) {
+            return match IfNoneMatch::parse(req) {
+                Ok(IfNoneMatch::Any) => true,
+                Ok(IfNoneMatch::Items(etags)) => etags.iter()
+                    .any(|etag| etag.weak_eq(&self.etag)),
+                Err(_) => false,
+            };
+        }
+
+        if let Some(last_modified) = self.last_modified {
+            if let Ok(IfModifiedSince(since)) = IfModifiedSince::parse(req) {
+                // HTTP dates have a resolution of one second.
+                let modified = SystemTime::from(last_modified);
+                let since = SystemTime::from(since);
+
+                debug!("If-Modified-Since check: {:?} <= {:?}", modified, since);
+
+                return modified <= since;
+            }
+        }
+
+        false
+    }
+
+    /// Adds the validators and the `Cache-Control` header to a response.
+    pub fn apply(
+        &self, builder: &mut HttpResponseBuilder, cache_control: &str
+    ) {
+        builder.insert_header(ETag(self.etag.clone()));
+
+        if let Some(last_modified) = self.last_modified {
+            builder.insert_header(LastModified(last_modified));
+        }
+
+        if !cache_control.is_empty() {
+            builder.insert_header((header::CACHE_CONTROL, cache_control));
+        }
+    }
+
+    /// Returns a `304 Not Modified` response carrying the validators.
+    pub fn not_modified(&self, cache_control: &str) -> HttpResponse {
+        let mut builder = HttpResponse::NotModified();
+
+        self.apply(&mut builder, cache_control);
+
+        builder.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{ Duration, TimeZone };
+
+    use super::*;
+
+    fn etag(version: &ContentVersion, path: &str) -> String {
+        CacheValidators::from_version(version, path).etag.tag().to_string()
+    }
+
+    #[test]
+    fn version_validators_depend_on_the_records_and_the_variant() {
+        let version = ContentVersion {
+            key: String::from("rendition-12"),
+            modified_on: Utc.with_ymd_and_hms(2024, 5, 1, 10, 0, 0).unwrap(),
+        };
+
+        // Each node has its own copy of the file.
+        assert_eq!(etag(&version, "/a/p/hero/thumb.jpg"), etag(&version, "/b/p/hero/thumb.jpg"));
+        assert_ne!(etag(&version, "/a/p/hero/thumb.jpg"), etag(&version, "/a/p/hero/thumb.webp"));
+
+        let modified = ContentVersion {
+            key: version.key.clone(),
+            modified_on: version.modified_on + Duration::seconds(1),
+        };
+
+        assert_ne!(etag(&version, "/a/p/hero/thumb.jpg"), etag(&modified, "/a/p/hero/thumb.jpg"));
+
+        let other = ContentVersion { key: String::from("rendition-13"), ..modified };
+
+        assert_ne!(etag(&other, "/a/p/hero/thumb.jpg"), etag(&modified, "/a/p/hero/thumb.jpg"));
+        assert!(
+            CacheValidators::from_version(&other, "thumb.jpg").last_modified
+                == Some(HttpDate::from(SystemTime::from(other.modified_on)))
+        );
+    }
+}
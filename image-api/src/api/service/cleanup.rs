@@ -0,0 +1,182 @@
+//! Temporary upload cleanup
+//!
+//! Accepted uploads wait in the `temp` directory until an image is created
+//! from them (see `add_image`). The uploads are tracked as pending in the
+//! `ServerState`, and removed by a background task when they are still
+//! pending after `UploadConfig::pending_ttl_hours`. The task also removes the
+//! expired resumable uploads and the leftovers of interrupted uploads.
+
+use std::{ fs::{ read_dir, remove_file }, path::Path, time::Duration };
+
+use actix_web::{ rt, web::{ block, Data } };
+use chrono::{ DateTime, Duration as ChronoDuration, Utc };
+use uuid::Uuid;
+use log::{ debug, info };
+
+use crate::{
+    api::service::{
+        sniff::{ find_upload, SUPPORTED_ENCODINGS, UPLOAD_DIR },
+        tus::{ get_upload, remove_upload },
+    },
+    model::encoding::Encoding,
+    server::config::ServerConfig,
+    server_state::{ ServerState, PendingUpload },
+};
+
+/// Registers an accepted upload as pending.
+pub fn add_pending_upload(state: &ServerState, upload_id: &str, size: u64) {
+    state.add_pending_upload(upload_id, PendingUpload {
+        uploaded_on: Utc::now(),
+        size,
+    });
+}
+
+/// Marks an upload as claimed by an image and removes what's left of it
+/// (e.g. the state of a resumable upload).
+pub fn claim_upload(state: &ServerState, upload_id: &str) {
+    state.remove_pending_upload(upload_id);
+    remove_upload(upload_id);
+}
+
+/// Removes an upload, whether it's complete or not.
+///
+/// Returns `false` if the upload doesn't exist.
+pub fn cancel_upload(state: &ServerState, upload_id: &str) -> bool {
+    let exists = state.remove_pending_upload(upload_id).is_some()
+        || find_upload(upload_id).is_some()
+        || get_upload(upload_id).is_some();
+
+    if exists {
+        remove_upload(upload_id);
+        info!("Cancelled upload {}", upload_id);
+    }
+
+    exists
+}
+
+/// Registers the accepted uploads left in the `temp` directory (e.g. by a
+/// previous run) as pending since they were last modified.
+pub fn scan_pending_uploads(state: &ServerState) {
+    let entries = match read_dir(UPLOAD_DIR) {
+        Ok(e) => e,
+        Err(_) => { return; }
+    };
+
+    let mut count = 0;
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        let upload_id = match accepted_upload_id(&path) {
+            Some(id) => id,
+            None => { continue; }
+        };
+
+        if let Ok(file_metadata) = entry.metadata() {
+            let uploaded_on: DateTime<Utc> = file_metadata.modified()
+                .map(DateTime::from)
+                .unwrap_or_else(|_| Utc::now());
+
+            state.add_pending_upload(&upload_id, PendingUpload {
+                uploaded_on,
+                size: file_metadata.len(),
+            });
+
+            count += 1;
+        }
+    }
+
+    if count > 0 {
+        info!("Found {} pending upload(s)", count);
+    }
+}
+
+/// Returns the upload ID if the path is an accepted upload
+/// (`{upload_id}{ext}`).
+fn accepted_upload_id(path: &Path) -> Option<String> {
+    let file_name = path.file_name()?.to_str()?;
+    let upload_id = path.file_stem()?.to_str()?;
+
+    if Uuid::parse_str(upload_id).is_err()
+        || !SUPPORTED_ENCODINGS.contains(&Encoding::from(file_name)) {
+        return None;
+    }
+
+    Some(upload_id.to_string())
+}
+
+/// Removes the uploads pending for longer than the TTL, the expired
+/// resumable uploads that weren't accepted, and the files of the interrupted multipart
+/// uploads.
+pub fn remove_expired_uploads(state: &ServerState, conf: &ServerConfig) {
+    let cutoff = Utc::now()
+        - ChronoDuration::hours(conf.upload.pending_ttl_hours as i64);
+
+    for upload_id in state.take_expired_uploads(cutoff) {
+        debug!("Removing unclaimed upload {}", upload_id);
+        remove_upload(&upload_id);
+    }
+
+    let entries = match read_dir(UPLOAD_DIR) {
+        Ok(e) => e,
+        Err(_) => { return; }
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        if path.extension().and_then(|e| e.to_str()) != Some("part") {
+            continue;
+        }
+
+        let upload_id = match path.file_stem().and_then(|s| s.to_str()) {
+            Some(id) => id.to_string(),
+            None => { continue; }
+        };
+
+        match get_upload(&upload_id) {
+            Some(info) => {
+                // Including the complete ones that failed to be accepted.
+                if info.is_expired() && !info.is_accepted() {
+                    debug!("Removing expired resumable upload {}", upload_id);
+                    remove_upload(&upload_id);
+                }
+            }
+
+            // Multipart uploads are only kept as `.part` while receiving.
+            None => {
+                let modified: Option<DateTime<Utc>> = entry.metadata()
+                    .and_then(|m| m.modified())
+                    .map(DateTime::from)
+                    .ok();
+
+                if modified.map_or(false, |m| m < cutoff) {
+                    debug!("Removing interrupted upload {}", upload_id);
+                    let _ = remove_file(&path);
+                }
+            }
+        }
+    }
+}
+
+/// Starts the background task removing the expired uploads.
+pub fn start_cleanup(state: Data<ServerState>, conf: ServerConfig) {
+    let period = Duration::from_secs(
+        conf.upload.cleanup_interval_minutes.max(1) as u64 * 60
+    );
+
+    scan_pending_uploads(&state);
+
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(period);
+
+        loop {
+            interval.tick().await;
+
+            let (task_state, task_conf) = (state.clone(), conf.clone());
+
+            let _ = block(move || remove_expired_uploads(&task_state, &task_conf))
+                .await;
+        }
+    });
+}
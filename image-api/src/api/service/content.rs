@@ -0,0 +1,140 @@
+//! Content-addressed original files
+//!
+//! Original files are stored by the SHA-256 of their content
+//! (`{upload_dir}/ab/cd/abcd...{ext}`), so identical uploads share a single
+//! file. The images referencing a file are counted through
+//! `Image::content_hash`, the file is removed along with its last image.
+//!
+//! Adding an image and removing the last image of a file are done under the
+//! lock of the content hash (`ContentLock`), otherwise an upload could reuse
+//! a file that is being removed.
+
+use std::{ fs::{ File, create_dir_all, rename, remove_file }, io::Read, path::Path };
+
+use actix_web::web::Data;
+use log::{ debug, error };
+use sha2::{ Digest, Sha256 };
+
+use crate::{
+    model::{ image::Image, encoding::Encoding },
+    repository::{ Repository, image::ImageRepository },
+    storage::Storages,
+};
+
+/// Lock of a content hash, held until it's dropped.
+pub struct ContentLock {
+    img_repo: Box<dyn ImageRepository>,
+    content_hash: String,
+}
+
+impl Drop for ContentLock {
+    fn drop(&mut self) {
+        self.img_repo.unlock_content(&self.content_hash);
+    }
+}
+
+/// Takes the lock of a content hash, waiting for it if it's taken.
+pub fn lock_content(repo: &Data<dyn Repository + Sync + Send>, content_hash: &str)
+    -> Result<ContentLock, ()> {
+    let mut img_repo = repo.get_image_repo().map_err(|e| {
+        error!("Error while getting image repo: {}", e);
+    })?;
+
+    img_repo.lock_content(content_hash).map_err(|_| ())?;
+
+    Ok(ContentLock { img_repo, content_hash: content_hash.to_string() })
+}
+
+/// Returns the hex encoded SHA-256 of a file's content.
+pub fn hash_file(path: &str) -> Result<String, ()> {
+    let mut file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) => {
+            error!("Error while opening {}: {}", path, e);
+            return Err(());
+        }
+    };
+
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => { break; }
+            Ok(n) => { hasher.update(&buffer[..n]); }
+            Err(e) => {
+                error!("Error while reading {}: {}", path, e);
+                return Err(());
+            }
+        }
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Returns the path of the original file with the given content hash.
+pub fn content_path(upload_dir: &str, content_hash: &str, encoding: Encoding)
+    -> String {
+    format!(
+        "{}/{}/{}/{}{}",
+        upload_dir, &content_hash[..2], &content_hash[2..4], content_hash,
+        encoding.extension()
+    )
+}
+
+/// Returns the path of an image's original file.
+pub fn original_path(upload_dir: &str, image: &Image) -> String {
+    match &image.content_hash {
+        Some(content_hash) => content_path(upload_dir, content_hash, image.encoding),
+        None => format!("{}/{}{}", upload_dir, image.id, image.encoding.extension()),
+    }
+}
+
+/// Moves an uploaded file to its content-addressed path and stores it,
+/// unless a file with the same content is stored already. The lock of the
+/// content hash must be held, from before the image is added.
+///
+/// Returns the path of the original file.
+pub fn store_content(
+    storage: &Storages, upload_dir: &str, src_path: &str, content_hash: &str,
+    encoding: Encoding,
+) -> Result<String, ()> {
+    let dest_path = content_path(upload_dir, content_hash, encoding);
+
+    match storage.has_original(&dest_path) {
+        Ok(true) => {
+            debug!("Reusing stored original file: {}", dest_path);
+
+            let _ = remove_file(src_path);
+
+            return Ok(dest_path);
+        }
+
+        Ok(false) => {}
+
+        Err(e) => {
+            error!("Error while looking up original file {}: {}", dest_path, e);
+            return Err(());
+        }
+    }
+
+    if let Some(parent) = Path::new(&dest_path).parent() {
+        if let Err(e) = create_dir_all(parent) {
+            error!("Error while creating directory {}: {}", parent.display(), e);
+            return Err(());
+        }
+    }
+
+    if let Err(e) = rename(src_path, &dest_path) {
+        error!("Error while moving {} to {}: {}", src_path, dest_path, e);
+        return Err(());
+    }
+
+    match storage.store_original(&dest_path) {
+        Ok(_) => Ok(dest_path),
+        Err(e) => {
+            error!("Error while storing original file {}: {}", dest_path, e);
+            Err(())
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! Pixel density (DPR) variants of renditions
+//!
+//! `hero@2x.jpg` (or `hero.jpg?dpr=2`) is the `hero` rendition with its width
+//! and height multiplied by 2, for high density displays. The dimensions are
+//! capped at the dimensions of the original image, the density that was
+//! actually used is reported with the `Content-DPR` header.
+
+use actix_web::{ web::Data, HttpRequest };
+use lazy_static::lazy_static;
+use log::error;
+use qstring::QString;
+use regex::Regex;
+
+use crate::{
+    api::service::path::get_rendition_from_path_segments,
+    model::{
+        image::Image, rendition::Rendition, error::{ Error, ErrorType },
+    },
+    repository::Repository,
+};
+
+/// Response header reporting the effective density.
+pub const CONTENT_DPR: &str = "content-dpr";
+
+/// Highest density that can be requested.
+pub const MAX_DENSITY: f32 = 4.0;
+
+lazy_static! {
+    static ref DENSITY_RE: Regex = Regex::new(
+        r"^(?P<slug>[^@]+)@(?P<density>\d+(\.\d+)?)x(?P<ext>\.\w{2,5})?$"
+    ).unwrap();
+}
+
+/// Splits the density suffix off a path segment, e.g. `hero@2x.jpg` becomes
+/// `hero.jpg` and `2.0`.
+pub fn split_density(segment: &str) -> (String, Option<f32>) {
+    match DENSITY_RE.captures(segment) {
+        Some(caps) => {
+            let density = caps["density"].parse::<f32>().ok()
+                .filter(|d| is_valid_density(*d));
+
+            match density {
+                Some(d) => (
+                    format!(
+                        "{}{}",
+                        &caps["slug"],
+                        caps.name("ext").map(|e| e.as_str()).unwrap_or("")
+                    ),
+                    Some(d),
+                ),
+
+                None => (String::from(segment), None),
+            }
+        }
+
+        None => (String::from(segment), None),
+    }
+}
+
+pub fn is_valid_density(density: f32) -> bool {
+    density >= 1.0 && density <= MAX_DENSITY
+}
+
+/// Returns the requested density, from the path suffix or the `dpr` query
+/// parameter (the suffix takes precedence).
+pub fn requested_density(req: &HttpRequest, path_segments: &Vec<&str>)
+    -> Result<Option<f32>, String> {
+    let last = path_segments[path_segments.len() - 1];
+
+    if let (_, Some(density)) = split_density(last) {
+        return Ok(Some(density));
+    }
+
+    match QString::from(req.query_string()).get("dpr") {
+        Some(dpr) => match dpr.parse::<f32>() {
+            Ok(d) if is_valid_density(d) => Ok(Some(d)),
+            _ => Err(format!(
+                "Invalid dpr: \"{}\", must be between 1 and {}", dpr, MAX_DENSITY
+            )),
+        },
+
+        None => Ok(None),
+    }
+}
+
+/// Multiplies the rendition's dimensions by the density, capped so that the
+/// rendition does not get larger than the (cropped) original image.
+///
+/// Returns the effective density.
+pub fn scale_rendition(rendition: &mut Rendition, image: &Image, density: f32)
+    -> f32 {
+    let (src_width, src_height) = match rendition.crop {
+        Some(rect) => (rect.width, rect.height),
+        None => (image.width, image.height),
+    };
+
+    let mut effective = density;
+
+    if rendition.width > 0 {
+        effective = effective.min(src_width as f32 / rendition.width as f32);
+    }
+
+    if rendition.height > 0 {
+        effective = effective.min(src_height as f32 / rendition.height as f32);
+    }
+
+    // Two decimals are enough, and keep the cache file names short.
+    let effective = ((effective * 100.0).floor() / 100.0).max(1.0);
+
+    rendition.width = (rendition.width as f32 * effective).round() as u16;
+    rendition.height = (rendition.height as f32 * effective).round() as u16;
+
+    effective
+}
+
+/// Resolves the rendition path and returns the path of its variant for the
+/// effective density (e.g. `hero@3x.jpg` becomes `hero@2x.jpg` for a rendition
+/// that is half as large as the original), and the effective density.
+///
+/// Requests that end up with a density of 1 are served the rendition itself.
+pub fn density_variant_path<'a>(
+    repo: &Data<dyn Repository + Sync + Send>, path_segments: &'a Vec<&str>,
+    density: f32,
+) -> Result<(String, f32), Error<'a>> {
+    let mut rendition = get_rendition_from_path_segments(repo, path_segments)?;
+
+    let image = match repo.get_image_repo() {
+        Ok(mut img_repo) => match img_repo.get(rendition.image_id) {
+            Ok(img) => img,
+            Err(_) => {
+                return Err(Error::new(ErrorType::NotFound, "NOT FOUND"));
+            }
+        },
+
+        Err(e) => {
+            error!("Error while getting image repo: {}", e);
+
+            return Err(Error::new(
+                ErrorType::InternalError, "Some internal error occured."
+            ));
+        }
+    };
+
+    let effective = scale_rendition(&mut rendition, &image, density);
+
+    let last = path_segments.len() - 1;
+    let (segment, _) = split_density(path_segments[last]);
+
+    let segment = if effective > 1.0 {
+        match segment.find('.') {
+            Some(i) => format!("{}@{}x{}", &segment[..i], effective, &segment[i..]),
+            None => format!("{}@{}x", segment, effective),
+        }
+    } else {
+        segment
+    };
+
+    let mut segments: Vec<&str> = path_segments[..last].to_vec();
+    segments.push(&segment);
+
+    Ok((segments.join("/"), effective))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_the_density_suffix() {
+        assert_eq!(split_density("hero@2x.jpg"), (String::from("hero.jpg"), Some(2.0)));
+        assert_eq!(split_density("hero@1.5x"), (String::from("hero"), Some(1.5)));
+    }
+
+    #[test]
+    fn keeps_segments_without_a_valid_density() {
+        for segment in [ "hero.jpg", "hero@x.jpg", "hero@0.5x.jpg", "hero@5x.jpg", "@2x.jpg" ] {
+            assert_eq!(split_density(segment), (String::from(segment), None), "{}", segment);
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Device-aware rendition selection
+//!
+//! Requests for an image's `default` rendition are served with the rendition
+//! that fits the requesting device best. The device is described by the
+//! Client Hints (`Width`, `Sec-CH-Viewport-Width` and `Sec-CH-DPR`) or, when
+//! the client does not send them, by its class (`Sec-CH-UA-Mobile`, then the
+//! `User-Agent`).
+
+use actix_web::{ web::Data, HttpRequest };
+use log::{ debug, error };
+
+use crate::{
+    api::service::{
+        path::{ get_rendition_from_path_segments, get_image_path },
+        manifest::rendition_dimensions,
+    },
+    model::{ image::Image, rendition::Rendition },
+    repository::Repository,
+};
+
+/// Value of the `Accept-CH` header, the hints the selection uses.
+pub const ACCEPT_CH: &str =
+    "Sec-CH-Viewport-Width, Sec-CH-DPR, Width, Sec-CH-UA-Mobile";
+
+/// Request headers the selected rendition depends on.
+pub const VARY: &str =
+    "Sec-CH-Viewport-Width, Sec-CH-DPR, Width, Sec-CH-UA-Mobile, User-Agent";
+
+/// Slug of the rendition that is served for the image path.
+const DEFAULT_SLUG: &str = "default";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DeviceClass {
+    Mobile,
+    Tablet,
+    Desktop,
+}
+
+impl DeviceClass {
+    /// Classifies the device from the request's `Sec-CH-UA-Mobile` and
+    /// `User-Agent` headers.
+    pub fn from_request(req: &HttpRequest) -> Self {
+        if header_str(req, "Sec-CH-UA-Mobile") == Some("?1") {
+            return DeviceClass::Mobile;
+        }
+
+        DeviceClass::from_user_agent(header_str(req, "User-Agent").unwrap_or(""))
+    }
+
+    pub fn from_user_agent(user_agent: &str) -> Self {
+        if user_agent.contains("iPad") || user_agent.contains("Tablet")
+            || (user_agent.contains("Android") && !user_agent.contains("Mobile")) {
+            DeviceClass::Tablet
+        } else if user_agent.contains("Mobi") || user_agent.contains("iPhone")
+            || user_agent.contains("Android") {
+            DeviceClass::Mobile
+        } else {
+            DeviceClass::Desktop
+        }
+    }
+
+    /// Name matched against `Rendition::target_device`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DeviceClass::Mobile => "mobile",
+            DeviceClass::Tablet => "tablet",
+            DeviceClass::Desktop => "desktop",
+        }
+    }
+}
+
+/// Returns the width (in physical pixels) the image is displayed with,
+/// according to the Client Hints. `None` if the client did not send them.
+pub fn hinted_width(req: &HttpRequest) -> Option<u32> {
+    if let Some(width) = header_str(req, "Width")
+        .and_then(|w| w.trim().parse::<u32>().ok()) {
+        return Some(width);
+    }
+
+    let viewport_width = header_str(req, "Sec-CH-Viewport-Width")
+        .or_else(|| header_str(req, "Viewport-Width"))
+        .and_then(|w| w.trim().parse::<f32>().ok())?;
+
+    let dpr = header_str(req, "Sec-CH-DPR")
+        .or_else(|| header_str(req, "DPR"))
+        .and_then(|d| d.trim().parse::<f32>().ok())
+        .filter(|d| *d > 0.0)
+        .unwrap_or(1.0);
+
+    Some((viewport_width * dpr).ceil() as u32)
+}
+
+/// Selects the rendition that fits the requesting device best.
+///
+/// With Client Hints, the narrowest rendition that is at least as wide as the
+/// hinted width is selected (or the widest one). Otherwise the widest
+/// rendition targeting the device class is selected. Falls back to the
+/// `default` rendition.
+pub fn select_rendition<'r>(
+    req: &HttpRequest, image: &Image, default: &'r Rendition,
+    renditions: &'r [Rendition],
+) -> &'r Rendition {
+    let mut candidates: Vec<(u16, &Rendition)> = renditions.iter()
+        .filter(|r| r.is_published)
+        .map(|r| (rendition_dimensions(r, image).0, r))
+        .collect();
+
+    candidates.sort_by_key(|(width, _)| *width);
+
+    if let Some(width) = hinted_width(req) {
+        debug!("--> Selecting rendition for width: {}", width);
+
+        return candidates.iter()
+            .find(|(w, _)| *w as u32 >= width)
+            .or(candidates.last())
+            .map(|(_, r)| *r)
+            .unwrap_or(default);
+    }
+
+    let device = DeviceClass::from_request(req);
+
+    debug!("--> Selecting rendition for device: {:?}", device);
+
+    candidates.iter()
+        .rev()
+        .find(|(_, r)| r.target_device.eq_ignore_ascii_case(device.name()))
+        .map(|(_, r)| *r)
+        .unwrap_or(default)
+}
+
+/// Returns the path of the rendition to serve when the path resolves to an
+/// image's `default` rendition, `None` for any other path.
+pub fn select_rendition_path(
+    repo: &Data<dyn Repository + Sync + Send>, req: &HttpRequest,
+    path_segments: &Vec<&str>,
+) -> Option<String> {
+    let default = get_rendition_from_path_segments(repo, path_segments).ok()?;
+
+    if default.slug != DEFAULT_SLUG {
+        return None;
+    }
+
+    let image;
+    let renditions;
+
+    match (repo.get_image_repo(), repo.get_rendition_repo()) {
+        (Ok(mut img_repo), Ok(mut ren_repo)) => {
+            image = img_repo.get(default.image_id).ok()?;
+            renditions = ren_repo.get_all_from_image(default.image_id)
+                .unwrap_or_default();
+        }
+
+        _ => {
+            error!("Error while getting image or rendition repo");
+            return None;
+        }
+    }
+
+    let selected = select_rendition(req, &image, &default, &renditions);
+
+    match get_image_path(repo, &image) {
+        Ok(image_path) => Some(format!(
+            "{}/{}{}", image_path, selected.slug, selected.encoding.extension()
+        )),
+
+        Err(_) => None,
+    }
+}
+
+fn header_str<'r>(req: &'r HttpRequest, name: &str) -> Option<&'r str> {
+    req.headers().get(name).and_then(|v| v.to_str().ok())
+}
@@ -0,0 +1,128 @@
+//! Image edit service
+//!
+//! Applies an image's edit stack (see `EditOperation`) to the original image.
+//! The original file is never modified, the edits are applied whenever a
+//! rendition is generated.
+
+use log::{ debug, error };
+
+use crate::{
+    api::service::{
+        orientation::{ open_upright, apply_orientation, read_orientation,
+            swaps_dimensions },
+        path::crop_image,
+        sniff::image_dimensions,
+    },
+    model::edit::{ EditOperation, FlipDirection },
+};
+
+/// Opens an image file, turns it upright and applies the edits.
+pub fn open_edited(path: &str, edits: &[EditOperation])
+    -> Result<raster::Image, ()> {
+    let mut raster_img = open_upright(path)?;
+
+    apply_edits(&mut raster_img, edits)?;
+
+    Ok(raster_img)
+}
+
+/// Applies the edits, in order, to the image.
+pub fn apply_edits(raster_img: &mut raster::Image, edits: &[EditOperation])
+    -> Result<(), ()> {
+    for edit in edits {
+        let result = match edit {
+            // Rotations and flips are the same pixel remaps as the EXIF
+            // orientations.
+            EditOperation::Rotate { degrees } => {
+                match EditOperation::normalized_degrees(*degrees) {
+                    90 => apply_orientation(raster_img, 6),
+                    180 => apply_orientation(raster_img, 3),
+                    270 => apply_orientation(raster_img, 8),
+                    _ => {}
+                }
+
+                Ok(())
+            }
+
+            EditOperation::Flip { direction } => {
+                match direction {
+                    FlipDirection::Horizontal => apply_orientation(raster_img, 2),
+                    FlipDirection::Vertical => apply_orientation(raster_img, 4),
+                }
+
+                Ok(())
+            }
+
+            EditOperation::Crop(rect) => crop_image(raster_img, rect),
+
+            EditOperation::Brightness { factor } => {
+                raster::filter::brightness(raster_img, *factor).map_err(|_| ())
+            }
+
+            EditOperation::Contrast { factor } => {
+                contrast(raster_img, *factor);
+                Ok(())
+            }
+
+            EditOperation::Grayscale => {
+                raster::filter::grayscale(raster_img).map_err(|_| ())
+            }
+
+            EditOperation::Sharpen => {
+                raster::filter::sharpen(raster_img).map_err(|_| ())
+            }
+
+            EditOperation::Blur => {
+                raster::filter::blur(raster_img, raster::BlurMode::Gaussian)
+                    .map_err(|_| ())
+            }
+        };
+
+        if result.is_err() {
+            error!("Error while applying image edits.");
+            return Err(());
+        }
+    }
+
+    if !edits.is_empty() {
+        debug!("Applied {} edit(s)", edits.len());
+    }
+
+    Ok(())
+}
+
+/// Returns the dimensions of the upright (unedited) original image.
+pub fn original_dimensions(path: &str) -> Result<(u16, u16), ()> {
+    match image_dimensions(path) {
+        Some((width, height)) => {
+            let (width, height) = match (u16::try_from(width), u16::try_from(height)) {
+                (Ok(w), Ok(h)) => (w, h),
+                _ => {
+                    error!("Dimensions of {} too large: {}x{}", path, width, height);
+                    return Err(());
+                }
+            };
+
+            if swaps_dimensions(read_orientation(path)) {
+                Ok((height, width))
+            } else {
+                Ok((width, height))
+            }
+        }
+
+        None => {
+            error!("Error while reading dimensions of {}", path);
+            Err(())
+        }
+    }
+}
+
+/// Scales the distance of the colors from the mid-gray.
+fn contrast(raster_img: &mut raster::Image, factor: f32) {
+    for pixel in raster_img.bytes.chunks_exact_mut(4) {
+        for c in pixel.iter_mut().take(3) {
+            *c = ((*c as f32 - 128.0) * factor + 128.0).round().clamp(0.0, 255.0)
+                as u8;
+        }
+    }
+}
@@ -0,0 +1,220 @@
+//! Image encoding service
+//!
+//! Writes raster images to the disk with control over the encoding options
+//! (e.g. quality, progressive encoding and metadata) that `raster::save` does
+//! not expose.
+
+use std::fs::{ read, remove_file, rename, write };
+
+use image::{
+    ColorType, ImageEncoder,
+    codecs::{ png::PngEncoder, gif::GifEncoder, avif::AvifEncoder },
+    Frame, RgbaImage,
+};
+use img_parts::{ Bytes, DynImage, ImageEXIF, ImageICC };
+use log::{ debug, error, warn };
+use uuid::Uuid;
+
+use crate::{
+    api::service::orientation::reset_orientation,
+    model::{ encoding::Encoding, rendition::Rendition },
+    server::config::OutputConfig,
+};
+
+/// Quality used when none is specified.
+pub const DEFAULT_QUALITY: u8 = 85;
+
+/// AVIF encoder speed (1: slowest/best - 10: fastest).
+const AVIF_SPEED: u8 = 8;
+
+/// Options used while encoding an image.
+#[derive(Clone)]
+pub struct EncodeOptions {
+    pub encoding: Encoding,
+    pub quality: u8,
+
+    /// Progressive encoding, only supported by JPEG.
+    pub progressive: bool,
+
+    /// File that the EXIF data and ICC profile are copied from. Decoded
+    /// images carry no metadata, so it is stripped when this is `None`.
+    pub metadata_source: Option<String>,
+}
+
+impl EncodeOptions {
+    pub fn new(encoding: Encoding, quality: Option<u8>) -> Self {
+        Self {
+            encoding,
+            quality: quality.unwrap_or(DEFAULT_QUALITY),
+            progressive: false,
+            metadata_source: None,
+        }
+    }
+
+    /// Returns the options of a rendition made from the image at `src_path`.
+    /// Settings missing on the rendition are taken from the project's
+    /// `output` defaults.
+    pub fn for_rendition(
+        rendition: &Rendition, defaults: &OutputConfig, src_path: &str
+    ) -> Self {
+        let strip_metadata = rendition.strip_metadata
+            .unwrap_or(defaults.strip_metadata);
+
+        Self {
+            encoding: rendition.encoding,
+            quality: rendition.quality.unwrap_or(defaults.quality),
+            progressive: rendition.progressive.unwrap_or(defaults.progressive),
+            metadata_source: if strip_metadata {
+                None
+            } else {
+                Some(String::from(src_path))
+            },
+        }
+    }
+}
+
+/// Encodes a raster image and saves it to `dest_path`.
+pub fn save_image(
+    raster_img: &raster::Image, dest_path: &str, options: &EncodeOptions
+) -> Result<(), ()> {
+    encode_rgba(
+        raster_img.width as u32,
+        raster_img.height as u32,
+        &raster_img.bytes,
+        dest_path,
+        options,
+    )
+}
+
+/// Encodes RGBA pixels and saves them to `dest_path`.
+pub fn encode_rgba(
+    width: u32, height: u32, rgba: &[u8], dest_path: &str,
+    options: &EncodeOptions,
+) -> Result<(), ()> {
+    debug!(
+        "Encoding {}x{} image to {} (quality: {}, progressive: {})",
+        width, height, dest_path, options.quality, options.progressive
+    );
+
+    let mut buffer: Vec<u8> = vec![];
+
+    let result: Result<(), String> = match options.encoding {
+        Encoding::JPG => {
+            let mut encoder = jpeg_encoder::Encoder::new(
+                &mut buffer, options.quality
+            );
+
+            encoder.set_progressive(options.progressive);
+
+            // The alpha channel is ignored, JPEG does not support transparency.
+            encoder.encode(
+                rgba, width as u16, height as u16, jpeg_encoder::ColorType::Rgba
+            ).map_err(|e| e.to_string())
+        }
+
+        Encoding::PNG => {
+            PngEncoder::new(&mut buffer)
+                .write_image(rgba, width, height, ColorType::Rgba8)
+                .map_err(|e| e.to_string())
+        }
+
+        Encoding::GIF => {
+            match RgbaImage::from_raw(width, height, rgba.to_vec()) {
+                Some(image_buffer) => GifEncoder::new(&mut buffer)
+                    .encode_frame(Frame::new(image_buffer))
+                    .map_err(|e| e.to_string()),
+                None => Err(String::from("Invalid image buffer")),
+            }
+        }
+
+        Encoding::WEBP => {
+            let webp_data = webp::Encoder::from_rgba(rgba, width, height)
+                .encode(options.quality as f32);
+
+            buffer.extend_from_slice(&webp_data);
+
+            Ok(())
+        }
+
+        Encoding::AVIF => {
+            AvifEncoder::new_with_speed_quality(
+                &mut buffer, AVIF_SPEED, options.quality
+            )
+                .write_image(rgba, width, height, ColorType::Rgba8)
+                .map_err(|e| e.to_string())
+        }
+
+        _ => Err(format!(
+            "Encoding to {} is not supported",
+            options.encoding.mime_type()
+        )),
+    };
+
+    if let Err(e) = result {
+        error!("Error while encoding image {}: {}", dest_path, e);
+        return Err(());
+    }
+
+    if let Some(src_path) = &options.metadata_source {
+        buffer = copy_metadata(src_path, buffer);
+    }
+
+    // The cached files are served as soon as they exist, so they're written
+    // to a temporary file first (unique, as concurrent requests may encode
+    // the same file).
+    let tmp_path = format!("{}.{}.part", dest_path, Uuid::new_v4());
+
+    match write(&tmp_path, buffer).and_then(|_| rename(&tmp_path, dest_path)) {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            error!("Error while writing file {}: {}", dest_path, e);
+            let _ = remove_file(&tmp_path);
+            Err(())
+        }
+    }
+}
+
+/// Copies the EXIF data and the ICC profile of the file at `src_path` into
+/// the encoded image.
+///
+/// The images are turned upright while decoding, so the copied orientation is
+/// reset. Only JPEG, PNG and WebP can carry the metadata, the encoded image is
+/// returned unchanged for other formats or on errors.
+fn copy_metadata(src_path: &str, encoded: Vec<u8>) -> Vec<u8> {
+    let source = match read(src_path) {
+        Ok(data) => DynImage::from_bytes(Bytes::from(data)),
+        Err(e) => {
+            warn!("Unable to read metadata from {}: {}", src_path, e);
+            return encoded;
+        }
+    };
+
+    let source = match source {
+        Ok(Some(img)) => img,
+        _ => {
+            debug!("No readable metadata in {}", src_path);
+            return encoded;
+        }
+    };
+
+    let encoded = Bytes::from(encoded);
+
+    match DynImage::from_bytes(encoded.clone()) {
+        Ok(Some(mut dest)) => {
+            dest.set_exif(source.exif().map(|exif| {
+                let mut exif = exif.to_vec();
+                reset_orientation(&mut exif);
+
+                Bytes::from(exif)
+            }));
+            dest.set_icc_profile(source.icc_profile());
+
+            dest.encoder().bytes().to_vec()
+        }
+
+        _ => {
+            warn!("Metadata cannot be written to the encoded image");
+            encoded.to_vec()
+        }
+    }
+}
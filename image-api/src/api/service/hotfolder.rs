@@ -0,0 +1,191 @@
+//! Hot folders
+//!
+//! Watches the directories of `ServerConfig::hot_folders` and imports their
+//! new files (see `service::import`). Once handled, files are moved to the
+//! `processed` subdirectory when imported, or to `failed` otherwise, keeping
+//! their relative path.
+
+use std::{
+    fs::{ create_dir_all, rename, File },
+    path::{ Path, PathBuf },
+    time::{ Duration, SystemTime },
+};
+
+use actix_web::{ rt, web::{ block, Data } };
+use chrono::Utc;
+use log::{ error, info, warn };
+
+use crate::{
+    api::service::import::{ check_target, walk_directory, Importer, ImportStatus },
+    repository::Repository,
+    server::config::{ HotFolderConfig, ServerConfig },
+    server_state::ServerState,
+    storage::Storages,
+};
+
+pub const PROCESSED_DIR: &str = "processed";
+pub const FAILED_DIR: &str = "failed";
+
+/// Returns the IDs of the hot folder's project and folder.
+fn resolve_target(
+    repo: &Data<dyn Repository + Sync + Send>, hot_folder: &HotFolderConfig,
+) -> Result<(u32, u32), String> {
+    let project_id = match repo.get_project_repo() {
+        Ok(mut proj_repo) => {
+            match proj_repo.is_valid_slug(hot_folder.project.clone()) {
+                Ok(Some(id)) => id,
+                Ok(None) => {
+                    return Err(format!("Project '{}' doesn't exist", hot_folder.project));
+                }
+                Err(e) => { return Err(e.to_string()); }
+            }
+        }
+
+        Err(e) => { return Err(e.to_string()); }
+    };
+
+    check_target(repo, project_id, hot_folder.folder_id)?;
+
+    Ok((project_id, hot_folder.folder_id))
+}
+
+/// Moves a handled file to `{hot folder}/{subdirectory}/{relative path}`,
+/// adding a timestamp to its name if the destination exists.
+fn move_file(root: &Path, relative_path: &str, subdirectory: &str) {
+    let source = root.join(relative_path);
+    let mut dest = root.join(subdirectory).join(relative_path);
+
+    if dest.exists() {
+        let name = format!(
+            "{}-{}", Utc::now().format("%Y%m%d%H%M%S"),
+            dest.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default(),
+        );
+
+        dest.set_file_name(name);
+    }
+
+    let moved = match dest.parent() {
+        Some(parent) => create_dir_all(parent).and_then(|_| rename(&source, &dest)),
+        None => rename(&source, &dest),
+    };
+
+    if let Err(e) = moved {
+        error!(
+            "Error while moving {} to {}: {}", source.display(), dest.display(), e
+        );
+    }
+}
+
+/// Whether the file was left unmodified for the hot folder's settle time.
+fn is_settled(path: &Path, settle_time: Duration) -> bool {
+    match path.metadata().and_then(|m| m.modified()) {
+        Ok(modified) => SystemTime::now().duration_since(modified)
+            .map_or(false, |age| age >= settle_time),
+        Err(_) => false,
+    }
+}
+
+/// Imports the new files of a hot folder.
+pub fn scan_hot_folder(
+    repo: &Data<dyn Repository + Sync + Send>, conf: &ServerConfig,
+    storage: &Storages, state: &ServerState, hot_folder: &HotFolderConfig,
+) {
+    let root = PathBuf::from(&hot_folder.path);
+
+    let (project_id, folder_id) = match resolve_target(repo, hot_folder) {
+        Ok(target) => target,
+        Err(msg) => {
+            error!("Hot folder {} can't be imported: {}", hot_folder.path, msg);
+            return;
+        }
+    };
+
+    let paths = match walk_directory(&root) {
+        Ok(p) => p,
+        Err(msg) => {
+            error!("{}", msg);
+            return;
+        }
+    };
+
+    let settle_time = Duration::from_secs(hot_folder.settle_seconds as u64);
+    let mut importer = Importer::new(repo, conf, storage, state, project_id, folder_id);
+
+    for (path, is_dir) in paths {
+        let top = path.split('/').next().unwrap_or("");
+
+        if is_dir || top == PROCESSED_DIR || top == FAILED_DIR {
+            continue;
+        }
+
+        let file_path = root.join(&path);
+
+        // Still being copied
+        if !is_settled(&file_path, settle_time) { continue; }
+
+        let status = match File::open(&file_path) {
+            Ok(mut file) => importer.import_file(&path, &mut file),
+            Err(e) => {
+                error!("Error while opening {}: {}", file_path.display(), e);
+                continue;
+            }
+        };
+
+        let message = importer.entries.last().map(|e| e.message.clone())
+            .unwrap_or_default();
+
+        match status {
+            ImportStatus::Imported => {
+                info!("Hot folder {}: imported {}", hot_folder.path, path);
+                move_file(&root, &path, PROCESSED_DIR);
+            }
+
+            _ => {
+                warn!(
+                    "Hot folder {}: {} not imported: {}", hot_folder.path, path,
+                    message
+                );
+                move_file(&root, &path, FAILED_DIR);
+            }
+        }
+    }
+}
+
+/// Starts watching the configured hot folders.
+pub fn start_hot_folders(
+    repo: Data<dyn Repository + Sync + Send>, storage: Data<Storages>,
+    state: Data<ServerState>, conf: ServerConfig,
+) {
+    for hot_folder in conf.hot_folders.iter().cloned() {
+        if !Path::new(&hot_folder.path).is_dir() {
+            error!("Hot folder {} is not a directory", hot_folder.path);
+            continue;
+        }
+
+        info!(
+            "Watching hot folder {} (project '{}', folder {})",
+            hot_folder.path, hot_folder.project, hot_folder.folder_id
+        );
+
+        let period = Duration::from_secs(hot_folder.scan_interval_seconds.max(1) as u64);
+        let (repo, storage, state, conf) =
+            (repo.clone(), storage.clone(), state.clone(), conf.clone());
+
+        rt::spawn(async move {
+            let mut interval = rt::time::interval(period);
+
+            loop {
+                interval.tick().await;
+
+                let (repo, storage, state, conf, hot_folder) = (
+                    repo.clone(), storage.clone(), state.clone(), conf.clone(),
+                    hot_folder.clone(),
+                );
+
+                let _ = block(move || {
+                    scan_hot_folder(&repo, &conf, &storage, &state, &hot_folder)
+                }).await;
+            }
+        });
+    }
+}
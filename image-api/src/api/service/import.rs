@@ -0,0 +1,513 @@
+//! Bulk import service
+//!
+//! Imports a tree of files into a project folder: directories become
+//! folders (reusing the existing folders with the same slug) and files become
+//! images, going through the same validation and ingest path as the uploads.
+//! Every directory and file gets an entry in the import report.
+
+use std::{
+    collections::HashMap,
+    fs::{ File, read_dir, remove_file },
+    io::{ self, Read },
+    path::Path,
+};
+
+use actix_web::web::Data;
+use chrono::Utc;
+use serde::Serialize;
+use uuid::Uuid;
+use log::{ debug, error, info };
+
+use crate::{
+    api::service::{
+        archive::read_archive,
+        sniff::{ accept_upload, UploadRejection, UPLOAD_DIR },
+        ingest::{ ingest_upload, IngestError },
+        tus::remove_upload,
+    },
+    model::{ folder::Folder, upload_image::UploadImage },
+    repository::Repository,
+    server::config::{ ServerConfig, UploadLimits },
+    server_state::ServerState,
+    storage::Storages,
+};
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportStatus {
+    Imported,
+    /// Not imported, e.g. an image with the same slug exists.
+    Skipped,
+    Failed,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportEntry {
+    pub path: String,
+    pub status: ImportStatus,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub folder_id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_id: Option<u32>,
+}
+
+/// Returns a slug generated from a name, the same way the admin UI does:
+/// lowercase, without special characters, whitespace replaced by `-`.
+pub fn generate_slug(name: &str) -> String {
+    name.trim().to_lowercase().chars()
+        .filter(|c| {
+            c.is_ascii_lowercase() || c.is_ascii_digit() || c.is_whitespace()
+                || ".-_()".contains(*c)
+        })
+        .map(|c| if c.is_whitespace() { '-' } else { c })
+        .collect()
+}
+
+/// Splits a relative path into its components, `None` if it leaves the
+/// import's root (`..`).
+fn path_components(path: &str) -> Option<Vec<String>> {
+    let mut components: Vec<String> = vec![];
+
+    for component in path.split(|c| c == '/' || c == '\\') {
+        match component {
+            "" | "." => {}
+            ".." => { return None; }
+            c => { components.push(c.to_string()); }
+        }
+    }
+
+    Some(components)
+}
+
+/// Files left by archivers and file managers, they aren't imported.
+fn is_hidden(components: &[String]) -> bool {
+    components.iter().any(|c| c.starts_with('.') || c == "__MACOSX")
+        || components.last().map_or(false, |name| name == "Thumbs.db")
+}
+
+/// Checks that the project exists, and that the folder (unless `0`, the
+/// project's root) exists in it.
+pub fn check_target(
+    repo: &Data<dyn Repository + Sync + Send>, project_id: u32, folder_id: u32,
+) -> Result<(), String> {
+    match repo.get_project_repo() {
+        Ok(mut proj_repo) => {
+            if proj_repo.get(project_id).is_err() {
+                return Err(format!("Project {} doesn't exist", project_id));
+            }
+        }
+
+        Err(e) => {
+            error!("Error while getting project repo: {}", e);
+            return Err(String::from("Some internal error occured."));
+        }
+    }
+
+    if folder_id == 0 { return Ok(()); }
+
+    match repo.get_folder_repo() {
+        Ok(mut fol_repo) => {
+            match fol_repo.get(folder_id) {
+                Ok(folder) if folder.project_id as u32 == project_id => Ok(()),
+                _ => Err(format!(
+                    "Folder {} doesn't exist in project {}", folder_id, project_id
+                )),
+            }
+        }
+
+        Err(e) => {
+            error!("Error while getting folder repo: {}", e);
+            Err(String::from("Some internal error occured."))
+        }
+    }
+}
+
+/// Lists the directories and files under `root` (not following symbolic
+/// links), as paths relative to `root` with a flag telling the directories
+/// apart. Hidden entries are left out, parents are listed before their
+/// content.
+pub fn walk_directory(root: &Path) -> Result<Vec<(String, bool)>, String> {
+    let mut paths: Vec<(String, bool)> = vec![];
+    walk(root, "", &mut paths)?;
+
+    Ok(paths)
+}
+
+fn walk(dir: &Path, prefix: &str, paths: &mut Vec<(String, bool)>)
+    -> Result<(), String> {
+    let mut entries: Vec<_> = read_dir(dir)
+        .map_err(|e| format!("Error while reading {}: {}", dir.display(), e))?
+        .flatten()
+        .collect();
+
+    entries.sort_by_key(|e| e.file_name());
+
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        if is_hidden(&[ name.clone() ]) { continue; }
+
+        let path = if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) };
+
+        match entry.file_type() {
+            Ok(t) if t.is_dir() => {
+                paths.push((path.clone(), true));
+                walk(&entry.path(), &path, paths)?;
+            }
+
+            Ok(t) if t.is_file() => { paths.push((path, false)); }
+
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+pub struct Importer<'a> {
+    repo: &'a Data<dyn Repository + Sync + Send>,
+    conf: &'a ServerConfig,
+    storage: &'a Storages,
+    state: &'a ServerState,
+    project_id: u32,
+    /// The folder the files are imported into, `0` for the project's root.
+    folder_id: u32,
+    limits: UploadLimits,
+    /// IDs of the folders created or reused so far, keyed by their path.
+    folders: HashMap<String, u32>,
+    pub entries: Vec<ImportEntry>,
+}
+
+impl<'a> Importer<'a> {
+    pub fn new(
+        repo: &'a Data<dyn Repository + Sync + Send>, conf: &'a ServerConfig,
+        storage: &'a Storages, state: &'a ServerState, project_id: u32,
+        folder_id: u32,
+    ) -> Self {
+        let project_slug = match repo.get_project_repo() {
+            Ok(mut proj_repo) => proj_repo.get(project_id).ok().map(|p| p.slug),
+            Err(_) => None,
+        };
+
+        Importer {
+            repo, conf, storage, state, project_id, folder_id,
+            limits: conf.get_upload_limits(project_slug.as_deref()),
+            folders: HashMap::new(),
+            entries: vec![],
+        }
+    }
+
+    /// Returns the number of entries with the given status.
+    pub fn count(&self, status: ImportStatus) -> usize {
+        self.entries.iter().filter(|e| e.status == status).count()
+    }
+
+    fn report(
+        &mut self, path: &str, status: ImportStatus, message: String,
+        folder_id: Option<u32>, image_id: Option<u32>,
+    ) -> ImportStatus {
+        debug!("Import of {}: {}", path, message);
+
+        self.entries.push(ImportEntry {
+            path: path.to_string(), status, message, folder_id, image_id,
+        });
+
+        status
+    }
+
+    /// Imports the entries of a ZIP or tar archive.
+    pub fn import_archive(&mut self, archive_path: &str) -> Result<(), String> {
+        read_archive(archive_path, |entry, content| {
+            match content {
+                Ok(_) if entry.is_dir => { self.import_directory_path(&entry.path); }
+                Ok(reader) => { self.import_file(&entry.path, reader); }
+                Err(msg) => {
+                    self.report(&entry.path, ImportStatus::Failed, msg, None, None);
+                }
+            }
+        })?;
+
+        info!(
+            "Imported {} into project {}: {} imported, {} skipped, {} failed",
+            archive_path, self.project_id, self.count(ImportStatus::Imported),
+            self.count(ImportStatus::Skipped), self.count(ImportStatus::Failed),
+        );
+
+        Ok(())
+    }
+
+    /// Imports the directory tree under `root`.
+    pub fn import_directory(&mut self, root: &Path) -> Result<(), String> {
+        for (path, is_dir) in walk_directory(root)? {
+            if is_dir {
+                self.import_directory_path(&path);
+                continue;
+            }
+
+            match File::open(root.join(&path)) {
+                Ok(mut file) => { self.import_file(&path, &mut file); }
+                Err(e) => {
+                    self.report(&path, ImportStatus::Failed, e.to_string(), None, None);
+                }
+            }
+        }
+
+        info!(
+            "Imported {} into project {}: {} imported, {} skipped, {} failed",
+            root.display(), self.project_id, self.count(ImportStatus::Imported),
+            self.count(ImportStatus::Skipped), self.count(ImportStatus::Failed),
+        );
+
+        Ok(())
+    }
+
+    /// Creates (or reuses) the folders of a directory path.
+    pub fn import_directory_path(&mut self, path: &str) {
+        match path_components(path) {
+            Some(components) if is_hidden(&components) => {}
+            Some(components) => { let _ = self.get_folder(path, &components); }
+            None => {
+                self.report(
+                    path, ImportStatus::Failed,
+                    String::from("The path leaves the import's root"), None, None,
+                );
+            }
+        }
+    }
+
+    /// Returns the ID of the folder of the directory `components`, creating
+    /// the folders that don't exist. The folders are reported once, when
+    /// they are first needed.
+    fn get_folder(&mut self, path: &str, components: &[String]) -> Result<u32, ()> {
+        let mut parent_id = self.folder_id;
+
+        for depth in 1..=components.len() {
+            let folder_path = components[..depth].join("/");
+
+            if let Some(id) = self.folders.get(&folder_path) {
+                parent_id = *id;
+                continue;
+            }
+
+            let name = &components[depth - 1];
+            let slug = generate_slug(name);
+
+            if slug.is_empty() {
+                self.report(
+                    &folder_path, ImportStatus::Failed,
+                    format!("No slug can be generated from '{}'", name), None, None,
+                );
+                return Err(());
+            }
+
+            match self.create_folder(name, &slug, parent_id) {
+                Ok((id, created)) => {
+                    if created {
+                        self.report(
+                            &folder_path, ImportStatus::Imported,
+                            String::from("Folder created"), Some(id), None,
+                        );
+                    } else {
+                        self.report(
+                            &folder_path, ImportStatus::Skipped,
+                            format!(
+                                "A folder exists with slug '{}', its content is \
+                                imported into it", slug
+                            ),
+                            Some(id), None,
+                        );
+                    }
+
+                    self.folders.insert(folder_path, id);
+                    parent_id = id;
+                }
+
+                Err(msg) => {
+                    self.report(&folder_path, ImportStatus::Failed, msg, None, None);
+                    return Err(());
+                }
+            }
+        }
+
+        debug!("Folder of {}: {}", path, parent_id);
+
+        Ok(parent_id)
+    }
+
+    /// Returns the ID of the folder with the given slug, and whether it was
+    /// created.
+    fn create_folder(&mut self, title: &str, slug: &str, parent_id: u32)
+        -> Result<(u32, bool), String> {
+        let mut fol_repo = self.repo.get_folder_repo().map_err(|e| {
+            error!("Error while getting folder repo: {}", e);
+            String::from("Some internal error occured.")
+        })?;
+
+        let existing = fol_repo.is_valid_slug(
+            self.project_id, parent_id, slug.to_string()
+        ).map_err(|_| String::from("Some internal error occured."))?;
+
+        if let Some(id) = existing {
+            return Ok((id, false));
+        }
+
+        fol_repo.add(Folder {
+            id: 0,
+            title: title.to_string(),
+            slug: slug.to_string(),
+            project_id: self.project_id as u16,
+            description: String::new(),
+            parent_folder_id: parent_id,
+            created_by: 0,
+            modified_by: 0,
+            created_on: Utc::now(),
+            modified_on: Utc::now(),
+        })?;
+
+        // The folder repository doesn't return the ID of new folders.
+        match fol_repo.is_valid_slug(self.project_id, parent_id, slug.to_string()) {
+            Ok(Some(id)) => Ok((id, true)),
+            _ => Err(String::from("The folder was created, but can't be found")),
+        }
+    }
+
+    /// Imports a file as an image, into the folder of its directory.
+    /// Returns the status reported for the file.
+    pub fn import_file(&mut self, path: &str, content: &mut dyn Read) -> ImportStatus {
+        let components = match path_components(path) {
+            Some(c) if !c.is_empty() => c,
+            _ => {
+                return self.report(
+                    path, ImportStatus::Failed,
+                    String::from("The path leaves the import's root"), None, None,
+                );
+            }
+        };
+
+        if is_hidden(&components) {
+            return self.report(
+                path, ImportStatus::Skipped, String::from("Hidden file"), None, None,
+            );
+        }
+
+        let (dir, name) = components.split_at(components.len() - 1);
+        let name = &name[0];
+
+        let folder_id = match self.get_folder(path, dir) {
+            Ok(id) => id,
+            Err(_) => {
+                return self.report(
+                    path, ImportStatus::Failed,
+                    String::from("The folder couldn't be created"), None, None,
+                );
+            }
+        };
+
+        let title = match name.rsplit_once('.') {
+            Some((stem, _)) if !stem.is_empty() => stem.to_string(),
+            _ => name.clone(),
+        };
+
+        let slug = generate_slug(&title);
+
+        if slug.is_empty() {
+            return self.report(
+                path, ImportStatus::Failed,
+                format!("No slug can be generated from '{}'", title), None, None,
+            );
+        }
+
+        match self.repo.get_image_repo()
+            .map(|mut img_repo| {
+                img_repo.is_valid_slug(self.project_id, folder_id, slug.clone())
+            }) {
+            Ok(Ok(None)) => {}
+
+            Ok(Ok(Some(_))) => {
+                return self.report(
+                    path, ImportStatus::Skipped,
+                    format!("An image exists with slug '{}'", slug), None, None,
+                );
+            }
+
+            _ => {
+                return self.report(
+                    path, ImportStatus::Failed,
+                    String::from("Some internal error occured."), None, None,
+                );
+            }
+        }
+
+        let upload_id = Uuid::new_v4().to_string();
+
+        if let Err(rejection) = self.receive(&upload_id, content) {
+            return self.report(path, ImportStatus::Failed, rejection.to_string(), None, None);
+        }
+
+        let details = UploadImage {
+            upload_id: upload_id.clone(),
+            name: name.clone(),
+            title,
+            slug,
+            project_id: self.project_id,
+            folder_id,
+        };
+
+        match ingest_upload(self.repo, self.conf, self.storage, self.state, &details) {
+            Ok(ingested) => {
+                let message = if ingested.similar_images.is_empty() {
+                    String::from("Image imported")
+                } else {
+                    format!(
+                        "Image imported, but similar images exist: {:?}",
+                        ingested.similar_images
+                    )
+                };
+
+                self.report(
+                    path, ImportStatus::Imported, message, Some(folder_id),
+                    Some(ingested.id),
+                )
+            }
+
+            Err(e) => {
+                remove_upload(&upload_id);
+
+                let message = match e {
+                    IngestError::Rejected(rejection) => rejection.to_string(),
+                    _ => String::from("Some internal error occured."),
+                };
+
+                self.report(path, ImportStatus::Failed, message, None, None)
+            }
+        }
+    }
+
+    /// Copies a file's content to the `temp` directory and validates it like
+    /// an upload.
+    fn receive(&self, upload_id: &str, content: &mut dyn Read)
+        -> Result<(), UploadRejection> {
+        let part_path = format!("{}/{}.part", UPLOAD_DIR, upload_id);
+
+        let copied = File::create(&part_path).and_then(|mut file| {
+            io::copy(&mut content.take(self.limits.max_size + 1), &mut file)
+        });
+
+        match copied {
+            Ok(size) if size > self.limits.max_size => {
+                let _ = remove_file(&part_path);
+                Err(UploadRejection::TooLarge(self.limits.max_size))
+            }
+
+            Ok(_) => accept_upload(&part_path, upload_id, &self.limits).map(|_| ()),
+
+            Err(e) => {
+                error!("Error while receiving {}: {}", part_path, e);
+                let _ = remove_file(&part_path);
+                Err(UploadRejection::IOError)
+            }
+        }
+    }
+}
@@ -0,0 +1,179 @@
+//! Image ingest service
+//!
+//! Creates an image from an accepted upload (see `service::sniff`): the upload
+//! is validated against the project's upload limits, stored by its content
+//! hash, and its metadata, placeholder and perceptual hash are saved.
+
+use actix_web::web::Data;
+use chrono::Utc;
+use log::{ debug, error, warn };
+
+use crate::{
+    api::service::{
+        orientation::{ read_orientation, swaps_dimensions },
+        metadata::extract_metadata,
+        placeholder::save_placeholder,
+        content::{ hash_file, store_content, lock_content },
+        sniff::{ find_upload, inspect_upload, check_limits, UploadRejection },
+        cleanup::claim_upload,
+        similarity::{ save_perceptual_hash, find_similar, DEFAULT_THRESHOLD },
+    },
+    model::{ image::Image, upload_image::UploadImage },
+    repository::Repository, server::config::ServerConfig,
+    server_state::ServerState, storage::Storages,
+};
+
+/// An image created from an upload.
+pub struct IngestedImage {
+    pub id: u32,
+    /// IDs of the near-duplicates of the image in its project.
+    pub similar_images: Vec<u32>,
+}
+
+pub enum IngestError {
+    /// The upload doesn't exist.
+    NotFound,
+    Rejected(UploadRejection),
+    InternalError,
+}
+
+/// Creates an image from the accepted upload `details.upload_id`.
+pub fn ingest_upload(
+    repo: &Data<dyn Repository + Sync + Send>, conf: &ServerConfig,
+    storage: &Storages, state: &ServerState, details: &UploadImage,
+) -> Result<IngestedImage, IngestError> {
+    // The type and dimensions of the image come from the uploaded file, not
+    // from the request.
+    let source_file_path = find_upload(&details.upload_id).ok_or(IngestError::NotFound)?;
+
+    debug!("source file path: {}", source_file_path);
+
+    // The project's upload limits apply, whatever was checked on upload.
+    let project_slug = match repo.get_project_repo() {
+        Ok(mut proj_repo) => {
+            proj_repo.get(details.project_id).ok().map(|project| project.slug)
+        }
+
+        Err(_) => None,
+    };
+
+    let limits = conf.get_upload_limits(project_slug.as_deref());
+
+    let uploaded = inspect_upload(&source_file_path)
+        .and_then(|u| check_limits(&u, &limits).map(|_| u))
+        .map_err(IngestError::Rejected)?;
+
+    let mut image = Image {
+        id: 0,
+        name: details.name.clone(),
+        title: details.title.clone(),
+        slug: details.slug.clone(),
+        encoding: uploaded.encoding,
+        height: 0,
+        width: 0,
+        is_published: true,
+        project_id: details.project_id,
+        folder_id: details.folder_id,
+        focal_point: None,
+        placeholder: None,
+        edits: vec![],
+        content_hash: None,
+        created_on: Utc::now(),
+        created_by: 0,
+        modified_on: Utc::now(),
+        modified_by: 0,
+    };
+
+    // Store the dimensions the image is displayed with.
+    if swaps_dimensions(read_orientation(source_file_path.as_str())) {
+        image.height = uploaded.width as u16;
+        image.width = uploaded.height as u16;
+    } else {
+        image.height = uploaded.height as u16;
+        image.width = uploaded.width as u16;
+    }
+
+    // Identical uploads share the original file.
+    let content_hash = hash_file(&source_file_path)
+        .map_err(|_| IngestError::InternalError)?;
+
+    image.content_hash = Some(content_hash.clone());
+
+    let mut img_repo = repo.get_image_repo().map_err(|e| {
+        error!("Error while getting image repo: {}", e);
+        IngestError::InternalError
+    })?;
+
+    // Keeps the original file from being removed along with another image
+    // until this one is added.
+    let content_lock = lock_content(repo, &content_hash)
+        .map_err(|_| IngestError::InternalError)?;
+
+    let id = img_repo.add(image.clone()).map_err(|_| IngestError::InternalError)?;
+
+    // Finally, move the temp image to its content-addressed path (or drop it,
+    // if the content is stored already)
+    let dest_file_path = store_content(
+        storage, &conf.upload_dir, &source_file_path, &content_hash,
+        image.encoding,
+    ).map_err(|_| {
+        error!("An I/O error occured while adding an image");
+
+        // The image would have no original file.
+        if let Err(e) = img_repo.remove_item(id) {
+            error!("Error while removing image {}: {}", id, e);
+        }
+
+        IngestError::InternalError
+    })?;
+
+    drop(content_lock);
+
+    claim_upload(state, &details.upload_id);
+    save_metadata(repo, id, &dest_file_path);
+    save_placeholder(repo, id, &dest_file_path, &[]);
+
+    let similar_images = match save_perceptual_hash(repo, id, &dest_file_path) {
+        Some(hash) => {
+            match img_repo.get_perceptual_hashes(Some(image.project_id)) {
+                Ok(hashes) => find_similar(
+                    &known_hashes(hashes), id, hash, DEFAULT_THRESHOLD,
+                ),
+                Err(_) => vec![],
+            }
+        }
+
+        None => vec![],
+    };
+
+    if !similar_images.is_empty() {
+        warn!("Image {} has near-duplicates: {:?}", id, similar_images);
+    }
+
+    Ok(IngestedImage { id, similar_images })
+}
+
+/// Extracts the embedded metadata of a newly added image and saves it.
+/// Failures are logged, the image is kept without metadata.
+fn save_metadata(
+    repo: &Data<dyn Repository + Sync + Send>, image_id: u32, file_path: &str
+) {
+    let metadata = extract_metadata(image_id, file_path);
+
+    match repo.get_image_metadata_repo() {
+        Ok(mut meta_repo) => {
+            if let Err(e) = meta_repo.save(metadata) {
+                error!("Error while saving metadata of image {}: {}", image_id, e);
+            }
+        }
+
+        Err(e) => {
+            error!("Error while getting image metadata repo: {}", e);
+        }
+    }
+}
+
+/// Drops the images whose perceptual hash was not computed.
+fn known_hashes(hashes: Vec<(u32, Option<u64>)>) -> Vec<(u32, u64)> {
+    hashes.into_iter().filter_map(|(id, hash)| Some((id, hash?))).collect()
+}
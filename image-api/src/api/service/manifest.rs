@@ -0,0 +1,240 @@
+//! Image manifest service
+//!
+//! Builds the list of an image's published renditions along with ready-made
+//! `srcset`/`sizes` attributes and `<picture>` markup for responsive images.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::model::{ image::Image, rendition::Rendition, transform::Fit };
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestRendition {
+    pub slug: String,
+    pub target_device: String,
+    /// Actual dimensions of the rendition file.
+    pub width: u16,
+    pub height: u16,
+    pub mime_type: String,
+    pub url: String,
+}
+
+/// Renditions of a target device (and mime type).
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenditionGroup {
+    pub target_device: String,
+    pub mime_type: String,
+    pub srcset: String,
+    pub sizes: String,
+    /// Media query of the group's `<source>` element, if configured.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageManifest {
+    pub image_id: u32,
+    pub title: String,
+    pub width: u16,
+    pub height: u16,
+    pub renditions: Vec<ManifestRendition>,
+    /// `srcset` of all the renditions (one per width).
+    pub srcset: String,
+    pub sizes: String,
+    pub groups: Vec<RenditionGroup>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub picture: Option<String>,
+}
+
+/// Builds the manifest of an image.
+///
+/// # Arguments
+///
+/// * `image_url` - Absolute URL of the image, the rendition slugs are appended
+///   to it.
+/// * `renditions` - Renditions of the image, unpublished ones are skipped.
+/// * `media_queries` - Media queries of the target devices.
+/// * `picture` - Whether to generate the `<picture>` snippet.
+pub fn build_manifest(
+    image: &Image, image_url: &str, renditions: &[Rendition],
+    media_queries: &HashMap<String, String>, picture: bool,
+) -> ImageManifest {
+    let mut manifest_renditions: Vec<ManifestRendition> = renditions.iter()
+        .filter(|r| r.is_published)
+        .map(|r| {
+            let (width, height) = rendition_dimensions(r, image);
+
+            ManifestRendition {
+                slug: r.slug.clone(),
+                target_device: r.target_device.clone(),
+                width,
+                height,
+                mime_type: r.encoding.mime_type(),
+                url: format!("{}/{}{}", image_url, r.slug, r.encoding.extension()),
+            }
+        })
+        .collect();
+
+    manifest_renditions.sort_by_key(|r| r.width);
+
+    // Groups keep the order in which their devices first appear.
+    let mut groups: Vec<RenditionGroup> = vec![];
+    let mut group_members: Vec<Vec<&ManifestRendition>> = vec![];
+
+    for r in &manifest_renditions {
+        match groups.iter().position(|g| {
+            g.target_device == r.target_device && g.mime_type == r.mime_type
+        }) {
+            Some(i) => group_members[i].push(r),
+            None => {
+                groups.push(RenditionGroup {
+                    target_device: r.target_device.clone(),
+                    mime_type: r.mime_type.clone(),
+                    srcset: String::new(),
+                    sizes: String::new(),
+                    media: media_queries.get(&r.target_device).cloned(),
+                });
+
+                group_members.push(vec![r]);
+            }
+        }
+    }
+
+    for (group, members) in groups.iter_mut().zip(&group_members) {
+        group.srcset = srcset(members);
+        group.sizes = sizes(members);
+    }
+
+    let all: Vec<&ManifestRendition> = manifest_renditions.iter().collect();
+    let all_srcset = srcset(&all);
+    let all_sizes = sizes(&all);
+
+    let picture = if picture {
+        picture_html(image, &groups, &all, &all_srcset, &all_sizes)
+    } else {
+        None
+    };
+
+    ImageManifest {
+        image_id: image.id,
+        title: image.title.clone(),
+        width: image.width,
+        height: image.height,
+        srcset: all_srcset,
+        sizes: all_sizes,
+        renditions: manifest_renditions,
+        groups,
+        picture,
+    }
+}
+
+/// Returns the dimensions of the rendition file, which differ from the
+/// rendition's dimensions when the image is fitted inside them.
+pub fn rendition_dimensions(rendition: &Rendition, image: &Image) -> (u16, u16) {
+    let (src_width, src_height) = match rendition.crop {
+        Some(rect) => (rect.width, rect.height),
+        None => (image.width, image.height),
+    };
+
+    match rendition.fit {
+        Fit::Fit if src_width > 0 && src_height > 0 => {
+            let scale = f64::min(
+                rendition.width as f64 / src_width as f64,
+                rendition.height as f64 / src_height as f64,
+            );
+
+            (
+                (src_width as f64 * scale).round() as u16,
+                (src_height as f64 * scale).round() as u16,
+            )
+        }
+
+        Fit::Crop => (
+            rendition.width.min(src_width), rendition.height.min(src_height)
+        ),
+
+        _ => (rendition.width, rendition.height),
+    }
+}
+
+/// `srcset` with width descriptors, one rendition per width. The renditions
+/// must be sorted by width.
+fn srcset(renditions: &[&ManifestRendition]) -> String {
+    let mut candidates: Vec<String> = vec![];
+    let mut last_width: u16 = 0;
+
+    for r in renditions {
+        if r.width == 0 || r.width == last_width { continue; }
+
+        candidates.push(format!("{} {}w", r.url, r.width));
+        last_width = r.width;
+    }
+
+    candidates.join(", ")
+}
+
+/// `sizes` that selects the smallest rendition that is at least as wide as
+/// the viewport. The renditions must be sorted by width.
+fn sizes(renditions: &[&ManifestRendition]) -> String {
+    let mut widths: Vec<u16> = renditions.iter()
+        .map(|r| r.width)
+        .filter(|w| *w > 0)
+        .collect();
+
+    widths.dedup();
+
+    match widths.split_last() {
+        Some((largest, rest)) => {
+            let mut conditions: Vec<String> = rest.iter()
+                .map(|w| format!("(max-width: {}px) {}px", w, w))
+                .collect();
+
+            conditions.push(format!("{}px", largest));
+            conditions.join(", ")
+        }
+
+        None => String::new(),
+    }
+}
+
+/// `<picture>` element with a `<source>` for every group with a media query
+/// and an `<img>` fallback showing the largest rendition.
+fn picture_html(
+    image: &Image, groups: &[RenditionGroup], all: &[&ManifestRendition],
+    all_srcset: &str, all_sizes: &str,
+) -> Option<String> {
+    let fallback = all.last()?;
+    let mut html = String::from("<picture>");
+
+    for group in groups {
+        if let Some(media) = &group.media {
+            html.push_str(&format!(
+                "<source media=\"{}\" type=\"{}\" srcset=\"{}\" sizes=\"{}\">",
+                escape_html(media), group.mime_type, escape_html(&group.srcset),
+                group.sizes,
+            ));
+        }
+    }
+
+    html.push_str(&format!(
+        "<img src=\"{}\" srcset=\"{}\" sizes=\"{}\" width=\"{}\" height=\"{}\" \
+            alt=\"{}\" loading=\"lazy\">",
+        escape_html(&fallback.url), escape_html(all_srcset), all_sizes,
+        fallback.width, fallback.height, escape_html(&image.title),
+    ));
+
+    html.push_str("</picture>");
+
+    Some(html)
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
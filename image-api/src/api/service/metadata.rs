@@ -0,0 +1,399 @@
+//! Image metadata service
+//!
+//! Extracts the descriptive metadata (camera, capture date, GPS, credits and
+//! rights) embedded in image files as EXIF, IPTC-IIM and XMP.
+//!
+//! EXIF values take precedence, missing values are filled from IPTC and then
+//! from XMP.
+
+use std::{ collections::HashMap, fs::read, io::Cursor };
+
+use chrono::NaiveDateTime;
+use exif::{ Reader, Tag, In, Value, Exif };
+use img_parts::{ Bytes, jpeg::{ Jpeg, markers } };
+use regex::Regex;
+use lazy_static::lazy_static;
+use log::{ debug, error };
+
+use crate::model::image_metadata::ImageMetadata;
+
+/// Photoshop image resource holding the IPTC-IIM data.
+const IPTC_RESOURCE_ID: u16 = 0x0404;
+
+/// IPTC-IIM application record (2) datasets.
+const IPTC_KEYWORDS: u8 = 25;
+const IPTC_BYLINE: u8 = 80;
+const IPTC_COPYRIGHT: u8 = 116;
+const IPTC_CAPTION: u8 = 120;
+
+/// XMP properties read by `read_xmp`.
+const XMP_PROPERTIES: [&str; 7] = [
+    "dc:creator", "dc:rights", "dc:description", "dc:subject",
+    "xmpRights:UsageTerms", "xmpRights:WebStatement", "xmpRights:Marked",
+];
+
+/// Sizes of the `IMAGE_METADATA` columns: characters of the `VARCHAR`s and
+/// bytes of the `TEXT`s.
+const CAMERA_LEN: usize = 128;
+const COPYRIGHT_LEN: usize = 512;
+const CREATOR_LEN: usize = 256;
+const WEB_STATEMENT_LEN: usize = 1000;
+const TEXT_BYTES: usize = 65535;
+
+lazy_static! {
+    static ref XMP_ITEM_RE: Regex = Regex::new(
+        r"(?s)<rdf:li(?:\s[^>]*)?>(.*?)</rdf:li>"
+    ).unwrap();
+
+    /// Patterns of the element and of the attribute of each XMP property.
+    static ref XMP_PROPERTY_RES: HashMap<&'static str, (Regex, Regex)> =
+        XMP_PROPERTIES.iter().map(|property| {
+            let escaped = regex::escape(property);
+
+            (*property, (
+                Regex::new(
+                    format!(r"(?s)<{0}(?:\s[^>]*)?>(.*?)</{0}>", escaped).as_str()
+                ).unwrap(),
+                Regex::new(
+                    format!(r#"\s{}\s*=\s*"([^"]*)""#, escaped).as_str()
+                ).unwrap(),
+            ))
+        }).collect();
+}
+
+/// Extracts the metadata of the image file at `path`.
+pub fn extract_metadata(image_id: u32, path: &str) -> ImageMetadata {
+    let mut metadata = ImageMetadata { image_id, ..Default::default() };
+
+    let data: Vec<u8> = match read(path) {
+        Ok(d) => d,
+        Err(e) => {
+            error!("Error while reading {}: {}", path, e);
+            return metadata;
+        }
+    };
+
+    match Reader::new().read_from_container(&mut Cursor::new(&data)) {
+        Ok(exif) => { read_exif(&exif, &mut metadata); }
+        Err(e) => { debug!("No EXIF data in {}: {}", path, e); }
+    }
+
+    read_iptc(&data, &mut metadata);
+    read_xmp(&data, &mut metadata);
+    fit_columns(&mut metadata);
+
+    metadata
+}
+
+/// Truncates the values to the sizes of their columns, the metadata would
+/// not be saved otherwise.
+fn fit_columns(metadata: &mut ImageMetadata) {
+    let chars = |value: &mut Option<String>, len: usize| {
+        if let Some(v) = value {
+            if let Some((end, _)) = v.char_indices().nth(len) {
+                v.truncate(end);
+            }
+        }
+    };
+
+    chars(&mut metadata.camera_make, CAMERA_LEN);
+    chars(&mut metadata.camera_model, CAMERA_LEN);
+    chars(&mut metadata.lens, CAMERA_LEN);
+    chars(&mut metadata.copyright, COPYRIGHT_LEN);
+    chars(&mut metadata.creator, CREATOR_LEN);
+    chars(&mut metadata.web_statement, WEB_STATEMENT_LEN);
+
+    for value in [ &mut metadata.caption, &mut metadata.usage_terms ] {
+        if let Some(v) = value {
+            truncate_bytes(v, TEXT_BYTES);
+        }
+    }
+
+    // Keywords are stored as a JSON array, the last ones are dropped.
+    while !metadata.keywords.is_empty()
+        && serde_json::to_string(&metadata.keywords).map_or(0, |k| k.len()) > TEXT_BYTES {
+        metadata.keywords.pop();
+    }
+}
+
+/// Truncates a string to at most `len` bytes, at a character boundary.
+fn truncate_bytes(value: &mut String, len: usize) {
+    if value.len() > len {
+        let end = (0..=len).rev().find(|i| value.is_char_boundary(*i)).unwrap_or(0);
+        value.truncate(end);
+    }
+}
+
+fn exif_string(exif: &Exif, tag: Tag) -> Option<String> {
+    match exif.get_field(tag, In::PRIMARY).map(|f| &f.value) {
+        Some(Value::Ascii(values)) => values.iter()
+            .map(|v| String::from_utf8_lossy(v)
+                .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+                .to_string())
+            .find(|v| !v.is_empty()),
+        _ => None,
+    }
+}
+
+fn exif_rational(exif: &Exif, tag: Tag, index: usize) -> Option<f64> {
+    match exif.get_field(tag, In::PRIMARY).map(|f| &f.value) {
+        Some(Value::Rational(values)) if values.len() > index => {
+            let value = values[index];
+
+            if value.denom == 0 { None } else { Some(value.to_f64()) }
+        }
+
+        _ => None,
+    }
+}
+
+/// Returns the GPS coordinate (in degrees, negative for south/west) from the
+/// degrees, minutes and seconds of `tag`.
+fn exif_coordinate(exif: &Exif, tag: Tag, ref_tag: Tag, negative: &str)
+    -> Option<f64> {
+    let degrees = exif_rational(exif, tag, 0)?;
+    let minutes = exif_rational(exif, tag, 1).unwrap_or(0.0);
+    let seconds = exif_rational(exif, tag, 2).unwrap_or(0.0);
+
+    let coordinate = degrees + minutes / 60.0 + seconds / 3600.0;
+
+    match exif_string(exif, ref_tag) {
+        Some(r) if r.eq_ignore_ascii_case(negative) => Some(-coordinate),
+        _ => Some(coordinate),
+    }
+}
+
+fn read_exif(exif: &Exif, metadata: &mut ImageMetadata) {
+    metadata.camera_make = exif_string(exif, Tag::Make);
+    metadata.camera_model = exif_string(exif, Tag::Model);
+    metadata.lens = exif_string(exif, Tag::LensModel);
+    metadata.copyright = exif_string(exif, Tag::Copyright);
+    metadata.creator = exif_string(exif, Tag::Artist);
+
+    metadata.capture_date = exif_string(exif, Tag::DateTimeOriginal)
+        .or_else(|| exif_string(exif, Tag::DateTime))
+        .and_then(|d| NaiveDateTime::parse_from_str(
+            d.as_str(), "%Y:%m:%d %H:%M:%S"
+        ).ok());
+
+    metadata.gps_latitude = exif_coordinate(
+        exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, "S"
+    );
+    metadata.gps_longitude = exif_coordinate(
+        exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, "W"
+    );
+
+    metadata.gps_altitude = exif_rational(exif, Tag::GPSAltitude, 0)
+        .map(|altitude| {
+            let below_sea_level = exif.get_field(Tag::GPSAltitudeRef, In::PRIMARY)
+                .and_then(|f| f.value.get_uint(0)) == Some(1);
+
+            if below_sea_level { -altitude } else { altitude }
+        });
+}
+
+/// Returns the IPTC-IIM data stored in the Photoshop resources (APP13) of a
+/// JPEG file.
+fn find_iptc(data: &[u8]) -> Option<Vec<u8>> {
+    let jpeg = Jpeg::from_bytes(Bytes::copy_from_slice(data)).ok()?;
+
+    for segment in jpeg.segments_by_marker(markers::APP13) {
+        let contents = segment.contents();
+        let mut pos = match contents.strip_prefix(b"Photoshop 3.0\0") {
+            Some(_) => 14,
+            None => { continue; }
+        };
+
+        while pos + 12 <= contents.len() && &contents[pos..pos + 4] == b"8BIM" {
+            let id = u16::from_be_bytes([contents[pos + 4], contents[pos + 5]]);
+
+            // Pascal string name, padded to an even length.
+            let name_len = contents[pos + 6] as usize;
+            pos += 6 + ((name_len + 2) & !1);
+
+            if pos + 4 > contents.len() { break; }
+
+            let size = u32::from_be_bytes([
+                contents[pos], contents[pos + 1],
+                contents[pos + 2], contents[pos + 3],
+            ]) as usize;
+            pos += 4;
+
+            if pos + size > contents.len() { break; }
+
+            if id == IPTC_RESOURCE_ID {
+                return Some(contents[pos..pos + size].to_vec());
+            }
+
+            pos += (size + 1) & !1;
+        }
+    }
+
+    None
+}
+
+fn read_iptc(data: &[u8], metadata: &mut ImageMetadata) {
+    let iptc = match find_iptc(data) {
+        Some(i) => i,
+        None => { return; }
+    };
+
+    let mut keywords: Vec<String> = vec![];
+    let mut byline: Option<String> = None;
+    let mut copyright: Option<String> = None;
+    let mut caption: Option<String> = None;
+
+    let mut pos = 0;
+
+    while pos + 5 <= iptc.len() && iptc[pos] == 0x1C {
+        let record = iptc[pos + 1];
+        let dataset = iptc[pos + 2];
+        let size = u16::from_be_bytes([iptc[pos + 3], iptc[pos + 4]]) as usize;
+
+        // Extended datasets (size with the highest bit set) are not used by
+        // the text fields.
+        if size & 0x8000 != 0 { break; }
+
+        pos += 5;
+
+        if pos + size > iptc.len() { break; }
+
+        if record == 2 {
+            let value = String::from_utf8_lossy(&iptc[pos..pos + size])
+                .trim()
+                .to_string();
+
+            if !value.is_empty() {
+                match dataset {
+                    IPTC_KEYWORDS => { keywords.push(value); }
+                    IPTC_BYLINE => { byline.get_or_insert(value); }
+                    IPTC_COPYRIGHT => { copyright.get_or_insert(value); }
+                    IPTC_CAPTION => { caption.get_or_insert(value); }
+                    _ => {}
+                }
+            }
+        }
+
+        pos += size;
+    }
+
+    if metadata.keywords.is_empty() { metadata.keywords = keywords; }
+    if metadata.creator.is_none() { metadata.creator = byline; }
+    if metadata.copyright.is_none() { metadata.copyright = copyright; }
+    if metadata.caption.is_none() { metadata.caption = caption; }
+}
+
+/// Returns the values of an XMP property, either from the `rdf:li` items of
+/// the element, the element's text or an attribute.
+/// Only the properties of `XMP_PROPERTIES` are read.
+fn xmp_values(xmp: &str, property: &str) -> Vec<String> {
+    let (element, attribute) = match XMP_PROPERTY_RES.get(property) {
+        Some(res) => res,
+        None => { return vec![]; }
+    };
+
+    if let Some(captures) = element.captures(xmp) {
+        let content = captures.get(1).map(|c| c.as_str()).unwrap_or("");
+
+        let items: Vec<String> = XMP_ITEM_RE.captures_iter(content)
+            .filter_map(|c| c.get(1))
+            .map(|c| unescape_xml(c.as_str().trim()))
+            .filter(|v| !v.is_empty())
+            .collect();
+
+        if !items.is_empty() || content.contains("<rdf:") {
+            return items;
+        }
+
+        let text = unescape_xml(content.trim());
+
+        return if text.is_empty() { vec![] } else { vec![text] };
+    }
+
+    attribute.captures(xmp)
+        .and_then(|c| c.get(1))
+        .map(|c| unescape_xml(c.as_str().trim()))
+        .filter(|v| !v.is_empty())
+        .map(|v| vec![v])
+        .unwrap_or_default()
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn read_xmp(data: &[u8], metadata: &mut ImageMetadata) {
+    // XMP packets are stored as plain XML in all the supported formats.
+    let text = String::from_utf8_lossy(data);
+
+    let start = match text.find("<x:xmpmeta") {
+        Some(s) => s,
+        None => { return; }
+    };
+
+    let end = match text[start..].find("</x:xmpmeta>") {
+        Some(e) => start + e,
+        None => { return; }
+    };
+
+    let xmp = &text[start..end];
+    let first = |property: &str| xmp_values(xmp, property).into_iter().next();
+
+    if metadata.creator.is_none() {
+        let creators = xmp_values(xmp, "dc:creator");
+
+        if !creators.is_empty() { metadata.creator = Some(creators.join(", ")); }
+    }
+
+    if metadata.copyright.is_none() { metadata.copyright = first("dc:rights"); }
+    if metadata.caption.is_none() { metadata.caption = first("dc:description"); }
+
+    if metadata.keywords.is_empty() {
+        metadata.keywords = xmp_values(xmp, "dc:subject");
+    }
+
+    metadata.usage_terms = first("xmpRights:UsageTerms");
+    metadata.web_statement = first("xmpRights:WebStatement");
+    metadata.rights_marked = first("xmpRights:Marked")
+        .map(|m| m.eq_ignore_ascii_case("true"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_xmp_items_elements_and_attributes() {
+        let xmp = r#"<x:xmpmeta><rdf:Description xmpRights:Marked="True">
+            <dc:subject><rdf:Bag><rdf:li>a</rdf:li><rdf:li>b &amp; c</rdf:li></rdf:Bag></dc:subject>
+            <xmpRights:WebStatement>https://example.com</xmpRights:WebStatement>
+            </rdf:Description>"#;
+
+        assert_eq!(xmp_values(xmp, "dc:subject"), vec![ "a", "b & c" ]);
+        assert_eq!(
+            xmp_values(xmp, "xmpRights:WebStatement"), vec![ "https://example.com" ]
+        );
+        assert_eq!(xmp_values(xmp, "xmpRights:Marked"), vec![ "True" ]);
+        assert!(xmp_values(xmp, "dc:rights").is_empty());
+    }
+
+    #[test]
+    fn truncates_values_to_the_columns() {
+        let mut metadata = ImageMetadata {
+            camera_make: Some("é".repeat(200)),
+            caption: Some("é".repeat(40000)),
+            keywords: vec![ "k".repeat(1000); 100 ],
+            ..Default::default()
+        };
+
+        fit_columns(&mut metadata);
+
+        assert_eq!(metadata.camera_make.unwrap().chars().count(), CAMERA_LEN);
+        assert_eq!(metadata.caption.unwrap().len(), TEXT_BYTES - 1);
+        assert_eq!(metadata.keywords.len(), 65);
+    }
+}
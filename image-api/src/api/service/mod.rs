@@ -4,4 +4,27 @@
 
 pub mod remove;
 pub mod path;
+pub mod cache;
+pub mod stream;
+pub mod signing;
+pub mod encode;
+pub mod publish;
+pub mod negotiate;
+pub mod orientation;
+pub mod metadata;
 
+pub mod placeholder;
+pub mod manifest;
+pub mod device;
+pub mod density;
+pub mod watermark;
+pub mod edit;
+pub mod content;
+pub mod similarity;
+pub mod tus;
+pub mod sniff;
+pub mod cleanup;
+pub mod ingest;
+pub mod archive;
+pub mod import;
+pub mod hotfolder;
@@ -0,0 +1,99 @@
+//! Content negotiation service
+//!
+//! Picks a modern image encoding (AVIF or WebP) for clients that advertise
+//! support for it in the `Accept` header.
+
+use std::fs::metadata;
+
+use actix_web::{ HttpRequest, http::header };
+use log::{ debug, error };
+
+use crate::{
+    api::service::encode::{ save_image, EncodeOptions },
+    model::encoding::Encoding,
+};
+
+/// Encodings that can be served instead of a JPG/PNG rendition, in the order
+/// of preference.
+const NEGOTIABLE_ENCODINGS: [Encoding; 2] = [ Encoding::AVIF, Encoding::WEBP ];
+
+/// Returns `true` if renditions with the `encoding` can be negotiated.
+pub fn is_negotiable(encoding: Encoding) -> bool {
+    encoding == Encoding::JPG || encoding == Encoding::PNG
+}
+
+/// Returns the preferred encoding that the client accepts, `None` if the
+/// client doesn't explicitly accept any of the negotiable encodings.
+///
+/// Wildcards (e.g.: `image/*`) are not considered as the client may not
+/// actually be able to decode the modern formats.
+pub fn negotiate_encoding(req: &HttpRequest, encoding: Encoding)
+    -> Option<Encoding> {
+    if !is_negotiable(encoding) { return None; }
+
+    let accept: &str = match req.headers().get(header::ACCEPT) {
+        Some(value) => value.to_str().unwrap_or(""),
+        None => { return None; }
+    };
+
+    let accepted: Vec<String> = accept.split(',')
+        .filter_map(|media_range| {
+            let mut parts = media_range.split(';');
+            let media_type = parts.next()?.trim().to_lowercase();
+
+            // Ignore media types explicitly marked as not acceptable.
+            for param in parts {
+                if let Some((name, value)) = param.split_once('=') {
+                    if name.trim() == "q"
+                        && value.trim().parse::<f32>().unwrap_or(1.0) <= 0.0 {
+                        return None;
+                    }
+                }
+            }
+
+            Some(media_type)
+        })
+        .collect();
+
+    NEGOTIABLE_ENCODINGS.iter()
+        .find(|enc| accepted.contains(&enc.mime_type()))
+        .copied()
+}
+
+/// Returns the path of the negotiated variant of a cached rendition file.
+pub fn variant_path(file_path: &str, encoding: Encoding) -> String {
+    format!("{}{}", file_path, encoding.extension())
+}
+
+/// Creates the negotiated variant of a cached rendition file if it doesn't
+/// exist yet (or is older than the rendition file) and returns it's path.
+pub fn cache_variant(file_path: &str, encoding: Encoding)
+    -> Result<String, ()> {
+    let dest_path = variant_path(file_path, encoding);
+
+    let modified = |path: &str| metadata(path).and_then(|m| m.modified()).ok();
+
+    match (modified(&dest_path), modified(file_path)) {
+        (Some(variant), Some(rendition)) if variant >= rendition => {
+            return Ok(dest_path);
+        }
+        _ => {}
+    }
+
+    debug!("Creating {} variant: {}", encoding.mime_type(), dest_path);
+
+    match raster::open(file_path) {
+        Ok(raster_img) => {
+            save_image(
+                &raster_img, &dest_path, &EncodeOptions::new(encoding, None)
+            )?;
+
+            Ok(dest_path)
+        }
+
+        Err(_) => {
+            error!("Error loading rendition file: {}", file_path);
+            Err(())
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! EXIF orientation service
+//!
+//! Cameras store photos as they were captured and record how they should be
+//! displayed in the EXIF `Orientation` tag. The images are turned upright
+//! while they are decoded, so that all renditions are displayed correctly.
+
+use std::{ fs::{ File, read }, io::BufReader };
+
+use exif::{ Reader, Tag, In };
+use webp::Decoder;
+use log::{ debug, error };
+
+use crate::model::encoding::Encoding;
+
+/// EXIF `Orientation` tag number.
+const ORIENTATION_TAG: u16 = 0x0112;
+
+/// Returns the EXIF orientation (1 - 8) of the image file, `1` (upright) if
+/// the file has no orientation.
+pub fn read_orientation(path: &str) -> u32 {
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) => {
+            error!("Error while opening file {}: {}", path, e);
+            return 1;
+        }
+    };
+
+    match Reader::new().read_from_container(&mut BufReader::new(file)) {
+        Ok(exif) => {
+            match exif.get_field(Tag::Orientation, In::PRIMARY)
+                .and_then(|field| field.value.get_uint(0)) {
+                Some(orientation) if (1..=8).contains(&orientation) => {
+                    orientation
+                }
+
+                _ => 1,
+            }
+        }
+
+        Err(_) => 1,
+    }
+}
+
+/// Returns `true` if the orientation swaps the width and the height.
+pub fn swaps_dimensions(orientation: u32) -> bool {
+    (5..=8).contains(&orientation)
+}
+
+/// Opens an image file and turns it upright according to its EXIF
+/// orientation.
+pub fn open_upright(path: &str) -> Result<raster::Image, ()> {
+    let decoded = match Encoding::from(path) {
+        Encoding::WEBP => open_webp(path),
+        _ => raster::open(path).map_err(|_| ()),
+    };
+
+    match decoded {
+        Ok(mut raster_img) => {
+            apply_orientation(&mut raster_img, read_orientation(path));
+
+            Ok(raster_img)
+        }
+
+        Err(_) => {
+            error!("Error loading image file: {}", path);
+            Err(())
+        }
+    }
+}
+
+/// Decodes a WebP image, which `raster` doesn't support.
+fn open_webp(path: &str) -> Result<raster::Image, ()> {
+    let data = read(path).map_err(|_| ())?;
+    let decoded = Decoder::new(&data).decode().ok_or(())?;
+
+    let bytes: Vec<u8> = if decoded.is_alpha() {
+        decoded.to_vec()
+    } else {
+        decoded.chunks_exact(3)
+            .flat_map(|rgb| [ rgb[0], rgb[1], rgb[2], 255 ])
+            .collect()
+    };
+
+    Ok(raster::Image {
+        width: decoded.width() as i32,
+        height: decoded.height() as i32,
+        bytes,
+    })
+}
+
+/// Rotates and/or flips the image as described by the EXIF orientation.
+pub fn apply_orientation(raster_img: &mut raster::Image, orientation: u32) {
+    if orientation < 2 || orientation > 8 { return; }
+
+    debug!("Applying EXIF orientation: {}", orientation);
+
+    let (width, height) = (raster_img.width as usize, raster_img.height as usize);
+
+    let (out_width, out_height) = if swaps_dimensions(orientation) {
+        (height, width)
+    } else {
+        (width, height)
+    };
+
+    let mut bytes: Vec<u8> = vec![0; out_width * out_height * 4];
+
+    for y in 0..out_height {
+        for x in 0..out_width {
+            let (src_x, src_y) = match orientation {
+                2 => (width - 1 - x, y),                // Mirrored
+                3 => (width - 1 - x, height - 1 - y),   // Rotated 180°
+                4 => (x, height - 1 - y),               // Flipped
+                5 => (y, x),                            // Transposed
+                6 => (y, height - 1 - x),               // Rotated 90° CW
+                7 => (width - 1 - y, height - 1 - x),   // Transversed
+                _ => (width - 1 - y, x),                // Rotated 90° CCW
+            };
+
+            let src = (src_y * width + src_x) * 4;
+            let dest = (y * out_width + x) * 4;
+
+            bytes[dest..dest + 4]
+                .copy_from_slice(&raster_img.bytes[src..src + 4]);
+        }
+    }
+
+    raster_img.width = out_width as i32;
+    raster_img.height = out_height as i32;
+    raster_img.bytes = bytes;
+}
+
+/// Sets the orientation in raw EXIF data (starting at the TIFF header) to
+/// `1` (upright), for metadata copied to images that were turned upright.
+pub fn reset_orientation(exif: &mut [u8]) {
+    if exif.len() < 8 { return; }
+
+    let little_endian = match &exif[0..2] {
+        b"II" => true,
+        b"MM" => false,
+        _ => { return; }
+    };
+
+    let read_u16 = |data: &[u8], pos: usize| -> u16 {
+        let b = [data[pos], data[pos + 1]];
+        if little_endian { u16::from_le_bytes(b) } else { u16::from_be_bytes(b) }
+    };
+
+    let b = [exif[4], exif[5], exif[6], exif[7]];
+    let ifd_offset = if little_endian {
+        u32::from_le_bytes(b)
+    } else {
+        u32::from_be_bytes(b)
+    } as usize;
+
+    if ifd_offset + 2 > exif.len() { return; }
+
+    let entries = read_u16(exif, ifd_offset) as usize;
+
+    for i in 0..entries {
+        let entry = ifd_offset + 2 + i * 12;
+
+        if entry + 12 > exif.len() { return; }
+
+        if read_u16(exif, entry) == ORIENTATION_TAG {
+            // SHORT value stored inline in the first two bytes.
+            let value = if little_endian {
+                1u16.to_le_bytes()
+            } else {
+                1u16.to_be_bytes()
+            };
+
+            exif[entry + 8..entry + 10].copy_from_slice(&value);
+
+            return;
+        }
+    }
+}
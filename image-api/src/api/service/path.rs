@@ -10,12 +10,26 @@ use regex::Regex;
 
 use crate::{
     server::db::DBError, repository::Repository,
+    api::service::{
+        encode::{ save_image, EncodeOptions }, edit::open_edited,
+        density::split_density,
+        watermark::{ apply_watermark, WatermarkImage },
+        publish::db_to_error,
+    },
+    server::config::{ ProjectConfig, OutputConfig },
     model::{
-        rendition::Rendition, error::{ Error, ErrorType }, image::Image,
+        rendition::Rendition, error::{ Error, ErrorType },
+        image::{ Image, FocalPoint },
         encoding::{ Encoding, RE },
+        transform::{ Fit, Gravity, CropRect, TransformParams },
+        edit::EditOperation,
     },
 };
 
+/// Name of the directory (inside an image's rendition cache directory) where
+/// the on-the-fly transformations are cached.
+const TRANSFORM_CACHE_DIR: &str = "_t";
+
 /// Gets rendition that is represented by the given path
 pub fn get_rendition_from_path_segments<'a >(
     repo: &Data<dyn Repository + Sync + Send>,
@@ -124,6 +138,11 @@ pub fn get_rendition_from_path_segments<'a >(
 
         debug!("\tChecking folder with slug: {}", path_seg_owned.clone());
 
+        // Density variants (`hero@2x.jpg`) are variants of the rendition.
+        if is_last {
+            path_seg_owned = split_density(&path_seg_owned).0;
+        }
+
         if is_last && Encoding::match_extension(path_segment) {
             // TODO: Extract the extension here and match it with the rendition
             path_seg_owned =  String::from(
@@ -248,6 +267,95 @@ pub fn get_rendition_from_path_segments<'a >(
     Err(Error::new(ErrorType::NotFound, "NOT FOUND"))
 }
 
+/// Gets the image that is represented by the given path.
+///
+/// The path is either the path of an image (`project/folder/image`) or of one
+/// of its renditions.
+pub fn get_image_from_path_segments<'a>(
+    repo: &Data<dyn Repository + Sync + Send>,
+    path_segments: &'a Vec<&str>
+) -> Result<Image, Error<'a>> {
+    let mut img_repo;
+    let mut fol_repo;
+
+    let project_id: u32;
+    let mut folder_id: u32 = 0;
+
+    match (repo.get_image_repo(), repo.get_folder_repo()) {
+        (Ok(i_repo), Ok(f_repo)) => { img_repo = i_repo; fol_repo = f_repo; }
+        _ => {
+            error!("Error while getting image or folder repo");
+
+            return Err(Error::new(
+                ErrorType::InternalError, "Some internal error occured."
+            ));
+        }
+    }
+
+    match repo.get_project_repo() {
+        Ok(mut proj_repo) => {
+            match proj_repo.is_valid_slug(path_segments[0].to_owned()) {
+                Ok(Some(id)) => { project_id = id; }
+                Ok(None) => {
+                    return Err(Error::new(ErrorType::NotFound, "NOT FOUND"));
+                }
+                Err(e) => {
+                    error!("Some error occured while getting project {}", e);
+
+                    return Err(Error::new(
+                        ErrorType::InternalError, "Some error occured"
+                    ));
+                }
+            }
+        }
+
+        Err(e) => {
+            error!("Error while getting project repo: {}", e);
+
+            return Err(Error::new(
+                ErrorType::InternalError, "Some internal error occured."
+            ));
+        }
+    }
+
+    if path_segments.len() < 2 {
+        return Err(Error::new(ErrorType::NotFound, "NOT FOUND"));
+    }
+
+    let last = path_segments.len() - 1;
+
+    for path_segment in &path_segments[1..last] {
+        match fol_repo.is_valid_slug(
+            project_id, folder_id, String::from(*path_segment)
+        ) {
+            Ok(Some(id)) => { folder_id = id; }
+            Ok(None) | Err(DBError::NotFound) => {
+                // Not an image path, may be the path of a rendition.
+                return get_rendition_from_path_segments(repo, path_segments)
+                    .and_then(|rendition| img_repo.get(rendition.image_id)
+                        .map_err(db_to_error));
+            }
+            Err(_) => {
+                return Err(Error::new(
+                    ErrorType::InternalError, "Some error occured"
+                ));
+            }
+        }
+    }
+
+    let image_slug = path_segments[last].split('.').next().unwrap_or("");
+
+    match img_repo.is_valid_slug(project_id, folder_id, String::from(image_slug)) {
+        Ok(Some(id)) => img_repo.get(id).map_err(db_to_error),
+        Ok(None) | Err(DBError::NotFound) => {
+            get_rendition_from_path_segments(repo, path_segments)
+                .and_then(|rendition| img_repo.get(rendition.image_id)
+                    .map_err(db_to_error))
+        }
+        Err(_) => Err(Error::new(ErrorType::InternalError, "Some error occured")),
+    }
+}
+
 /// Gets the path of an image
 pub fn get_image_path(
     repo: &Data<dyn Repository + Sync + Send>,
@@ -433,43 +541,67 @@ pub fn rendition_cache_path(path: &str) -> Option<String> {
     None
 }
 
+/// Creates a rendition out of the source image (see `crop_image` and
+/// `resize_image`) and saves it to `dest_path`.
+///
+/// `focal_point` (of the source image) is kept in frame by the cropping fit
+/// modes.
 pub fn resize_and_save_rendition(
-    raster_img: &mut raster::Image, dest_path: &str, width: u16, height: u16
+    raster_img: &mut raster::Image, dest_path: &str, rendition: &Rendition,
+    focal_point: Option<FocalPoint>, options: &EncodeOptions,
+    watermark: Option<&WatermarkImage>,
 ) -> Result<(),()> {
-    match raster::editor::resize(
-        raster_img,
-        width as i32,
-        height as i32,
-        raster::ResizeMode::Fit
-    ) {
-        Ok(_) => {
-            match create_folder_tree(dest_path) {
-                Err (()) => { return Err(()); }
-                _ => {}
-            }
+    let mut focal_point = focal_point;
 
-            debug!("Saving rendition to path: {}", dest_path);
+    if let Some(crop) = rendition.crop {
+        focal_point = focal_point.map(|f| focal_point_in_rect(
+            f, &crop, raster_img.width as u16, raster_img.height as u16
+        ));
 
-            match raster::save(&raster_img, dest_path) {
-                Ok (_) => { return Ok (()); }
-                Err(_) => {
-                    error!("Error while saving file.");
-                    return Err(());
-                }
-            }
-        }
+        crop_image(raster_img, &crop)?;
+    }
 
-        Err(_) => { error!("Error while resizing."); return Err(()); }
+    resize_image(
+        raster_img,
+        rendition.width,
+        rendition.height,
+        rendition.fit,
+        rendition.gravity,
+        focal_point
+    )?;
+
+    if let Some(mark) = watermark {
+        apply_watermark(raster_img, mark)?;
     }
+
+    match create_folder_tree(dest_path) {
+        Err (()) => { return Err(()); }
+        _ => {}
+    }
+
+    debug!("Saving rendition to path: {}", dest_path);
+
+    save_image(&raster_img, dest_path, options)
 }
 
+/// Creates the rendition file from the (edited) source image,
+/// `project_config` holds the project's encoding defaults, `watermark` the
+/// rendition's watermark (see `load_watermark`).
 pub fn cache_rendition_file(
-    src_path: &str, dest_path: &str, width: u16, height: u16
+    src_path: &str, dest_path: &str, rendition: &Rendition, image: &Image,
+    project_config: &ProjectConfig, watermark: Option<&WatermarkImage>,
 ) -> Result<(),()> {
-    match raster::open(src_path) {
+    match open_edited(src_path, &image.edits) {
         Ok(mut raster_img) => {
             return resize_and_save_rendition(
-                &mut raster_img, dest_path, width, height
+                &mut raster_img,
+                dest_path,
+                rendition,
+                image.focal_point,
+                &EncodeOptions::for_rendition(
+                    rendition, &project_config.output, src_path
+                ),
+                watermark,
             );
         }
 
@@ -477,3 +609,257 @@ pub fn cache_rendition_file(
     }
 }
 
+/// Translates a focal point of an image into the coordinates of a rectangle
+/// cut out of it. Points outside the rectangle are moved to it's edge.
+fn focal_point_in_rect(
+    focal_point: FocalPoint, rect: &CropRect, width: u16, height: u16
+) -> FocalPoint {
+    let x = (focal_point.x * width as f32 - rect.x as f32) / rect.width as f32;
+    let y = (focal_point.y * height as f32 - rect.y as f32) / rect.height as f32;
+
+    FocalPoint { x: x.clamp(0.0, 1.0), y: y.clamp(0.0, 1.0) }
+}
+
+/// Returns the offset of a `length` long window centered on the focal point
+/// `fraction` of `total`, kept inside the `total`.
+fn focal_offset(fraction: f32, total: i32, length: i32) -> i32 {
+    let offset = (fraction * total as f32).round() as i32 - length / 2;
+
+    offset.clamp(0, (total - length).max(0))
+}
+
+/// Crops the image to the dimensions, keeping the focal point in frame if
+/// supplied, otherwise positioned by the gravity.
+fn crop_to(
+    raster_img: &mut raster::Image, width: i32, height: i32, gravity: Gravity,
+    focal_point: Option<FocalPoint>,
+) -> raster::error::RasterResult<()> {
+    match focal_point {
+        Some(f) => {
+            let offset_x = focal_offset(f.x, raster_img.width, width);
+            let offset_y = focal_offset(f.y, raster_img.height, height);
+
+            raster::editor::crop(
+                raster_img, width, height, raster::PositionMode::TopLeft,
+                offset_x, offset_y
+            )
+        }
+
+        None => raster::editor::crop(
+            raster_img, width, height, position_mode(gravity), 0, 0
+        ),
+    }
+}
+
+/// Cuts the rectangle out of the image.
+pub fn crop_image(raster_img: &mut raster::Image, rect: &CropRect)
+    -> Result<(),()> {
+    if !rect.is_within(raster_img.width as u16, raster_img.height as u16) {
+        error!("Crop rectangle {} is outside the image.", rect.key());
+        return Err(());
+    }
+
+    match raster::editor::crop(
+        raster_img,
+        rect.width as i32,
+        rect.height as i32,
+        raster::PositionMode::TopLeft,
+        rect.x as i32,
+        rect.y as i32
+    ) {
+        Ok(_) => Ok(()),
+        Err(_) => { error!("Error while cropping."); Err(()) }
+    }
+}
+
+/// Returns the raster position mode (anchor) of a gravity.
+pub fn position_mode(gravity: Gravity) -> raster::PositionMode {
+    match gravity {
+        Gravity::Center => raster::PositionMode::Center,
+        Gravity::North => raster::PositionMode::TopCenter,
+        Gravity::South => raster::PositionMode::BottomCenter,
+        Gravity::East => raster::PositionMode::CenterRight,
+        Gravity::West => raster::PositionMode::CenterLeft,
+        Gravity::NorthEast => raster::PositionMode::TopRight,
+        Gravity::NorthWest => raster::PositionMode::TopLeft,
+        Gravity::SouthEast => raster::PositionMode::BottomRight,
+        Gravity::SouthWest => raster::PositionMode::BottomLeft,
+    }
+}
+
+/// Resizes an image into the given dimensions according to the `fit` mode.
+///
+/// `gravity` selects the part of the image that is kept when the image gets
+/// cropped (`Fit::Cover` and `Fit::Crop`), a `focal_point` takes precedence
+/// over it.
+pub fn resize_image(
+    raster_img: &mut raster::Image, width: u16, height: u16, fit: Fit,
+    gravity: Gravity, focal_point: Option<FocalPoint>,
+) -> Result<(),()> {
+    let (width, height) = (width as i32, height as i32);
+
+    let resized = match fit {
+        Fit::Fit => raster::editor::resize(
+            raster_img, width, height, raster::ResizeMode::Fit
+        ),
+
+        Fit::Exact => raster::editor::resize(
+            raster_img, width, height, raster::ResizeMode::Exact
+        ),
+
+        Fit::Cover => {
+            // Scale to cover the dimensions, then crop the excess.
+            let scale = f64::max(
+                width as f64 / raster_img.width as f64,
+                height as f64 / raster_img.height as f64,
+            );
+
+            raster::editor::resize(
+                raster_img,
+                ((raster_img.width as f64 * scale).ceil() as i32).max(width),
+                ((raster_img.height as f64 * scale).ceil() as i32).max(height),
+                raster::ResizeMode::Exact
+            ).and_then(|_| crop_to(
+                raster_img, width, height, gravity, focal_point
+            ))
+        }
+
+        Fit::Crop => {
+            let crop_width = width.min(raster_img.width);
+            let crop_height = height.min(raster_img.height);
+
+            crop_to(raster_img, crop_width, crop_height, gravity, focal_point)
+        }
+    };
+
+    match resized {
+        Ok(_) => Ok(()),
+        Err(_) => { error!("Error while resizing."); Err(()) }
+    }
+}
+
+/// Returns the path of the directory the transformations of an image are
+/// cached in, relative to the rendition directory.
+pub fn transform_cache_path(image_path: &str) -> String {
+    format!("{}/{}", image_path, TRANSFORM_CACHE_DIR)
+}
+
+/// Generates the path where the transformed image is cached.
+///
+/// Watermarked transformations are cached per rendition (`watermarked_slug`),
+/// as the renditions of an image may have different watermarks.
+pub fn generate_transform_path(
+    rendition_dir: &str, image_path: &str, params: &TransformParams,
+    encoding: Encoding, watermarked_slug: Option<&str>,
+) -> String {
+    let watermark_suffix = match watermarked_slug {
+        Some(slug) => format!("_wm-{}", slug),
+        None => String::new(),
+    };
+
+    generate_dest_rendition_path(
+        rendition_dir,
+        format!(
+            "{}/{}{}{}",
+            transform_cache_path(image_path),
+            params.normalized(),
+            watermark_suffix,
+            encoding.extension(),
+        ).as_str()
+    )
+}
+
+/// Returns the dimensions of the transformed image, a missing width or height
+/// is calculated from the aspect ratio of the source image.
+///
+/// Fails if a calculated dimension doesn't fit into 16 bits.
+pub fn transform_dimensions(
+    params: &TransformParams, src_width: u32, src_height: u32,
+) -> Result<(u16, u16), ()> {
+    let (src_width, src_height) = (src_width.max(1) as u64, src_height.max(1) as u64);
+
+    let (width, height): (u64, u64) = match (params.width, params.height) {
+        (Some(w), Some(h)) => (w as u64, h as u64),
+        (Some(w), None) => (w as u64, ((w as u64 * src_height) / src_width).max(1)),
+        (None, Some(h)) => (((h as u64 * src_width) / src_height).max(1), h as u64),
+        (None, None) => (src_width, src_height),
+    };
+
+    match (u16::try_from(width), u16::try_from(height)) {
+        (Ok(w), Ok(h)) => Ok((w, h)),
+        _ => {
+            error!("Transformed dimensions {}x{} are too large.", width, height);
+            Err(())
+        }
+    }
+}
+
+/// Applies the transformation parameters to the source image and saves the
+/// result to `dest_path`.
+///
+/// A missing width or height is calculated from the aspect ratio of the
+/// source image. `output` holds the project's encoding defaults. The
+/// `focal_point` of the image is kept when the image gets cropped, as in its
+/// renditions.
+pub fn transform_and_save_image(
+    src_path: &str, dest_path: &str, params: &TransformParams,
+    encoding: Encoding, output: &OutputConfig, watermark: Option<&WatermarkImage>,
+    edits: &[EditOperation], focal_point: Option<FocalPoint>,
+) -> Result<(),()> {
+    let mut raster_img: raster::Image;
+
+    match open_edited(src_path, edits) {
+        Ok(r_img) => { raster_img = r_img; }
+        Err(_) => { return Err(()); }
+    }
+
+    let (width, height) = transform_dimensions(
+        params, raster_img.width as u32, raster_img.height as u32
+    )?;
+
+    resize_image(
+        &mut raster_img, width, height, params.fit, params.gravity, focal_point
+    )?;
+
+    if let Some(w) = watermark {
+        apply_watermark(&mut raster_img, w)?;
+    }
+
+    create_folder_tree(dest_path)?;
+
+    debug!("Saving transformed image to path: {}", dest_path);
+
+    let mut options = EncodeOptions::new(
+        encoding, params.quality.or(Some(output.quality))
+    );
+
+    options.progressive = output.progressive;
+
+    if !output.strip_metadata {
+        options.metadata_source = Some(String::from(src_path));
+    }
+
+    save_image(&raster_img, dest_path, &options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calculates_the_missing_dimension() {
+        let params = |width, height| TransformParams {
+            width, height, fit: Fit::Fit, gravity: Gravity::Center,
+            quality: None, encoding: None,
+        };
+
+        assert_eq!(transform_dimensions(&params(Some(200), None), 800, 600), Ok((200, 150)));
+        assert_eq!(transform_dimensions(&params(None, Some(300)), 800, 600), Ok((400, 300)));
+        assert_eq!(transform_dimensions(&params(Some(10), None), 4000, 10), Ok((10, 1)));
+        assert_eq!(transform_dimensions(&params(None, None), 800, 600), Ok((800, 600)));
+
+        // A wide source would make the height wrap around.
+        assert_eq!(transform_dimensions(&params(Some(60000), None), 10, 1000), Err(()));
+        assert_eq!(transform_dimensions(&params(None, Some(60000)), 1000, 10), Err(()));
+    }
+}
@@ -0,0 +1,178 @@
+//! Placeholder service
+//!
+//! Generates the low-quality placeholders (BlurHash, LQIP and dominant color)
+//! that the front-ends render while the actual rendition is being loaded.
+
+use std::collections::HashMap;
+
+use base64::{ Engine, engine::general_purpose::STANDARD };
+use actix_web::web::Data;
+use log::error;
+
+use crate::{
+    api::service::edit::open_edited,
+    model::{ image::Placeholder, edit::EditOperation },
+    repository::Repository,
+};
+
+/// Size of the (longer side of the) thumbnail the BlurHash and the dominant
+/// color are computed from.
+const SAMPLE_SIZE: usize = 32;
+
+/// Size of the (longer side of the) LQIP.
+const LQIP_SIZE: usize = 16;
+
+/// JPEG quality of the LQIP.
+const LQIP_QUALITY: u8 = 50;
+
+/// Number of BlurHash components along the longer side of the image.
+const BLURHASH_COMPONENTS: u32 = 4;
+
+/// Generates the placeholders of an image file, with the image's edits
+/// applied.
+pub fn generate_placeholder(path: &str, edits: &[EditOperation])
+    -> Result<Placeholder, ()> {
+    let raster_img = open_edited(path, edits)?;
+
+    let (width, height, sample) = downscale(&raster_img, SAMPLE_SIZE);
+
+    let (components_x, components_y) = if width >= height {
+        (BLURHASH_COMPONENTS, BLURHASH_COMPONENTS - 1)
+    } else {
+        (BLURHASH_COMPONENTS - 1, BLURHASH_COMPONENTS)
+    };
+
+    let blurhash = match blurhash::encode(
+        components_x, components_y, width as u32, height as u32, &sample
+    ) {
+        Ok(hash) => hash,
+        Err(e) => {
+            error!("Error while generating BlurHash of {}: {}", path, e);
+            return Err(());
+        }
+    };
+
+    let (lqip_width, lqip_height, lqip) = downscale(&raster_img, LQIP_SIZE);
+    let mut buffer: Vec<u8> = Vec::new();
+
+    // The alpha channel is ignored, the LQIP is only a rough preview.
+    if let Err(e) = jpeg_encoder::Encoder::new(&mut buffer, LQIP_QUALITY).encode(
+        &lqip, lqip_width as u16, lqip_height as u16,
+        jpeg_encoder::ColorType::Rgba
+    ) {
+        error!("Error while generating LQIP of {}: {}", path, e);
+        return Err(());
+    }
+
+    Ok(Placeholder {
+        blurhash,
+        lqip: format!("data:image/jpeg;base64,{}", STANDARD.encode(&buffer)),
+        dominant_color: dominant_color(&sample),
+    })
+}
+
+/// Generates the placeholders of an image and saves them.
+///
+/// Returns `None` if the placeholders could not be generated, failures while
+/// saving are only logged.
+pub fn save_placeholder(
+    repo: &Data<dyn Repository + Sync + Send>, image_id: u32, file_path: &str,
+    edits: &[EditOperation],
+) -> Option<Placeholder> {
+    let placeholder = generate_placeholder(file_path, edits).ok()?;
+
+    match repo.get_image_repo() {
+        Ok(mut img_repo) => {
+            if let Err(e) = img_repo.set_placeholder(image_id, &placeholder) {
+                error!(
+                    "Error while saving placeholder of image {}: {}", image_id, e
+                );
+            }
+        }
+
+        Err(e) => {
+            error!("Error while getting image repo: {}", e);
+        }
+    }
+
+    Some(placeholder)
+}
+
+/// Scales the image down (averaging the pixels) to fit in a `size` x `size`
+/// box.
+///
+/// Returns the width, height and the RGBA pixels of the scaled image.
+fn downscale(raster_img: &raster::Image, size: usize) -> (usize, usize, Vec<u8>) {
+    let src_width = raster_img.width.max(1) as usize;
+    let src_height = raster_img.height.max(1) as usize;
+
+    let (width, height) = if src_width >= src_height {
+        (size.min(src_width), (size * src_height / src_width).clamp(1, src_height))
+    } else {
+        ((size * src_width / src_height).clamp(1, src_width), size.min(src_height))
+    };
+
+    let mut pixels: Vec<u8> = Vec::with_capacity(width * height * 4);
+
+    for y in 0..height {
+        let (top, bottom) = (y * src_height / height, (y + 1) * src_height / height);
+
+        for x in 0..width {
+            let (left, right) = (x * src_width / width, (x + 1) * src_width / width);
+            let mut sum = [0u64; 4];
+
+            for sy in top..bottom {
+                for sx in left..right {
+                    let i = (sy * src_width + sx) * 4;
+
+                    for c in 0..4 {
+                        sum[c] += raster_img.bytes[i + c] as u64;
+                    }
+                }
+            }
+
+            let count = ((bottom - top) * (right - left)).max(1) as u64;
+
+            for c in sum {
+                pixels.push((c / count) as u8);
+            }
+        }
+    }
+
+    (width, height, pixels)
+}
+
+/// Returns the dominant color (`#rrggbb`) of the RGBA pixels.
+///
+/// The colors are grouped into buckets (4 bits per channel), the dominant
+/// color is the average color of the most populated bucket. Mostly
+/// transparent pixels are ignored.
+fn dominant_color(pixels: &[u8]) -> String {
+    let mut buckets: HashMap<u16, (u32, [u32; 3])> = HashMap::new();
+
+    for pixel in pixels.chunks_exact(4) {
+        if pixel[3] < 128 { continue; }
+
+        let key = ((pixel[0] as u16 >> 4) << 8)
+            | ((pixel[1] as u16 >> 4) << 4)
+            | (pixel[2] as u16 >> 4);
+
+        let bucket = buckets.entry(key).or_insert((0, [0; 3]));
+        bucket.0 += 1;
+
+        for c in 0..3 {
+            bucket.1[c] += pixel[c] as u32;
+        }
+    }
+
+    // Ties are broken by the bucket key, so the result is deterministic.
+    match buckets.iter().max_by_key(|(key, (count, _))| (*count, **key)) {
+        Some((_, (count, sum))) => format!(
+            "#{:02x}{:02x}{:02x}",
+            sum[0] / count, sum[1] / count, sum[2] / count
+        ),
+
+        // Fully transparent image.
+        None => String::from("#000000"),
+    }
+}
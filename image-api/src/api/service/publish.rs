@@ -0,0 +1,68 @@
+//! Publishing service
+//!
+//! Decides whether a resource can be served on the public image route without
+//! a signed URL.
+
+use actix_web::web::Data;
+use log::error;
+
+use crate::{
+    api::service::path::get_rendition_from_path_segments,
+    repository::Repository, server::db::DBError,
+    model::error::{ Error, ErrorType },
+};
+
+/// Returns `true` if the rendition represented by the path segments is
+/// public, i.e.: the rendition and its image are published and the image's
+/// project does not restrict its users.
+pub fn is_public<'a>(
+    repo: &Data<dyn Repository + Sync + Send>,
+    path_segments: &'a Vec<&str>
+) -> Result<bool, Error<'a>> {
+    let rendition = get_rendition_from_path_segments(repo, path_segments)?;
+
+    if !rendition.is_published {
+        return Ok(false);
+    }
+
+    let image;
+
+    match repo.get_image_repo() {
+        Ok(mut img_repo) => {
+            match img_repo.get(rendition.image_id) {
+                Ok(img) => { image = img; }
+                Err(e) => { return Err(db_to_error(e)); }
+            }
+        }
+
+        Err(e) => {
+            error!("Error while getting image repo: {}", e);
+            return Err(db_to_error(e));
+        }
+    }
+
+    if !image.is_published {
+        return Ok(false);
+    }
+
+    match repo.get_project_repo() {
+        Ok(mut proj_repo) => {
+            match proj_repo.get(image.project_id) {
+                Ok(project) => Ok(!project.restrict_users),
+                Err(e) => Err(db_to_error(e)),
+            }
+        }
+
+        Err(e) => {
+            error!("Error while getting project repo: {}", e);
+            Err(db_to_error(e))
+        }
+    }
+}
+
+pub(crate) fn db_to_error<'a>(e: DBError) -> Error<'a> {
+    match e {
+        DBError::NotFound => Error::new(ErrorType::NotFound, "NOT FOUND"),
+        _ => Error::new(ErrorType::InternalError, "Some error occured"),
+    }
+}
@@ -1,19 +1,27 @@
 //! Delete service
 
-use std::fs::{ remove_file, remove_dir_all };
+use std::{ fs::{ remove_file, remove_dir_all, read_dir }, path::Path };
 
 use actix_web::web::Data;
 use log::{ debug, error, info };
 
 use crate::{
-    api::service::path::get_image_path, repository::Repository,
-    model::{ image::Image, rendition::Rendition }, server::db::DBError,
+    api::service::{
+        path::{ get_image_path, transform_cache_path }, negotiate::variant_path,
+        content::{ original_path, lock_content },
+    },
+    repository::Repository,
+    model::{
+        image::Image, rendition::Rendition, encoding::Encoding, transform::Fit,
+    },
+    server::db::DBError,
+    storage::{ Storages, version_path },
 };
 
 /// Removes images in `image_ids`
 pub fn remove_images(
     repo: &Data<dyn Repository + Sync + Send>, image_ids: &Vec<u32>, rendition_path: String,
-    upload_path: String,
+    upload_path: String, storage: &Storages,
 ) -> Result<String, String> {
     let mut image: Option<Image>;
     let mut error: bool = false;
@@ -89,36 +97,90 @@ pub fn remove_images(
             Ok (_message) => {
                 if let Some(img) = image {
                     if let Ok(image_path) = get_image_path(&repo, &img) {
-                        // Delete the image file from upload directory
-                        match remove_file(format!("{}/{}{}",
-                            upload_path, image_id, img.encoding.extension()
-                        )) {
-                            Ok (_) => {}
-                            Err (e) => {
-                                error!("Error while deleting image file for \
-                                    image id: {}: {}", image_id, e);
+                        let image_file_path = original_path(&upload_path, &img);
+
+                        // Identical uploads share the original file, which is
+                        // kept until its last image is removed. The lock keeps
+                        // uploads from reusing the file while it's removed.
+                        let content_lock = img.content_hash.as_ref()
+                            .map(|content_hash| lock_content(repo, content_hash));
+
+                        let shared = match (&img.content_hash, &content_lock) {
+                            (Some(content_hash), Some(Ok(_))) => {
+                                match img_repo.count_content_references(content_hash) {
+                                    Ok(refs) => refs > 0,
+                                    Err(_) => {
+                                        error = true;
+                                        true
+                                    }
+                                }
+                            }
+
+                            (Some(_), _) => {
                                 error = true;
+                                true
                             }
+
+                            (None, _) => false,
+                        };
+
+                        // Delete the image file from the storage and the
+                        // upload directory
+                        if shared {
+                            debug!(
+                                "Keeping image file shared with other images: {}",
+                                image_file_path
+                            );
+                        } else if let Err(e) = storage.remove_original(&image_file_path) {
+                            error!("Error while deleting stored image file for \
+                                image id: {}: {}", image_id, e);
+                            error = true;
                         }
 
-                        // Delete the image directory
-                        match remove_dir_all(
-                            format!("{}/{}", rendition_path, image_path
-                        )) {
-                            Ok(_) => {
-                                info!(
-                                    "Deleted image directory: {}",
-                                    image_path
-                                );
+                        if !shared && Path::new(&image_file_path).exists() {
+                            match remove_file(&image_file_path) {
+                                Ok (_) => {}
+                                Err (e) => {
+                                    error!("Error while deleting image file for \
+                                        image id: {}: {}", image_id, e);
+                                    error = true;
+                                }
                             }
+                        }
 
-                            Err(e) => {
-                                error!(
-                                    "Error deleting image directory ({}): {}",
-                                    image_path, e
-                                );
+                        drop(content_lock);
 
-                                error = true;
+                        // Delete the image directory
+                        if let Err(e) = storage.remove_renditions(
+                            &format!("{}/", image_path)
+                        ) {
+                            error!(
+                                "Error deleting stored renditions ({}): {}",
+                                image_path, e
+                            );
+
+                            error = true;
+                        }
+
+                        let image_dir = format!("{}/{}", rendition_path, image_path);
+
+                        if Path::new(&image_dir).exists() {
+                            match remove_dir_all(&image_dir) {
+                                Ok(_) => {
+                                    info!(
+                                        "Deleted image directory: {}",
+                                        image_path
+                                    );
+                                }
+
+                                Err(e) => {
+                                    error!(
+                                        "Error deleting image directory ({}): {}",
+                                        image_path, e
+                                    );
+
+                                    error = true;
+                                }
                             }
                         }
                     }
@@ -148,7 +210,7 @@ pub fn remove_images(
 /// - `folder_ids`: IDs of folders to be deleted
 pub fn remove_folders(
     repo: Data<dyn Repository + Sync + Send>, folder_ids: &mut Vec<u32>,
-    rendition_path: String, upload_path: String,
+    rendition_path: String, upload_path: String, storage: &Storages,
 ) -> Result<String, String> {
     let mut error: bool = false;
 
@@ -199,6 +261,7 @@ pub fn remove_folders(
                             &image_ids,
                             rendition_path.clone(),
                             upload_path.clone(),
+                            storage,
                         ) {
                             Ok (_) => {}
                             Err (_) => { error = true; }
@@ -220,7 +283,8 @@ pub fn remove_folders(
 }
 
 pub fn remove_rendition_file(
-    ren_dir: &String, image_path: &String, rendition: &Rendition
+    ren_dir: &String, image_path: &String, rendition: &Rendition,
+    storage: &Storages,
 ) -> bool {
     let file_name: String = format!(
         "{}/{}/{}{}",
@@ -232,6 +296,52 @@ pub fn remove_rendition_file(
 
     debug!("Deleting rendition (id: {}) file: {}", rendition.id, file_name);
 
+    // Negotiated variants (see `negotiate::cache_variant`) and the version of
+    // the working copy may not exist.
+    for encoding in [ Encoding::WEBP, Encoding::AVIF ] {
+        let _ = remove_file(variant_path(&file_name, encoding));
+    }
+
+    let _ = remove_file(version_path(&file_name));
+
+    // Density variants (`slug@2x.jpg`, see `density`) and their negotiated
+    // variants.
+    if let Ok(entries) = read_dir(format!("{}/{}", ren_dir, image_path)) {
+        let prefix = format!("{}@", rendition.slug);
+
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                let _ = remove_file(entry.path());
+            }
+        }
+    }
+
+    // The rendition, its negotiated and its density variants in the storage.
+    let file = format!("{}{}", rendition.slug, rendition.encoding.extension());
+    let density_prefix = format!("{}@", rendition.slug);
+
+    if let Err(e) = storage.remove_renditions_matching(
+        &format!("{}/{}", image_path, rendition.slug),
+        |key| {
+            let key_name = key.rsplit('/').next().unwrap_or(key);
+
+            key_name == file
+                || key_name.starts_with(&format!("{}.", file))
+                || key_name.starts_with(&density_prefix)
+        },
+    ) {
+        error!(
+            "Error while deleting stored rendition files (id: {}): {}",
+            rendition.id, e
+        );
+
+        return false;
+    }
+
+    if !Path::new(&file_name).exists() {
+        return true;
+    }
+
     match remove_file(&file_name) {
         Ok(_) => true,
         Err(e) => {
@@ -245,6 +355,73 @@ pub fn remove_rendition_file(
     }
 }
 
+/// Removes the cached files of the image's renditions that crop the image
+/// (`cover` and `crop` fit modes) and its cached transformations, e.g. after
+/// the focal point was changed.
+///
+/// The files are re-generated on the next request.
+pub fn remove_crop_rendition_files(
+    repo: &Data<dyn Repository + Sync + Send>, ren_dir: &String, image: &Image,
+    storage: &Storages,
+) -> Result<(), String> {
+    let renditions: Vec<Rendition>;
+    let image_path: String;
+
+    match repo.get_rendition_repo() {
+        Ok(mut ren_repo) => {
+            match ren_repo.get_all_from_image(image.id) {
+                Ok(rens) => { renditions = rens; }
+                Err(DBError::NotFound) => { renditions = vec![]; }
+                Err(e) => {
+                    error!("Error while getting renditions: {}", e);
+                    return Err(String::from("Error while getting renditions"));
+                }
+            }
+        }
+
+        Err(e) => {
+            error!("Error while getting rendition repo: {}", e);
+            return Err(String::from("Error while getting rendition repo"));
+        }
+    }
+
+    match get_image_path(repo, image) {
+        Ok(i_path) => { image_path = i_path; }
+        Err(_) => { return Err(String::from("Error while getting image path")); }
+    }
+
+    let mut error = false;
+
+    for rendition in renditions.iter()
+        .filter(|r| r.fit == Fit::Cover || r.fit == Fit::Crop) {
+        // The file may be stored without a local copy.
+        if !remove_rendition_file(ren_dir, &image_path, rendition, storage) {
+            error = true;
+        }
+    }
+
+    let transform_path = transform_cache_path(&image_path);
+    let transform_dir = format!("{}/{}", ren_dir, transform_path);
+
+    if let Err(e) = storage.remove_renditions(&format!("{}/", transform_path)) {
+        error!("Error while removing stored transformations {}: {}", transform_path, e);
+        error = true;
+    }
+
+    if Path::new(&transform_dir).exists() {
+        if let Err(e) = remove_dir_all(&transform_dir) {
+            error!("Error while removing transformations {}: {}", transform_dir, e);
+            error = true;
+        }
+    }
+
+    if error {
+        Err(String::from("Some rendition files could not be removed"))
+    } else {
+        Ok(())
+    }
+}
+
 fn get_subfolders(repo: &Data<dyn Repository + Sync + Send>, folder_id: u32) -> Vec<u32> {
     let mut f_ids: Vec<u32> = vec![];
 
@@ -265,3 +442,58 @@ fn get_subfolders(repo: &Data<dyn Repository + Sync + Send>, folder_id: u32) ->
     return f_ids;
 }
 
+
+/// Removes all the cached files of an image's renditions (including the
+/// transformations and density variants), e.g. after the image was edited.
+///
+/// The files are re-generated on the next request.
+pub fn remove_rendition_cache(
+    repo: &Data<dyn Repository + Sync + Send>, ren_dir: &String, image: &Image,
+    storage: &Storages,
+) -> Result<(), String> {
+    let image_path: String;
+
+    match get_image_path(repo, image) {
+        Ok(i_path) => { image_path = i_path; }
+        Err(_) => { return Err(String::from("Error while getting image path")); }
+    }
+
+    let cache_dir = format!("{}/{}", ren_dir, image_path);
+
+    debug!("Removing rendition cache: {}", cache_dir);
+
+    let density_prefix = match image_path.rfind('/') {
+        Some(i) => format!("{}{}@", &image_path[..=i], image.slug),
+        None => format!("{}@", image.slug),
+    };
+
+    for prefix in [ format!("{}/", image_path), density_prefix ] {
+        if let Err(e) = storage.remove_renditions(&prefix) {
+            error!("Error while removing stored renditions {}: {}", prefix, e);
+            return Err(String::from("Error while removing rendition cache"));
+        }
+    }
+
+    if Path::new(&cache_dir).exists() {
+        if let Err(e) = remove_dir_all(&cache_dir) {
+            error!("Error while removing rendition cache {}: {}", cache_dir, e);
+            return Err(String::from("Error while removing rendition cache"));
+        }
+    }
+
+    // Density variants of the default rendition (`image@2x/`) are siblings of
+    // the image's directory.
+    if let Some(parent) = Path::new(&cache_dir).parent() {
+        if let Ok(entries) = read_dir(parent) {
+            let prefix = format!("{}@", image.slug);
+
+            for entry in entries.flatten() {
+                if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                    let _ = remove_dir_all(entry.path());
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
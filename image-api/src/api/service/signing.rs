@@ -0,0 +1,229 @@
+//! URL signing service
+//!
+//! Signs and verifies messages (usually normalized URLs) with HMAC-SHA256.
+
+use chrono::Utc;
+use hmac::{ Hmac, Mac };
+use qstring::QString;
+use sha2::Sha256;
+use log::error;
+
+use crate::model::transform::TransformParams;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Maximum validity of a signed URL (30 days).
+pub const MAX_EXPIRES_IN: i64 = 30 * 24 * 60 * 60;
+
+/// Result of checking the signature of an image URL.
+#[derive(PartialEq)]
+pub enum SignatureStatus {
+    /// The URL is not signed.
+    Absent,
+    Valid,
+    Invalid,
+
+    /// The signature is valid but the URL has expired.
+    Expired,
+}
+
+/// Returns the message that is signed for an image URL.
+///
+/// ### Parameters
+/// - `path`: The image path without leading and trailing slashes.
+/// - `transform`: Signed query parameters (`signed_params`), if any.
+/// - `expiry`: Expiry timestamp (seconds since epoch), if any.
+pub fn url_message(path: &str, transform: Option<&str>, expiry: Option<i64>)
+    -> String {
+    let mut message = format!("{}?{}", path, transform.unwrap_or(""));
+
+    if let Some(exp) = expiry {
+        message.push_str(format!("&exp={}", exp).as_str());
+    }
+
+    message
+}
+
+/// Returns the query parameters of an image URL that are signed: the
+/// normalized transformation parameters and the requested density (`dpr`),
+/// as every parameter that changes the served image must be covered by the
+/// signature.
+pub fn signed_params(params: Option<&TransformParams>, query_string: &str)
+    -> Option<String> {
+    let mut signed: Vec<String> = vec![];
+
+    if let Some(p) = params {
+        signed.push(p.normalized());
+    }
+
+    if let Some(dpr) = QString::from(query_string).get("dpr") {
+        match dpr.parse::<f32>() {
+            Ok(d) => signed.push(format!("dpr={}", d)),
+            Err(_) => signed.push(format!("dpr={}", dpr)),
+        }
+    }
+
+    if signed.is_empty() { None } else { Some(signed.join("&")) }
+}
+
+/// Checks the `sig` and `exp` parameters of an image URL's query string.
+///
+/// Signatures grant access to unpublished images, so they must expire: a
+/// signature without `exp`, or expiring later than `MAX_EXPIRES_IN` from now,
+/// is invalid.
+pub fn verify_url(
+    secret: &str, path: &str, transform: Option<&str>, query_string: &str
+) -> SignatureStatus {
+    let qs = QString::from(query_string);
+    let signature: &str;
+    let expiry: i64;
+
+    match qs.get("sig") {
+        Some(sig) => { signature = sig; }
+        None => { return SignatureStatus::Absent; }
+    }
+
+    match qs.get("exp").map(|exp_str| exp_str.parse::<i64>()) {
+        Some(Ok(exp)) => { expiry = exp; }
+        _ => { return SignatureStatus::Invalid; }
+    }
+
+    if !verify(secret, &url_message(path, transform, Some(expiry)), signature) {
+        return SignatureStatus::Invalid;
+    }
+
+    let now = Utc::now().timestamp();
+
+    if expiry < now {
+        return SignatureStatus::Expired;
+    }
+
+    if expiry > now + MAX_EXPIRES_IN {
+        return SignatureStatus::Invalid;
+    }
+
+    SignatureStatus::Valid
+}
+
+/// Returns the hex encoded HMAC-SHA256 signature of `message`.
+pub fn sign(secret: &str, message: &str) -> String {
+    match HmacSha256::new_from_slice(secret.as_bytes()) {
+        Ok(mut mac) => {
+            mac.update(message.as_bytes());
+
+            hex::encode(mac.finalize().into_bytes())
+        }
+
+        Err(e) => {
+            error!("Error while creating signature: {}", e);
+
+            String::new()
+        }
+    }
+}
+
+/// Returns `true` if `signature` is a valid signature of `message`.
+///
+/// The comparison is done in constant time.
+pub fn verify(secret: &str, message: &str, signature: &str) -> bool {
+    let signature_bytes: Vec<u8>;
+
+    match hex::decode(signature) {
+        Ok(bytes) => { signature_bytes = bytes; }
+        Err(_) => { return false; }
+    }
+
+    match HmacSha256::new_from_slice(secret.as_bytes()) {
+        Ok(mut mac) => {
+            mac.update(message.as_bytes());
+
+            mac.verify_slice(&signature_bytes).is_ok()
+        }
+
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "secret";
+    const PATH: &str = "project/folder/image/thumb.jpg";
+
+    /// Query string of a URL signed for `expiry`.
+    fn signed_query(transform: Option<&str>, expiry: i64) -> String {
+        let sig = sign(SECRET, &url_message(PATH, transform, Some(expiry)));
+
+        format!("exp={}&sig={}", expiry, sig)
+    }
+
+    fn status(transform: Option<&str>, query_string: &str) -> SignatureStatus {
+        verify_url(SECRET, PATH, transform, query_string)
+    }
+
+    #[test]
+    fn accepts_valid_signatures() {
+        let expiry = Utc::now().timestamp() + 3600;
+
+        assert!(status(None, &signed_query(None, expiry)) == SignatureStatus::Valid);
+        assert!(
+            status(Some("w=100"), &signed_query(Some("w=100"), expiry))
+                == SignatureStatus::Valid
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_urls() {
+        let expiry = Utc::now().timestamp() + 3600;
+        let query = signed_query(None, expiry);
+
+        assert!(status(Some("w=100"), &query) == SignatureStatus::Invalid);
+        assert!(verify_url("other", PATH, None, &query) == SignatureStatus::Invalid);
+        assert!(
+            status(None, &query.replace(&expiry.to_string(), &(expiry + 1).to_string()))
+                == SignatureStatus::Invalid
+        );
+        assert!(status(None, "exp=1&sig=zz") == SignatureStatus::Invalid);
+    }
+
+    #[test]
+    fn signs_the_density() {
+        let expiry = Utc::now().timestamp() + 3600;
+        let params = TransformParams::from_query("w=100").ok().unwrap();
+        let dpr2 = signed_params(None, "dpr=2&exp=1&sig=ab");
+        let query = signed_query(dpr2.as_deref(), expiry);
+
+        assert!(dpr2.as_deref() == Some("dpr=2"));
+        assert!(signed_params(None, "dpr=2.0").as_deref() == Some("dpr=2"));
+        assert!(signed_params(None, "exp=1&sig=ab").is_none());
+        assert!(
+            signed_params(params.as_ref(), "w=100&dpr=3").as_deref()
+                == Some("w100_h0_fit&dpr=3")
+        );
+
+        assert!(status(dpr2.as_deref(), &query) == SignatureStatus::Valid);
+        assert!(
+            status(signed_params(None, "dpr=3").as_deref(), &query)
+                == SignatureStatus::Invalid
+        );
+        assert!(status(None, &query) == SignatureStatus::Invalid);
+    }
+
+    #[test]
+    fn requires_a_bounded_expiry() {
+        let sig = sign(SECRET, &url_message(PATH, None, None));
+        let too_late = Utc::now().timestamp() + MAX_EXPIRES_IN + 3600;
+
+        assert!(status(None, &format!("sig={}", sig)) == SignatureStatus::Invalid);
+        assert!(status(None, &signed_query(None, too_late)) == SignatureStatus::Invalid);
+    }
+
+    #[test]
+    fn reports_expired_and_absent_signatures() {
+        let expiry = Utc::now().timestamp() - 1;
+
+        assert!(status(None, &signed_query(None, expiry)) == SignatureStatus::Expired);
+        assert!(status(None, "") == SignatureStatus::Absent);
+    }
+}
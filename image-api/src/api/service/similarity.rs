@@ -0,0 +1,351 @@
+//! Similarity service
+//!
+//! Detects near-duplicate images (resized or recompressed copies of the same
+//! photo) by the Hamming distance of their perceptual hashes (dHash).
+
+use std::collections::HashMap;
+
+use actix_web::{ rt, web::{ block, Data } };
+use log::{ error, info };
+
+use crate::{
+    api::service::{ orientation::open_upright, content::original_path },
+    repository::Repository, server::config::ServerConfig, storage::Storages,
+};
+
+/// Default highest Hamming distance (out of 64 bits) between the hashes of
+/// near-duplicates.
+pub const DEFAULT_THRESHOLD: u32 = 10;
+
+/// Highest accepted threshold, unrelated images match above it.
+pub const MAX_THRESHOLD: u32 = 32;
+
+/// Width and height of the grayscale thumbnail the hash is computed from,
+/// each row gives `HASH_WIDTH` bits.
+const HASH_WIDTH: usize = 8;
+const HASH_HEIGHT: usize = 8;
+
+/// Returns the difference hash (dHash) of the upright original image: each
+/// bit tells whether a pixel of a 9x8 grayscale thumbnail is brighter than
+/// its right neighbour.
+pub fn perceptual_hash(path: &str) -> Result<u64, ()> {
+    let raster_img = open_upright(path)?;
+    let thumbnail = grayscale_thumbnail(&raster_img, HASH_WIDTH + 1, HASH_HEIGHT);
+
+    let mut hash: u64 = 0;
+
+    for y in 0..HASH_HEIGHT {
+        for x in 0..HASH_WIDTH {
+            let i = y * (HASH_WIDTH + 1) + x;
+
+            hash <<= 1;
+
+            if thumbnail[i] > thumbnail[i + 1] {
+                hash |= 1;
+            }
+        }
+    }
+
+    Ok(hash)
+}
+
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Computes the perceptual hash of an image and saves it.
+///
+/// Returns `None` if the hash could not be computed, failures while saving
+/// are only logged.
+pub fn save_perceptual_hash(
+    repo: &Data<dyn Repository + Sync + Send>, image_id: u32, file_path: &str,
+) -> Option<u64> {
+    let hash = perceptual_hash(file_path).ok()?;
+
+    match repo.get_image_repo() {
+        Ok(mut img_repo) => {
+            if let Err(e) = img_repo.set_perceptual_hash(image_id, hash) {
+                error!(
+                    "Error while saving perceptual hash of image {}: {}",
+                    image_id, e
+                );
+            }
+        }
+
+        Err(e) => {
+            error!("Error while getting image repo: {}", e);
+        }
+    }
+
+    Some(hash)
+}
+
+/// Computes the missing perceptual hashes of the images added before the
+/// hashes were introduced. Images whose hash can't be computed are logged
+/// and retried on the next start.
+pub fn backfill_perceptual_hashes(
+    repo: &Data<dyn Repository + Sync + Send>, conf: &ServerConfig,
+    storage: &Storages,
+) {
+    let images = match repo.get_image_repo().map(|mut img_repo| {
+        let missing: Vec<u32> = img_repo.get_perceptual_hashes(None)?
+            .into_iter()
+            .filter(|(_, hash)| hash.is_none())
+            .map(|(id, _)| id)
+            .collect();
+
+        img_repo.get_all_by_ids(&missing)
+    }) {
+        Ok(Ok(images)) => images,
+        Ok(Err(_)) => { return; }
+        Err(e) => {
+            error!("Error while getting image repo: {}", e);
+            return;
+        }
+    };
+
+    if images.is_empty() { return; }
+
+    info!("Computing the perceptual hashes of {} image(s)", images.len());
+
+    for image in images {
+        let file_path = original_path(&conf.upload_dir, &image);
+
+        if storage.fetch_original(&file_path).is_err()
+            || save_perceptual_hash(repo, image.id, &file_path).is_none() {
+            error!("Could not compute the perceptual hash of image {}", image.id);
+        }
+    }
+}
+
+/// Starts computing the missing perceptual hashes in the background.
+pub fn start_hash_backfill(
+    repo: Data<dyn Repository + Sync + Send>, storage: Data<Storages>,
+    conf: ServerConfig,
+) {
+    rt::spawn(async move {
+        let _ = block(move || backfill_perceptual_hashes(&repo, &conf, &storage))
+            .await;
+    });
+}
+
+/// Returns the IDs of the images whose hashes are within `threshold` of
+/// `hash`, `image_id` itself excluded.
+pub fn find_similar(
+    hashes: &[(u32, u64)], image_id: u32, hash: u64, threshold: u32,
+) -> Vec<u32> {
+    hashes.iter()
+        .filter(|(id, h)| *id != image_id && hamming_distance(*h, hash) <= threshold)
+        .map(|(id, _)| *id)
+        .collect()
+}
+
+/// Groups the images into clusters of near-duplicates. Images are in the
+/// same cluster if they are linked by a chain of hashes within `threshold`
+/// of each other, images without near-duplicates are left out.
+///
+/// Only the hashes that share a block of bits are compared: split into
+/// `threshold + 1` blocks, hashes within `threshold` of each other are equal
+/// in one of the blocks at least.
+///
+/// Returns the image IDs of each cluster, along with the largest distance
+/// between two of its hashes.
+pub fn cluster(hashes: &[(u32, u64)], threshold: u32) -> Vec<(Vec<u32>, u32)> {
+    let mut parents: Vec<usize> = (0..hashes.len()).collect();
+
+    for mask in block_masks(threshold) {
+        let mut buckets: HashMap<u64, Vec<usize>> = HashMap::new();
+
+        for (i, (_, hash)) in hashes.iter().enumerate() {
+            buckets.entry(hash & mask).or_default().push(i);
+        }
+
+        for members in buckets.values() {
+            for (n, &i) in members.iter().enumerate() {
+                for &j in members.iter().skip(n + 1) {
+                    if hamming_distance(hashes[i].1, hashes[j].1) > threshold {
+                        continue;
+                    }
+
+                    let (root_i, root_j) = (find(&mut parents, i), find(&mut parents, j));
+
+                    if root_i != root_j {
+                        parents[root_j] = root_i;
+                    }
+                }
+            }
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+
+    for i in 0..hashes.len() {
+        let root = find(&mut parents, i);
+        groups.entry(root).or_default().push(i);
+    }
+
+    let mut clusters: Vec<(Vec<u32>, u32)> = groups.into_values()
+        .filter(|members| members.len() > 1)
+        .map(|members| {
+            let mut max_distance = 0;
+
+            for (n, &i) in members.iter().enumerate() {
+                for &j in members.iter().skip(n + 1) {
+                    max_distance = max_distance.max(
+                        hamming_distance(hashes[i].1, hashes[j].1)
+                    );
+                }
+            }
+
+            let mut ids: Vec<u32> = members.iter().map(|&i| hashes[i].0).collect();
+            ids.sort_unstable();
+
+            (ids, max_distance)
+        })
+        .collect();
+
+    // Largest clusters first, the lowest image ID breaks ties.
+    clusters.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then(a.0[0].cmp(&b.0[0])));
+
+    clusters
+}
+
+/// Returns the masks of the `threshold + 1` blocks the hash bits are split
+/// into, a single empty mask (all the hashes compared) if a block can't be
+/// made for each differing bit.
+fn block_masks(threshold: u32) -> Vec<u64> {
+    if threshold >= u64::BITS {
+        return vec![ 0 ];
+    }
+
+    let count = threshold + 1;
+
+    (0..count)
+        .map(|b| {
+            let (start, end) = (b * u64::BITS / count, (b + 1) * u64::BITS / count);
+
+            (u64::MAX >> (u64::BITS - (end - start))) << start
+        })
+        .collect()
+}
+
+/// Returns the root of the set `i` belongs to, compressing the path.
+fn find(parents: &mut Vec<usize>, i: usize) -> usize {
+    let mut root = i;
+
+    while parents[root] != root {
+        root = parents[root];
+    }
+
+    let mut node = i;
+
+    while parents[node] != root {
+        let next = parents[node];
+        parents[node] = root;
+        node = next;
+    }
+
+    root
+}
+
+/// Scales the image down to `width` x `height` (averaging the pixels) and
+/// returns the luma of the pixels.
+fn grayscale_thumbnail(raster_img: &raster::Image, width: usize, height: usize)
+    -> Vec<f32> {
+    let src_width = raster_img.width.max(1) as usize;
+    let src_height = raster_img.height.max(1) as usize;
+
+    let mut pixels: Vec<f32> = Vec::with_capacity(width * height);
+
+    for y in 0..height {
+        let top = y * src_height / height;
+        let bottom = ((y + 1) * src_height / height).max(top + 1);
+
+        for x in 0..width {
+            let left = x * src_width / width;
+            let right = ((x + 1) * src_width / width).max(left + 1);
+            let mut sum = 0.0;
+
+            for sy in top..bottom {
+                for sx in left..right {
+                    let i = (sy * src_width + sx) * 4;
+
+                    sum += 0.299 * raster_img.bytes[i] as f32
+                        + 0.587 * raster_img.bytes[i + 1] as f32
+                        + 0.114 * raster_img.bytes[i + 2] as f32;
+                }
+            }
+
+            pixels.push(sum / ((bottom - top) * (right - left)) as f32);
+        }
+    }
+
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clusters_chains_of_near_duplicates() {
+        let hashes = [
+            (4, u64::MAX),
+            (1, 0),
+            (6, 0xFFFF_0000_0000),
+            (2, 0b1111),
+            (5, u64::MAX ^ 1),
+            (3, 0xFF),
+        ];
+
+        // 1 and 3 are 8 bits apart, but both within 4 bits of 2.
+        assert_eq!(cluster(&hashes, 5), vec![ (vec![ 1, 2, 3 ], 8), (vec![ 4, 5 ], 1) ]);
+        assert_eq!(cluster(&hashes, 1), vec![ (vec![ 4, 5 ], 1) ]);
+        assert!(cluster(&hashes, 0).is_empty());
+        assert!(cluster(&[], 5).is_empty());
+    }
+
+    #[test]
+    fn finds_the_same_clusters_as_comparing_every_pair() {
+        // Pseudo-random hashes, with near-duplicates of some of them.
+        let mut seed: u64 = 0x2545F4914F6CDD1D;
+        let mut hashes: Vec<(u32, u64)> = vec![];
+
+        for id in 0..300 {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+
+            let hash = match id % 3 {
+                0 => seed,
+                _ => hashes[id as usize - 1].1 ^ (seed >> 40) & (seed >> 20),
+            };
+
+            hashes.push((id, hash));
+        }
+
+        for threshold in [ 0, 3, 10, 32, 64 ] {
+            let mut pairs: Vec<(u32, u32)> = vec![];
+
+            for (n, (a, hash_a)) in hashes.iter().enumerate() {
+                for (b, hash_b) in hashes.iter().skip(n + 1) {
+                    if hamming_distance(*hash_a, *hash_b) <= threshold {
+                        pairs.push((*a, *b));
+                    }
+                }
+            }
+
+            let clusters = cluster(&hashes, threshold);
+            let cluster_of = |id: u32| clusters.iter().position(|(ids, _)| ids.contains(&id));
+
+            for (a, b) in pairs.iter() {
+                assert!(cluster_of(*a).is_some() && cluster_of(*a) == cluster_of(*b));
+            }
+
+            let clustered: usize = clusters.iter().map(|(ids, _)| ids.len()).sum();
+            let paired: std::collections::HashSet<u32> = pairs.iter()
+                .flat_map(|(a, b)| [ *a, *b ])
+                .collect();
+
+            assert_eq!(clustered, paired.len(), "threshold {}", threshold);
+        }
+    }
+}
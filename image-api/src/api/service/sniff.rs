@@ -0,0 +1,714 @@
+//! Upload validation service
+//!
+//! Uploads are identified by their magic bytes, never by their file name or
+//! the type the client claims. Only images the pipeline can decode are
+//! accepted, files that are also something else (polyglots, e.g. a JPEG with
+//! a ZIP archive appended) are rejected, as they could be served as images
+//! and then opened by another program.
+//!
+//! Accepted uploads are kept as `temp/{upload_id}{ext}`, with the extension
+//! of the detected encoding, until `add_image` picks them up.
+
+use std::{
+    fmt,
+    fs::{ File, remove_file, rename },
+    io::{ BufReader, Read, Seek, SeekFrom },
+    path::Path,
+};
+
+use actix_web::http::StatusCode;
+use image::{ ImageFormat, io::Reader as ImageReader };
+use serde::Serialize;
+use uuid::Uuid;
+use log::{ debug, error };
+
+use crate::{ model::encoding::Encoding, server::config::UploadLimits };
+
+pub const UPLOAD_DIR: &str = "temp";
+
+/// Encodings of the images that can be uploaded (the ones the pipeline
+/// decodes).
+pub const SUPPORTED_ENCODINGS: [Encoding; 4] = [
+    Encoding::JPG, Encoding::PNG, Encoding::GIF, Encoding::WEBP,
+];
+
+/// Number of leading bytes the encoding is detected from.
+const HEADER_LEN: usize = 1024;
+
+/// ZIP readers look for the end of the central directory in the last
+/// 64 KiB (plus the record itself) of a file.
+const ZIP_TRAILER_LEN: u64 = 65536 + 22;
+
+/// Markup that browsers or script interpreters would run, looked for in the
+/// leading bytes and the metadata of images (case insensitive).
+const SCRIPT_MARKERS: [&str; 4] = [ "<?php", "<script", "<html", "<iframe" ];
+
+/// Number of leading bytes of a metadata segment searched for markup.
+const MAX_SEGMENT_LEN: u64 = 1024 * 1024;
+
+/// A validated upload.
+#[derive(Clone, Copy)]
+pub struct UploadedFile {
+    pub encoding: Encoding,
+    /// Width and height as stored, before the EXIF orientation is applied.
+    pub width: u32,
+    pub height: u32,
+    /// Size in bytes.
+    pub size: u64,
+}
+
+pub enum UploadRejection {
+    /// The file is larger than the maximum size (in bytes).
+    TooLarge(u64),
+    /// The image has more pixels than the maximum.
+    TooManyPixels(u64),
+    /// The image is wider or taller than the maximum (in pixels).
+    TooWide(u64),
+    /// The image's encoding isn't among the allowed ones.
+    NotAllowed(Encoding, Vec<Encoding>),
+    /// The file is not an image, or an image of an unsupported type.
+    Unsupported(String),
+    /// The file is an image of a supported type, but can't be accepted.
+    Invalid(String),
+    IOError,
+}
+
+/// Body of the responses rejecting an upload.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadError {
+    /// Identifies the reason of the rejection (e.g. `max-size`).
+    pub code: &'static str,
+    pub message: String,
+    /// The limit the upload exceeds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_encodings: Option<Vec<Encoding>>,
+}
+
+impl UploadRejection {
+    /// Status of the response rejecting the upload.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            UploadRejection::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            UploadRejection::TooManyPixels(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            UploadRejection::TooWide(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            UploadRejection::NotAllowed(_, _) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            UploadRejection::Unsupported(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            UploadRejection::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            UploadRejection::IOError => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn to_error(&self) -> UploadError {
+        let (code, limit, allowed_encodings) = match self {
+            UploadRejection::TooLarge(max_size) => ("max-size", Some(*max_size), None),
+            UploadRejection::TooManyPixels(max_pixels) => {
+                ("max-pixels", Some(*max_pixels), None)
+            }
+            UploadRejection::TooWide(max_dimension) => {
+                ("max-dimension", Some(*max_dimension), None)
+            }
+            UploadRejection::NotAllowed(_, allowed) => {
+                ("encoding-not-allowed", None, Some(allowed.clone()))
+            }
+            UploadRejection::Unsupported(_) => ("unsupported-type", None, None),
+            UploadRejection::Invalid(_) => ("invalid-image", None, None),
+            UploadRejection::IOError => ("internal-error", None, None),
+        };
+
+        UploadError { code, message: self.to_string(), limit, allowed_encodings }
+    }
+}
+
+impl fmt::Display for UploadRejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UploadRejection::TooLarge(max_size) => {
+                write!(f, "Uploads are limited to {} bytes", max_size)
+            }
+            UploadRejection::TooManyPixels(max_pixels) => {
+                write!(f, "Images are limited to {} pixels", max_pixels)
+            }
+            UploadRejection::TooWide(max_dimension) => {
+                write!(
+                    f, "Images are limited to {} pixels in width and height",
+                    max_dimension
+                )
+            }
+            UploadRejection::NotAllowed(encoding, _) => {
+                write!(f, "Images of type {} are not allowed", encoding.mime_type())
+            }
+            UploadRejection::Unsupported(msg) => write!(f, "{}", msg),
+            UploadRejection::Invalid(msg) => write!(f, "{}", msg),
+            UploadRejection::IOError => write!(f, "Error while reading the upload"),
+        }
+    }
+}
+
+/// Checks a validated upload against the upload limits. Only the image's
+/// header has been read at this point, so oversized images are rejected
+/// before they are decoded.
+pub fn check_limits(uploaded: &UploadedFile, limits: &UploadLimits)
+    -> Result<(), UploadRejection> {
+    if uploaded.size > limits.max_size {
+        return Err(UploadRejection::TooLarge(limits.max_size));
+    }
+
+    if !limits.allowed_encodings.contains(&uploaded.encoding) {
+        return Err(UploadRejection::NotAllowed(
+            uploaded.encoding, limits.allowed_encodings.clone()
+        ));
+    }
+
+    // Dimensions are stored as SMALLINT UNSIGNED
+    if uploaded.width > u16::MAX as u32 || uploaded.height > u16::MAX as u32 {
+        return Err(UploadRejection::TooWide(u16::MAX as u64));
+    }
+
+    if uploaded.width as u64 * uploaded.height as u64 > limits.max_pixels {
+        return Err(UploadRejection::TooManyPixels(limits.max_pixels));
+    }
+
+    Ok(())
+}
+
+/// Detects the encoding of an image from its first bytes.
+pub fn sniff_encoding(header: &[u8]) -> Option<Encoding> {
+    if header.starts_with(&[ 0xFF, 0xD8, 0xFF ]) {
+        return Some(Encoding::JPG);
+    }
+
+    if header.starts_with(&[ 0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A ]) {
+        return Some(Encoding::PNG);
+    }
+
+    if header.starts_with(b"GIF87a") || header.starts_with(b"GIF89a") {
+        return Some(Encoding::GIF);
+    }
+
+    if header.len() >= 12 && &header[0..4] == b"RIFF" && &header[8..12] == b"WEBP" {
+        return Some(Encoding::WEBP);
+    }
+
+    if header.len() >= 12 && &header[4..8] == b"ftyp"
+        && (&header[8..12] == b"avif" || &header[8..12] == b"avis") {
+        return Some(Encoding::AVIF);
+    }
+
+    if header.starts_with(b"II*\0") || header.starts_with(b"MM\0*") {
+        return Some(Encoding::TIF);
+    }
+
+    if header.starts_with(b"BM") {
+        return Some(Encoding::BMP);
+    }
+
+    None
+}
+
+/// Names the type of files that are commonly disguised as images.
+fn sniff_disguise(header: &[u8]) -> Option<&'static str> {
+    let signatures: [(&[u8], &str); 9] = [
+        (b"MZ", "a Windows executable"),
+        (b"\x7FELF", "an ELF executable"),
+        (&[ 0xCF, 0xFA, 0xED, 0xFE ], "a Mach-O executable"),
+        (&[ 0xCA, 0xFE, 0xBA, 0xBE ], "a Java class or Mach-O executable"),
+        (b"#!", "a script"),
+        (b"PK\x03\x04", "a ZIP archive"),
+        (b"%PDF-", "a PDF document"),
+        (b"<?xml", "an XML document"),
+        (b"<svg", "an SVG document"),
+    ];
+
+    signatures.iter()
+        .find(|(signature, _)| header.starts_with(signature))
+        .map(|(_, name)| *name)
+}
+
+/// Validates an upload: the file must be an image of a supported encoding,
+/// with readable dimensions, and mustn't be readable as another type of file.
+pub fn inspect_upload(path: &str) -> Result<UploadedFile, UploadRejection> {
+    let mut file = File::open(path).map_err(|e| {
+        error!("Error while opening upload {}: {}", path, e);
+        UploadRejection::IOError
+    })?;
+
+    let size = file.metadata().map(|m| m.len()).map_err(|_| UploadRejection::IOError)?;
+
+    let mut header: Vec<u8> = Vec::with_capacity(HEADER_LEN);
+    (&mut file).take(HEADER_LEN as u64).read_to_end(&mut header)
+        .map_err(|_| UploadRejection::IOError)?;
+
+    let encoding = match sniff_encoding(&header) {
+        Some(e) if SUPPORTED_ENCODINGS.contains(&e) => e,
+
+        Some(e) => {
+            return Err(UploadRejection::Unsupported(
+                format!("Images of type {} are not supported", e.mime_type())
+            ));
+        }
+
+        None => {
+            return Err(UploadRejection::Unsupported(match sniff_disguise(&header) {
+                Some(name) => format!("The file is {}, not an image", name),
+                None => String::from("The file is not a supported image"),
+            }));
+        }
+    };
+
+    let (width, height) = read_dimensions(path, encoding, &header)
+        .filter(|(w, h)| *w > 0 && *h > 0)
+        .ok_or_else(|| UploadRejection::Invalid(
+            String::from("The image is damaged or truncated")
+        ))?;
+
+    if let Some(name) = find_embedded_file(&mut file, size, encoding, &header)? {
+        return Err(UploadRejection::Invalid(
+            format!("The image also contains {}", name)
+        ));
+    }
+
+    Ok(UploadedFile { encoding, width, height, size })
+}
+
+/// Returns the dimensions of an image file (as stored), read from its
+/// header.
+pub fn image_dimensions(path: &str) -> Option<(u32, u32)> {
+    let mut header: Vec<u8> = Vec::with_capacity(HEADER_LEN);
+
+    File::open(path).ok()?.take(HEADER_LEN as u64).read_to_end(&mut header).ok()?;
+
+    read_dimensions(path, sniff_encoding(&header)?, &header)
+}
+
+/// Reads the dimensions from the image's header, without decoding it.
+fn read_dimensions(path: &str, encoding: Encoding, header: &[u8])
+    -> Option<(u32, u32)> {
+    let format = match encoding {
+        Encoding::JPG => ImageFormat::Jpeg,
+        Encoding::PNG => ImageFormat::Png,
+        Encoding::GIF => ImageFormat::Gif,
+        Encoding::WEBP => { return webp_dimensions(header); }
+        _ => { return None; }
+    };
+
+    let file = File::open(path).ok()?;
+
+    ImageReader::with_format(BufReader::new(file), format).into_dimensions().ok()
+}
+
+/// Reads the dimensions from the first chunk of a WebP image (lossy `VP8 `,
+/// lossless `VP8L` or extended `VP8X`).
+fn webp_dimensions(header: &[u8]) -> Option<(u32, u32)> {
+    if header.len() < 30 { return None; }
+
+    let b = |i: usize| header[i] as u32;
+
+    match &header[12..16] {
+        b"VP8 " => Some(((b(26) | b(27) << 8) & 0x3FFF, (b(28) | b(29) << 8) & 0x3FFF)),
+
+        b"VP8L" => Some((
+            1 + (b(21) | (b(22) & 0x3F) << 8),
+            1 + (b(22) >> 6 | b(23) << 2 | (b(24) & 0x0F) << 10),
+        )),
+
+        b"VP8X" => Some((
+            1 + (b(24) | b(25) << 8 | b(26) << 16),
+            1 + (b(27) | b(28) << 8 | b(29) << 16),
+        )),
+
+        _ => None,
+    }
+}
+
+/// Looks for another file hidden in an image where the programs reading that
+/// type of file would find it: a PDF header in the first KiB, a ZIP central
+/// directory at the end, or markup where browsers sniff it (the first KiB)
+/// and in the metadata (comments, text chunks, EXIF and XMP). The image data
+/// itself isn't searched, compressed data matches short markers by chance.
+///
+/// Returns the type of the hidden file.
+fn find_embedded_file(
+    file: &mut File, size: u64, encoding: Encoding, header: &[u8],
+) -> Result<Option<&'static str>, UploadRejection> {
+    if contains(header, b"%PDF-") {
+        return Ok(Some("a PDF document"));
+    }
+
+    let mut trailer: Vec<u8> = Vec::new();
+
+    file.seek(SeekFrom::Start(size.saturating_sub(ZIP_TRAILER_LEN)))
+        .and_then(|_| file.read_to_end(&mut trailer))
+        .map_err(|_| UploadRejection::IOError)?;
+
+    if contains(&trailer, b"PK\x05\x06") {
+        return Ok(Some("a ZIP archive"));
+    }
+
+    file.seek(SeekFrom::Start(0)).map_err(|_| UploadRejection::IOError)?;
+
+    let segments = metadata_segments(&mut BufReader::new(file), encoding)
+        .map_err(|_| UploadRejection::IOError)?;
+
+    for data in std::iter::once(header).chain(segments.iter().map(|s| s.as_slice())) {
+        if contains_markup(data) {
+            return Ok(Some("HTML or script markup"));
+        }
+    }
+
+    Ok(None)
+}
+
+fn contains_markup(data: &[u8]) -> bool {
+    let data = data.to_ascii_lowercase();
+
+    SCRIPT_MARKERS.iter().any(|marker| contains(&data, marker.as_bytes()))
+}
+
+/// Reads the metadata segments of an image: JPEG `APPn` and `COM` segments,
+/// PNG text chunks, GIF comment and application extensions, and WebP `EXIF`
+/// and `XMP ` chunks. Only the first `MAX_SEGMENT_LEN` bytes of a segment are
+/// read, reading stops at a damaged or truncated structure.
+fn metadata_segments<R: Read + Seek>(reader: &mut R, encoding: Encoding)
+    -> std::io::Result<Vec<Vec<u8>>> {
+    let mut segments: Vec<Vec<u8>> = vec![];
+
+    // Reading past the end of the file ends the walk.
+    match walk_metadata(reader, encoding, &mut segments) {
+        Err(e) if e.kind() != std::io::ErrorKind::UnexpectedEof => Err(e),
+        _ => Ok(segments),
+    }
+}
+
+fn walk_metadata<R: Read + Seek>(
+    reader: &mut R, encoding: Encoding, segments: &mut Vec<Vec<u8>>,
+) -> std::io::Result<()> {
+    let mut buf = [0u8; 8];
+
+    match encoding {
+        Encoding::JPG => {
+            reader.seek(SeekFrom::Start(2))?;
+
+            loop {
+                reader.read_exact(&mut buf[..2])?;
+
+                if buf[0] != 0xFF { return Ok(()); }
+
+                match buf[1] {
+                    // Fill byte
+                    0xFF => { reader.seek(SeekFrom::Current(-1))?; }
+                    // Markers without a length
+                    0x01 | 0xD0..=0xD7 => {}
+                    // Start of scan (image data) or end of image
+                    0xDA | 0xD9 => { return Ok(()); }
+
+                    marker => {
+                        reader.read_exact(&mut buf[..2])?;
+                        let len = (u16::from_be_bytes([ buf[0], buf[1] ]) as u64)
+                            .saturating_sub(2);
+
+                        if (0xE0..=0xEF).contains(&marker) || marker == 0xFE {
+                            read_segment(reader, len, segments)?;
+                        } else {
+                            reader.seek(SeekFrom::Current(len as i64))?;
+                        }
+                    }
+                }
+            }
+        }
+
+        Encoding::PNG => {
+            reader.seek(SeekFrom::Start(8))?;
+
+            loop {
+                reader.read_exact(&mut buf)?;
+                let len = u32::from_be_bytes([ buf[0], buf[1], buf[2], buf[3] ]) as u64;
+
+                match &buf[4..8] {
+                    b"IEND" => { return Ok(()); }
+                    b"tEXt" | b"zTXt" | b"iTXt" => {
+                        read_segment(reader, len, segments)?;
+                    }
+                    _ => { reader.seek(SeekFrom::Current(len as i64))?; }
+                }
+
+                // CRC
+                reader.seek(SeekFrom::Current(4))?;
+            }
+        }
+
+        Encoding::GIF => {
+            reader.seek(SeekFrom::Start(10))?;
+            reader.read_exact(&mut buf[..3])?;
+
+            // Global color table
+            if buf[0] & 0x80 != 0 {
+                reader.seek(SeekFrom::Current(3 << ((buf[0] & 0x07) + 1)))?;
+            }
+
+            loop {
+                reader.read_exact(&mut buf[..1])?;
+
+                let keep = match buf[0] {
+                    // Extension: comment and application extensions
+                    // are kept.
+                    0x21 => {
+                        reader.read_exact(&mut buf[..1])?;
+                        buf[0] == 0xFE || buf[0] == 0xFF
+                    }
+
+                    // Image descriptor, local color table and LZW code
+                    // size.
+                    0x2C => {
+                        reader.read_exact(&mut buf[..8])?;
+                        let mut table = [0u8; 1];
+                        reader.read_exact(&mut table)?;
+
+                        if table[0] & 0x80 != 0 {
+                            reader.seek(
+                                SeekFrom::Current(3 << ((table[0] & 0x07) + 1))
+                            )?;
+                        }
+
+                        reader.seek(SeekFrom::Current(1))?;
+                        false
+                    }
+
+                    _ => { return Ok(()); }
+                };
+
+                let mut data: Vec<u8> = Vec::new();
+
+                // Data sub-blocks, up to an empty one.
+                loop {
+                    reader.read_exact(&mut buf[..1])?;
+                    let len = buf[0] as u64;
+
+                    if len == 0 { break; }
+
+                    if keep && (data.len() as u64) < MAX_SEGMENT_LEN {
+                        reader.by_ref().take(len).read_to_end(&mut data)?;
+                    } else {
+                        reader.seek(SeekFrom::Current(len as i64))?;
+                    }
+                }
+
+                if keep { segments.push(data); }
+            }
+        }
+
+        Encoding::WEBP => {
+            reader.seek(SeekFrom::Start(12))?;
+
+            loop {
+                reader.read_exact(&mut buf)?;
+                let len = u32::from_le_bytes([ buf[4], buf[5], buf[6], buf[7] ]) as u64;
+                // Chunks are padded to an even size.
+                let padding = len % 2;
+
+                match &buf[0..4] {
+                    b"EXIF" | b"XMP " => { read_segment(reader, len, segments)?; }
+                    _ => { reader.seek(SeekFrom::Current(len as i64))?; }
+                }
+
+                reader.seek(SeekFrom::Current(padding as i64))?;
+            }
+        }
+
+        _ => Ok(()),
+    }
+}
+
+fn read_segment<R: Read + Seek>(
+    reader: &mut R, len: u64, segments: &mut Vec<Vec<u8>>,
+) -> std::io::Result<()> {
+    let mut data: Vec<u8> = Vec::new();
+    reader.by_ref().take(len.min(MAX_SEGMENT_LEN)).read_to_end(&mut data)?;
+
+    if (data.len() as u64) < len.min(MAX_SEGMENT_LEN) {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+
+    reader.seek(SeekFrom::Current(len.saturating_sub(MAX_SEGMENT_LEN) as i64))?;
+    segments.push(data);
+
+    Ok(())
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+/// Validates a received file and moves it to the path of the upload, the
+/// file is removed if it's rejected.
+pub fn accept_upload(src_path: &str, upload_id: &str, limits: &UploadLimits)
+    -> Result<UploadedFile, UploadRejection> {
+    match inspect_upload(src_path)
+        .and_then(|uploaded| check_limits(&uploaded, limits).map(|_| uploaded)) {
+        Ok(uploaded) => {
+            let dest_path = upload_path(upload_id, uploaded.encoding);
+
+            if let Err(e) = rename(src_path, &dest_path) {
+                error!("Error while moving upload {}: {}", upload_id, e);
+                return Err(UploadRejection::IOError);
+            }
+
+            debug!(
+                "Accepted upload {}: {} {}x{}, {} bytes", upload_id,
+                uploaded.encoding.mime_type(), uploaded.width, uploaded.height,
+                uploaded.size
+            );
+
+            Ok(uploaded)
+        }
+
+        Err(rejection) => {
+            debug!("Rejected upload {}: {}", upload_id, rejection);
+
+            let _ = remove_file(src_path);
+
+            Err(rejection)
+        }
+    }
+}
+
+/// Path of an accepted upload.
+pub fn upload_path(upload_id: &str, encoding: Encoding) -> String {
+    format!("{}/{}{}", UPLOAD_DIR, upload_id, encoding.extension())
+}
+
+/// Returns the path of the accepted upload with the given ID, `None` if
+/// there is none.
+pub fn find_upload(upload_id: &str) -> Option<String> {
+    // Upload IDs are UUIDs, anything else is rejected before it ends up in
+    // a file path.
+    if Uuid::parse_str(upload_id).is_err() {
+        return None;
+    }
+
+    SUPPORTED_ENCODINGS.iter()
+        .map(|encoding| upload_path(upload_id, *encoding))
+        .find(|path| Path::new(path).exists())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn segments(data: &[u8], encoding: Encoding) -> Vec<Vec<u8>> {
+        metadata_segments(&mut Cursor::new(data), encoding).unwrap()
+    }
+
+    /// Returns the first 30 bytes of a WebP file with the given chunk.
+    fn webp_header(chunk: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut webp: Vec<u8> = b"RIFF\0\0\0\0WEBP".to_vec();
+        webp.extend(chunk);
+        webp.extend([ 0; 4 ]);
+        webp.extend(data);
+        webp.resize(30, 0);
+        webp
+    }
+
+    #[test]
+    fn sniffs_the_encoding_from_the_signature() {
+        let cases: [(&[u8], Option<Encoding>); 8] = [
+            (&[ 0xFF, 0xD8, 0xFF, 0xE0 ], Some(Encoding::JPG)),
+            (b"\x89PNG\r\n\x1A\n\0\0", Some(Encoding::PNG)),
+            (b"GIF87a", Some(Encoding::GIF)),
+            (b"RIFF\x10\0\0\0WEBPVP8 ", Some(Encoding::WEBP)),
+            (b"\0\0\0\x1CftypavifXXXX", Some(Encoding::AVIF)),
+            (b"MM\0*\0\0\0\x08", Some(Encoding::TIF)),
+            (b"RIFF\x10\0\0\0WAVE", None),
+            (b"<svg", None),
+        ];
+
+        for (header, encoding) in cases {
+            assert!(sniff_encoding(header) == encoding, "{:?}", header);
+        }
+    }
+
+    #[test]
+    fn reads_webp_dimensions() {
+        // Lossy: 640x480 after the frame tag and start code
+        let lossy = webp_header(b"VP8 ", &[ 0, 0, 0, 0x9D, 0x01, 0x2A, 0x80, 0x02, 0xE0, 0x01 ]);
+        assert_eq!(webp_dimensions(&lossy), Some((640, 480)));
+
+        // Lossless: 640x480 as 14-bit values minus one
+        let lossless = webp_header(b"VP8L", &[ 0x2F, 0x7F, 0xC2, 0x77, 0x00 ]);
+        assert_eq!(webp_dimensions(&lossless), Some((640, 480)));
+
+        // Extended: 70000x3 as 24-bit values minus one
+        let extended = webp_header(b"VP8X", &[ 0, 0, 0, 0, 0x6F, 0x11, 0x01, 0x02, 0x00, 0x00 ]);
+        assert_eq!(webp_dimensions(&extended), Some((70000, 3)));
+
+        assert_eq!(webp_dimensions(&webp_header(b"ALPH", &[])), None);
+        assert_eq!(webp_dimensions(&lossy[..29]), None);
+    }
+
+    #[test]
+    fn reads_jpeg_comments_but_not_the_image_data() {
+        let mut jpeg: Vec<u8> = vec![ 0xFF, 0xD8 ];
+        // Quantization table, skipped
+        jpeg.extend([ 0xFF, 0xDB, 0x00, 0x04, 0x01, 0x02 ]);
+        // Comment
+        jpeg.extend([ 0xFF, 0xFE, 0x00, 0x07 ]);
+        jpeg.extend(b"hello");
+        // Start of scan, followed by data that happens to match a marker
+        jpeg.extend([ 0xFF, 0xDA, 0x00, 0x02 ]);
+        jpeg.extend(b"<SCRIPT");
+
+        assert_eq!(segments(&jpeg, Encoding::JPG), vec![ b"hello".to_vec() ]);
+    }
+
+    #[test]
+    fn reads_png_text_chunks() {
+        let mut png: Vec<u8> = vec![ 0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A ];
+        png.extend([ 0, 0, 0, 2 ]);
+        png.extend(b"IDAT<h");
+        png.extend([ 0; 4 ]);
+        png.extend([ 0, 0, 0, 14 ]);
+        png.extend(b"tEXtComment\0<html>");
+        png.extend([ 0; 4 ]);
+        png.extend([ 0, 0, 0, 0 ]);
+        png.extend(b"IEND");
+
+        let found = segments(&png, Encoding::PNG);
+
+        assert_eq!(found, vec![ b"Comment\0<html>".to_vec() ]);
+        assert!(contains_markup(&found[0]));
+    }
+
+    #[test]
+    fn reads_gif_comment_extensions() {
+        let mut gif: Vec<u8> = b"GIF89a".to_vec();
+        // 1x1, global color table of 2 colors
+        gif.extend([ 1, 0, 1, 0, 0x80, 0, 0 ]);
+        gif.extend([ 0; 6 ]);
+        // Comment extension in two sub-blocks
+        gif.extend([ 0x21, 0xFE, 3 ]);
+        gif.extend(b"<IF");
+        gif.extend([ 4 ]);
+        gif.extend(b"RAME");
+        gif.extend([ 0 ]);
+        // Image, its data is skipped
+        gif.extend([ 0x2C, 0, 0, 0, 0, 1, 0, 1, 0, 0, 2, 2 ]);
+        gif.extend(b"<p");
+        gif.extend([ 0, 0x3B ]);
+
+        let found = segments(&gif, Encoding::GIF);
+
+        assert_eq!(found, vec![ b"<IFRAME".to_vec() ]);
+        assert!(contains_markup(&found[0]));
+    }
+
+    #[test]
+    fn stops_at_truncated_segments() {
+        let jpeg: Vec<u8> = vec![ 0xFF, 0xD8, 0xFF, 0xFE, 0x10, 0x00, b'a' ];
+
+        assert!(segments(&jpeg, Encoding::JPG).is_empty());
+    }
+}
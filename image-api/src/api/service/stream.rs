@@ -0,0 +1,381 @@
+//! File streaming service
+//!
+//! Serves files from the disk as streamed bodies and handles `Range` requests
+//! (single and multiple byte ranges) along with conditional requests.
+
+use std::{
+    cmp::min, fs::File, io::{ Read, Seek, SeekFrom, Error, ErrorKind },
+};
+
+use actix_web::{
+    HttpRequest, HttpResponse, body::SizedStream,
+    web::{ block, Bytes },
+    http::header::{ self, Header, IfRange },
+};
+use futures::{ stream::{ self, LocalBoxStream }, StreamExt };
+use uuid::Uuid;
+use log::{ debug, error };
+
+use crate::api::service::cache::{ CacheValidators, ContentVersion };
+
+/// Size of the chunks that are read from the disk at once.
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Maximum number of ranges served for a single request. Requests asking for
+/// more ranges get the complete file instead.
+const MAX_RANGES: usize = 16;
+
+/// An inclusive byte range.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn length(&self) -> u64 { self.end - self.start + 1 }
+
+    fn content_range(&self, total: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, total)
+    }
+}
+
+pub enum RangeError {
+    /// The `Range` header is malformed and should be ignored.
+    Invalid,
+
+    /// None of the requested ranges overlap the file.
+    Unsatisfiable,
+}
+
+/// Parses the value of a `Range` header for a file of `length` bytes.
+///
+/// ### Example:
+/// ```rust
+/// let ranges = parse_ranges("bytes=0-99,-100", 1000);
+/// ```
+pub fn parse_ranges(value: &str, length: u64) -> Result<Vec<ByteRange>, RangeError> {
+    let specs: &str;
+
+    match value.trim().strip_prefix("bytes=") {
+        Some(s) => { specs = s; }
+        None => { return Err(RangeError::Invalid); }
+    }
+
+    let mut ranges: Vec<ByteRange> = vec![];
+
+    for spec in specs.split(',') {
+        let spec = spec.trim();
+
+        if spec.is_empty() { continue; }
+
+        let (start_str, end_str) = match spec.split_once('-') {
+            Some(s) => s,
+            None => { return Err(RangeError::Invalid); }
+        };
+
+        if start_str.is_empty() {
+            // Suffix range: last `n` bytes
+            match end_str.parse::<u64>() {
+                Ok(0) => {}
+                Ok(n) => {
+                    if length > 0 {
+                        ranges.push(ByteRange {
+                            start: length - min(n, length),
+                            end: length - 1,
+                        });
+                    }
+                }
+                Err(_) => { return Err(RangeError::Invalid); }
+            }
+
+            continue;
+        }
+
+        let start: u64 = match start_str.parse() {
+            Ok(s) => s,
+            Err(_) => { return Err(RangeError::Invalid); }
+        };
+
+        let end: u64 = if end_str.is_empty() {
+            u64::MAX
+        } else {
+            match end_str.parse() {
+                Ok(e) => e,
+                Err(_) => { return Err(RangeError::Invalid); }
+            }
+        };
+
+        if end < start { return Err(RangeError::Invalid); }
+
+        if start < length {
+            ranges.push(ByteRange { start, end: min(end, length - 1) });
+        }
+    }
+
+    if ranges.is_empty() {
+        return Err(RangeError::Unsatisfiable);
+    }
+
+    if ranges.len() > MAX_RANGES {
+        return Err(RangeError::Invalid);
+    }
+
+    Ok(ranges)
+}
+
+/// Returns a stream that reads `length` bytes of `file` starting at `offset`.
+pub fn file_stream(file: File, offset: u64, length: u64)
+    -> LocalBoxStream<'static, Result<Bytes, Error>> {
+    stream::try_unfold(
+        (file, offset, length),
+        |(mut file, offset, remaining)| async move {
+            if remaining == 0 { return Ok(None); }
+
+            let chunk_size = min(remaining, CHUNK_SIZE);
+
+            let (file, bytes) = block(move || {
+                let mut buffer = vec![0u8; chunk_size as usize];
+
+                file.seek(SeekFrom::Start(offset))?;
+                file.read_exact(&mut buffer)?;
+
+                Ok::<(File, Bytes), Error>((file, Bytes::from(buffer)))
+            }).await.map_err(|e| Error::new(ErrorKind::Other, e.to_string()))??;
+
+            Ok(Some((
+                bytes, (file, offset + chunk_size, remaining - chunk_size)
+            )))
+        }
+    ).boxed_local()
+}
+
+/// Checks the `If-Range` header. Returns `true` if the range request should
+/// be honoured.
+fn if_range_matches(req: &HttpRequest, validators: &Option<CacheValidators>)
+    -> bool {
+    if !req.headers().contains_key(header::IF_RANGE) {
+        return true;
+    }
+
+    if let Some(v) = validators {
+        match IfRange::parse(req) {
+            Ok(IfRange::EntityTag(etag)) => {
+                return etag.strong_eq(&v.etag);
+            }
+
+            Ok(IfRange::Date(date)) => {
+                return v.last_modified == Some(date);
+            }
+
+            Err(_) => {}
+        }
+    }
+
+    false
+}
+
+/// Serves a file as a streamed response.
+///
+/// Handles conditional requests (`304 Not Modified`), `Range` requests
+/// (`206 Partial Content`, including `multipart/byteranges` for multiple
+/// ranges) and unsatisfiable ranges (`416 Range Not Satisfiable`).
+///
+/// The validators of files generated from database records are derived from
+/// their `version`, those of the other files from the file itself.
+pub async fn serve_file(
+    req: &HttpRequest, file_path: String, mime_type: String,
+    cache_control: &str, version: Option<&ContentVersion>,
+) -> HttpResponse {
+    let validators = match version {
+        Some(v) => Some(CacheValidators::from_version(v, &file_path)),
+        None => CacheValidators::from_file(&file_path),
+    };
+
+    if let Some(v) = &validators {
+        if v.is_not_modified(req) {
+            debug!("--> Not modified, returning 304");
+            return v.not_modified(cache_control);
+        }
+    }
+
+    let path = file_path.clone();
+    let file: File;
+    let length: u64;
+
+    match block(move || {
+        let f = File::open(path)?;
+        let len = f.metadata()?.len();
+
+        Ok::<(File, u64), Error>((f, len))
+    }).await {
+        Ok(Ok((f, len))) => { file = f; length = len; }
+
+        Ok(Err(e)) => {
+            error!("Error while opening file {}: {}", file_path, e);
+
+            return match e.kind() {
+                ErrorKind::NotFound => HttpResponse::NotFound()
+                    .body("Not Found"),
+                _ => HttpResponse::InternalServerError()
+                    .body("Internal Server Error"),
+            };
+        }
+
+        Err(e) => {
+            error!("Error while opening file {}: {}", file_path, e);
+
+            return HttpResponse::InternalServerError()
+                .body("Internal Server Error");
+        }
+    }
+
+    let mut ranges: Vec<ByteRange> = vec![];
+
+    if let Some(range_header) = req.headers().get(header::RANGE) {
+        if if_range_matches(req, &validators) {
+            match parse_ranges(range_header.to_str().unwrap_or(""), length) {
+                Ok(r) => { ranges = r; }
+
+                Err(RangeError::Unsatisfiable) => {
+                    return HttpResponse::RangeNotSatisfiable()
+                        .insert_header((
+                            header::CONTENT_RANGE,
+                            format!("bytes */{}", length)
+                        ))
+                        .finish();
+                }
+
+                Err(RangeError::Invalid) => {
+                    debug!("Ignoring invalid range header");
+                }
+            }
+        }
+    }
+
+    let mut response = if ranges.is_empty() {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::PartialContent()
+    };
+
+    response.insert_header((header::ACCEPT_RANGES, "bytes"));
+
+    if let Some(v) = &validators {
+        v.apply(&mut response, cache_control);
+    }
+
+    match ranges.len() {
+        0 => {
+            response.content_type(mime_type)
+                .body(SizedStream::new(length, file_stream(file, 0, length)))
+        }
+
+        1 => {
+            let range = ranges[0];
+
+            response.content_type(mime_type)
+                .insert_header((
+                    header::CONTENT_RANGE, range.content_range(length)
+                ))
+                .body(SizedStream::new(
+                    range.length(),
+                    file_stream(file, range.start, range.length())
+                ))
+        }
+
+        _ => {
+            let boundary = Uuid::new_v4().simple().to_string();
+            let mut body_length: u64 = 0;
+            let mut parts: Vec<LocalBoxStream<'static, Result<Bytes, Error>>>
+                = vec![];
+
+            for range in ranges.iter() {
+                let part_header = format!(
+                    "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+                    boundary, mime_type, range.content_range(length)
+                );
+
+                let part_file = match file.try_clone() {
+                    Ok(f) => f,
+                    Err(e) => {
+                        error!("Error while cloning file handle: {}", e);
+
+                        return HttpResponse::InternalServerError()
+                            .body("Internal Server Error");
+                    }
+                };
+
+                body_length += part_header.len() as u64 + range.length();
+
+                parts.push(
+                    stream::once(async move { Ok(Bytes::from(part_header)) })
+                        .boxed_local()
+                );
+                parts.push(file_stream(part_file, range.start, range.length()));
+            }
+
+            let closing = format!("\r\n--{}--\r\n", boundary);
+
+            body_length += closing.len() as u64;
+
+            parts.push(
+                stream::once(async move { Ok(Bytes::from(closing)) })
+                    .boxed_local()
+            );
+
+            response
+                .content_type(format!(
+                    "multipart/byteranges; boundary={}", boundary
+                ))
+                .body(SizedStream::new(
+                    body_length, stream::iter(parts).flatten().boxed_local()
+                ))
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u64, end: u64) -> ByteRange {
+        ByteRange { start, end }
+    }
+
+    #[test]
+    fn parses_closed_open_and_suffix_ranges() {
+        assert_eq!(
+            parse_ranges("bytes=0-99, 500-, -100", 1000).ok(),
+            Some(vec![ range(0, 99), range(500, 999), range(900, 999) ])
+        );
+    }
+
+    #[test]
+    fn clamps_ranges_to_the_file() {
+        assert_eq!(parse_ranges("bytes=900-2000", 1000).ok(), Some(vec![ range(900, 999) ]));
+        assert_eq!(parse_ranges("bytes=-2000", 1000).ok(), Some(vec![ range(0, 999) ]));
+    }
+
+    #[test]
+    fn rejects_malformed_ranges() {
+        for value in [ "0-99", "bytes=a-b", "bytes=99-0", "bytes=5", "items=0-1" ] {
+            assert!(matches!(parse_ranges(value, 1000), Err(RangeError::Invalid)), "{}", value);
+        }
+    }
+
+    #[test]
+    fn ignores_requests_for_too_many_ranges() {
+        let value = format!("bytes={}", vec!["0-0"; MAX_RANGES + 1].join(","));
+
+        assert!(matches!(parse_ranges(&value, 1000), Err(RangeError::Invalid)));
+    }
+
+    #[test]
+    fn rejects_ranges_outside_the_file() {
+        assert!(matches!(parse_ranges("bytes=1000-", 1000), Err(RangeError::Unsatisfiable)));
+        assert!(matches!(parse_ranges("bytes=-0", 1000), Err(RangeError::Unsatisfiable)));
+        assert!(matches!(parse_ranges("bytes=-10", 0), Err(RangeError::Unsatisfiable)));
+    }
+}
@@ -0,0 +1,225 @@
+//! Resumable upload service (tus 1.0)
+//!
+//! Keeps the state of the resumable uploads (see `api::upload`) in the
+//! `temp` directory: the received bytes in `{upload_id}.part` and the upload's
+//! length, metadata and expiration in `{upload_id}.info`. The offset of an
+//! upload is the size of its `.part` file, so it survives restarts. The
+//! `temp` directory is the node's own, the uploads' URLs point at the node
+//! that created them (`upload.nodeUrl`).
+//!
+//! Completed uploads are validated and moved to `temp/{upload_id}{ext}`, like
+//! the multipart uploads, where `add_image` picks them up.
+
+use std::{
+    collections::HashMap,
+    fs::{ metadata, read_to_string, remove_file, write, File },
+};
+
+use base64::{ Engine, engine::general_purpose::STANDARD };
+use chrono::{ DateTime, Duration, Utc };
+use serde::{ Serialize, Deserialize };
+use uuid::Uuid;
+use log::{ debug, error };
+
+use crate::{
+    api::service::sniff::{
+        accept_upload, find_upload, UploadedFile, UploadRejection, UPLOAD_DIR,
+    },
+    server::config::UploadLimits,
+};
+
+/// Supported version of the tus protocol.
+pub const TUS_VERSION: &str = "1.0.0";
+
+/// Supported protocol extensions.
+pub const TUS_EXTENSIONS: &str = "creation,expiration,termination";
+
+/// Content type of the `PATCH` requests.
+pub const OFFSET_CONTENT_TYPE: &str = "application/offset+octet-stream";
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadInfo {
+    pub id: String,
+    /// Total size of the upload, in bytes.
+    pub length: u64,
+    /// Decoded `Upload-Metadata` (e.g. `filename`, `filetype`).
+    pub metadata: HashMap<String, String>,
+    pub expires: DateTime<Utc>,
+}
+
+impl UploadInfo {
+    pub fn is_expired(&self) -> bool {
+        self.expires < Utc::now()
+    }
+
+    /// Returns the number of bytes received so far.
+    pub fn offset(&self) -> u64 {
+        if self.is_accepted() {
+            return self.length;
+        }
+
+        metadata(part_path(&self.id)).map(|m| m.len()).unwrap_or(0)
+    }
+
+    /// Returns whether the upload was completed and accepted, it's then
+    /// pending for `add_image` and expires with the pending uploads.
+    pub fn is_accepted(&self) -> bool {
+        find_upload(&self.id).is_some()
+    }
+
+    /// Postpones the expiration, counted from now.
+    pub fn extend(&mut self, expiration_hours: u32) {
+        self.expires = Utc::now() + Duration::hours(expiration_hours as i64);
+    }
+}
+
+/// Upload IDs are UUIDs, anything else is rejected before it ends up in a
+/// file path.
+pub fn is_valid_upload_id(upload_id: &str) -> bool {
+    Uuid::parse_str(upload_id).is_ok()
+}
+
+/// Parses the `Upload-Metadata` header: comma-separated keys, each followed
+/// by its base64 encoded value (which may be omitted).
+pub fn parse_metadata(header: &str) -> Result<HashMap<String, String>, String> {
+    let mut upload_metadata: HashMap<String, String> = HashMap::new();
+
+    for pair in header.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let mut parts = pair.splitn(2, ' ');
+        let key = parts.next().unwrap_or("");
+
+        let value = match parts.next() {
+            Some(encoded) => {
+                match STANDARD.decode(encoded.trim()) {
+                    Ok(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+                    Err(_) => {
+                        return Err(format!("Invalid metadata value of {}", key));
+                    }
+                }
+            }
+
+            None => String::new(),
+        };
+
+        upload_metadata.insert(key.to_string(), value);
+    }
+
+    Ok(upload_metadata)
+}
+
+/// Creates a new (empty) upload.
+pub fn create_upload(
+    length: u64, upload_metadata: HashMap<String, String>, expiration_hours: u32,
+) -> Result<UploadInfo, ()> {
+    let mut info = UploadInfo {
+        id: Uuid::new_v4().to_string(),
+        length,
+        metadata: upload_metadata,
+        expires: Utc::now(),
+    };
+
+    info.extend(expiration_hours);
+
+    if let Err(e) = File::create(part_path(&info.id)) {
+        error!("Error while creating upload file: {}", e);
+        return Err(());
+    }
+
+    save_upload(&info)?;
+
+    debug!("Created upload {} ({} bytes)", info.id, info.length);
+
+    Ok(info)
+}
+
+/// Returns the upload with the given ID, `None` if it doesn't exist.
+pub fn get_upload(upload_id: &str) -> Option<UploadInfo> {
+    if !is_valid_upload_id(upload_id) {
+        return None;
+    }
+
+    let content = read_to_string(info_path(upload_id)).ok()?;
+
+    match serde_json::from_str(&content) {
+        Ok(info) => Some(info),
+        Err(e) => {
+            error!("Error while reading upload {}: {}", upload_id, e);
+            None
+        }
+    }
+}
+
+pub fn save_upload(info: &UploadInfo) -> Result<(), ()> {
+    let content = serde_json::to_string(info).map_err(|_| ())?;
+
+    write(info_path(&info.id), content).map_err(|e| {
+        error!("Error while saving upload {}: {}", info.id, e);
+    })
+}
+
+/// Validates a complete upload and moves it to the path `add_image`
+/// expects. Rejected uploads are removed.
+pub fn complete_upload(info: &UploadInfo, limits: &UploadLimits)
+    -> Result<UploadedFile, UploadRejection> {
+    let result = accept_upload(&part_path(&info.id), &info.id, limits);
+
+    match &result {
+        Ok(_) => { debug!("Completed upload {}", info.id); }
+        Err(_) => { remove_upload(&info.id); }
+    }
+
+    result
+}
+
+/// Removes the files of an upload.
+pub fn remove_upload(upload_id: &str) {
+    for path in [ part_path(upload_id), info_path(upload_id) ] {
+        let _ = remove_file(path);
+    }
+
+    if let Some(path) = find_upload(upload_id) {
+        let _ = remove_file(path);
+    }
+
+    debug!("Removed upload {}", upload_id);
+}
+
+/// Path of the bytes received so far.
+pub fn part_path(upload_id: &str) -> String {
+    format!("{}/{}.part", UPLOAD_DIR, upload_id)
+}
+
+fn info_path(upload_id: &str) -> String {
+    format!("{}/{}.info", UPLOAD_DIR, upload_id)
+}
+
+/// Formats a date as an HTTP date (e.g. `Upload-Expires`).
+pub fn http_date(date: DateTime<Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_upload_metadata() {
+        // "project" => "blog", "filename" => "hero.jpg"
+        let upload_metadata = parse_metadata(
+            "project YmxvZw==, filename aGVyby5qcGc=,is_draft,"
+        ).unwrap();
+
+        assert_eq!(upload_metadata.len(), 3);
+        assert_eq!(upload_metadata["project"], "blog");
+        assert_eq!(upload_metadata["filename"], "hero.jpg");
+        assert_eq!(upload_metadata["is_draft"], "");
+
+        assert!(parse_metadata("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_values_that_are_not_base64() {
+        assert!(parse_metadata("project blog!").is_err());
+    }
+}
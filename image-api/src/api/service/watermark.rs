@@ -0,0 +1,169 @@
+//! Watermark service
+//!
+//! Composites the watermark image over renditions while they are generated.
+//! Watermarks are images of the DAM, loaded (with their edits) through the
+//! storage like any original.
+
+use actix_web::web::Data;
+use log::{ debug, error };
+
+use crate::{
+    api::service::{
+        path::position_mode, content::original_path, edit::open_edited,
+    },
+    model::{ watermark::Watermark, transform::Gravity },
+    repository::Repository, storage::Storages,
+};
+
+/// A watermark along with its (edited) image.
+pub struct WatermarkImage {
+    pub watermark: Watermark,
+    pub image: raster::Image,
+}
+
+/// Resolves the watermark of a rendition (see `Watermark::resolve`) and
+/// loads its image.
+///
+/// Returns `None` if no watermark applies.
+pub fn load_watermark(
+    repo: &Data<dyn Repository + Sync + Send>, upload_dir: &str,
+    storage: &Storages, rendition: Option<&Watermark>, project: Option<&Watermark>,
+) -> Result<Option<WatermarkImage>, ()> {
+    let watermark = match Watermark::resolve(rendition, project) {
+        Some(w) => w,
+        None => { return Ok(None); }
+    };
+
+    let image = match repo.get_image_repo().map(|mut img_repo| img_repo.get(watermark.image_id)) {
+        Ok(Ok(i)) => i,
+        _ => {
+            error!("Error while getting watermark image {}", watermark.image_id);
+            return Err(());
+        }
+    };
+
+    let file_path = original_path(upload_dir, &image);
+
+    if let Err(e) = storage.fetch_original(&file_path) {
+        error!("Error while fetching watermark image {}: {}", image.id, e);
+        return Err(());
+    }
+
+    Ok(Some(WatermarkImage { image: open_edited(&file_path, &image.edits)?, watermark }))
+}
+
+/// Space between the watermark and the edges of the rendition (and between
+/// the tiles), as a fraction of the watermark's width.
+const MARGIN: f32 = 0.1;
+
+/// Composites the watermark over the image.
+pub fn apply_watermark(raster_img: &mut raster::Image, mark: &WatermarkImage)
+    -> Result<(), ()> {
+    let watermark = &mark.watermark;
+    let mut mark = mark.image.clone();
+
+    let width = ((raster_img.width as f32 * watermark.scale).round() as i32)
+        .clamp(1, raster_img.width.max(1));
+
+    let height = ((mark.height as f32 * width as f32 / mark.width.max(1) as f32)
+        .round() as i32).clamp(1, raster_img.height.max(1));
+
+    if raster::editor::resize(&mut mark, width, height, raster::ResizeMode::Exact)
+        .is_err() {
+        error!("Error while resizing watermark: {}", watermark.image_id);
+        return Err(());
+    }
+
+    let margin = (width as f32 * MARGIN).round() as i32;
+
+    debug!(
+        "Applying watermark {} ({}x{}, tiled: {})",
+        watermark.image_id, width, height, watermark.tile
+    );
+
+    if watermark.tile {
+        let mut y = margin;
+
+        while y < raster_img.height {
+            let mut x = margin;
+
+            while x < raster_img.width {
+                blend(raster_img, &mark, x, y, watermark.opacity);
+                x += width + margin;
+            }
+
+            y += height + margin;
+        }
+    } else {
+        let (x, y) = offset(
+            raster_img.width, raster_img.height, width, height, margin,
+            watermark.gravity,
+        );
+
+        blend(raster_img, &mark, x, y, watermark.opacity);
+    }
+
+    Ok(())
+}
+
+/// Position of the top left corner of the watermark.
+fn offset(
+    img_width: i32, img_height: i32, width: i32, height: i32, margin: i32,
+    gravity: Gravity,
+) -> (i32, i32) {
+    use raster::PositionMode::*;
+
+    let left = margin;
+    let right = img_width - width - margin;
+    let center_x = (img_width - width) / 2;
+    let top = margin;
+    let bottom = img_height - height - margin;
+    let center_y = (img_height - height) / 2;
+
+    match position_mode(gravity) {
+        TopLeft => (left, top),
+        TopCenter => (center_x, top),
+        TopRight => (right, top),
+        CenterLeft => (left, center_y),
+        Center => (center_x, center_y),
+        CenterRight => (right, center_y),
+        BottomLeft => (left, bottom),
+        BottomCenter => (center_x, bottom),
+        BottomRight => (right, bottom),
+    }
+}
+
+/// Alpha-blends `mark` over the image at (`x`, `y`), parts outside the image
+/// are clipped.
+fn blend(raster_img: &mut raster::Image, mark: &raster::Image, x: i32, y: i32,
+    opacity: f32) {
+    let (img_width, img_height) = (raster_img.width, raster_img.height);
+
+    for my in 0..mark.height {
+        let iy = y + my;
+        if iy < 0 || iy >= img_height { continue; }
+
+        for mx in 0..mark.width {
+            let ix = x + mx;
+            if ix < 0 || ix >= img_width { continue; }
+
+            let m = ((my * mark.width + mx) * 4) as usize;
+            let i = ((iy * img_width + ix) * 4) as usize;
+
+            let alpha = mark.bytes[m + 3] as f32 / 255.0 * opacity;
+            if alpha <= 0.0 { continue; }
+
+            for c in 0..3 {
+                raster_img.bytes[i + c] = (
+                    mark.bytes[m + c] as f32 * alpha
+                        + raster_img.bytes[i + c] as f32 * (1.0 - alpha)
+                ).round() as u8;
+            }
+
+            // Watermarks make transparent pixels (partially) opaque.
+            raster_img.bytes[i + 3] = (
+                255.0 * alpha + raster_img.bytes[i + 3] as f32 * (1.0 - alpha)
+            ).round() as u8;
+        }
+    }
+}
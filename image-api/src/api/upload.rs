@@ -0,0 +1,339 @@
+//! Resumable uploads, implementing the tus 1.0 protocol
+//! (https://tus.io/protocols/resumable-upload) with the `creation`,
+//! `expiration` and `termination` extensions.
+//!
+//! The upload ID (the last segment of the `Location` returned on creation)
+//! is the `uploadId` that `add_image` consumes once the upload is complete.
+//! Complete uploads are validated like the multipart ones (see
+//! `service::sniff`), the `project` metadata (a project slug) selects the
+//! project's upload limits.
+//!
+//! An upload lives on the node that created it: its files are in the node's
+//! `temp` directory and the lock of the chunk it's receiving is in the
+//! node's `ServerState`. When several nodes serve the API, each sets
+//! `upload.nodeUrl` and the `Location` returned on creation points at the
+//! node, so that every chunk of the upload reaches it. `add_image` has to
+//! be sent to the same node.
+
+use std::{ collections::HashMap, fs::OpenOptions, io::Write };
+
+use actix_web::{
+    HttpRequest, HttpResponse, HttpResponseBuilder, delete, head, options,
+    patch, post,
+    http::{ StatusCode, header },
+    web::{ block, Data, Payload },
+};
+use futures::StreamExt;
+use log::{ debug, error };
+
+use crate::{
+    api::service::tus::{
+        UploadInfo, create_upload as create_tus_upload, get_upload,
+        save_upload, complete_upload, remove_upload, part_path, parse_metadata,
+        http_date, TUS_VERSION, TUS_EXTENSIONS, OFFSET_CONTENT_TYPE,
+    },
+    api::service::{
+        sniff::UploadRejection, cleanup::{ add_pending_upload, cancel_upload },
+    },
+    auth::AuthMiddleware, server_state::ServerState,
+    server::config::{ ServerConfig, UploadLimits },
+};
+
+const TUS_RESUMABLE: &str = "Tus-Resumable";
+const UPLOAD_OFFSET: &str = "Upload-Offset";
+const UPLOAD_LENGTH: &str = "Upload-Length";
+const UPLOAD_EXPIRES: &str = "Upload-Expires";
+
+/// Starts a response carrying the `Tus-Resumable` header.
+fn tus_response(status: StatusCode) -> HttpResponseBuilder {
+    let mut builder = HttpResponse::build(status);
+    builder.insert_header((TUS_RESUMABLE, TUS_VERSION));
+    builder
+}
+
+fn header_str<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    req.headers().get(name).and_then(|v| v.to_str().ok())
+}
+
+/// Rejects requests for an unsupported protocol version.
+fn check_version(req: &HttpRequest) -> Result<(), HttpResponse> {
+    if header_str(req, TUS_RESUMABLE) == Some(TUS_VERSION) {
+        return Ok(());
+    }
+
+    Err(tus_response(StatusCode::PRECONDITION_FAILED)
+        .insert_header(("Tus-Version", TUS_VERSION))
+        .finish())
+}
+
+/// Returns the upload a request points to, or the response to send if it
+/// doesn't exist or has expired (expired uploads are removed).
+fn find_upload(req: &HttpRequest) -> Result<UploadInfo, HttpResponse> {
+    let upload_id = req.match_info().get("upload_id").unwrap_or("");
+
+    match get_upload(upload_id) {
+        Some(info) if info.is_expired() && !info.is_accepted() => {
+            remove_upload(&info.id);
+            Err(tus_response(StatusCode::GONE).finish())
+        }
+
+        Some(info) => Ok(info),
+        None => Err(tus_response(StatusCode::NOT_FOUND).finish()),
+    }
+}
+
+/// Returns the upload limits of the project named in the upload's metadata
+/// (`project`, the project slug), the server's if there is none.
+fn upload_limits(conf: &ServerConfig, upload_metadata: &HashMap<String, String>)
+    -> UploadLimits {
+    conf.get_upload_limits(upload_metadata.get("project").map(String::as_str))
+}
+
+/// Returns the URL of an upload, on this node when `upload.nodeUrl` is set.
+fn upload_url(conf: &ServerConfig, upload_id: &str) -> String {
+    format!(
+        "{}/api/upload/{}", conf.upload.node_url.trim_end_matches('/'), upload_id
+    )
+}
+
+fn rejection_response(rejection: &UploadRejection) -> HttpResponse {
+    tus_response(rejection.status_code()).json(rejection.to_error())
+}
+
+/// Describes the server's tus support.
+#[options("/api/upload")]
+pub async fn upload_options(conf: Data<ServerConfig>) -> HttpResponse {
+    tus_response(StatusCode::NO_CONTENT)
+        .insert_header(("Tus-Version", TUS_VERSION))
+        .insert_header(("Tus-Extension", TUS_EXTENSIONS))
+        .insert_header(("Tus-Max-Size", conf.upload.max_size.to_string()))
+        .finish()
+}
+
+/// Creates an upload of `Upload-Length` bytes, the upload's URL is returned
+/// in the `Location` header.
+#[post("/api/upload")]
+pub async fn create_upload(
+    req: HttpRequest, _: AuthMiddleware, conf: Data<ServerConfig>,
+) -> HttpResponse {
+    if let Err(resp) = check_version(&req) {
+        return resp;
+    }
+
+    // Empty uploads can't be images, and would never receive a chunk.
+    let length: u64 = match header_str(&req, UPLOAD_LENGTH).map(|l| l.parse()) {
+        Some(Ok(l)) if l > 0 => l,
+        _ => {
+            return tus_response(StatusCode::BAD_REQUEST)
+                .body("Missing or invalid Upload-Length");
+        }
+    };
+
+    let upload_metadata = match parse_metadata(
+        header_str(&req, "Upload-Metadata").unwrap_or("")
+    ) {
+        Ok(m) => m,
+        Err(msg) => { return tus_response(StatusCode::BAD_REQUEST).body(msg); }
+    };
+
+    let max_size = upload_limits(&conf, &upload_metadata).max_size;
+
+    if length > max_size {
+        return rejection_response(&UploadRejection::TooLarge(max_size));
+    }
+
+    let expiration_hours = conf.upload.expiration_hours;
+
+    match block(move || create_tus_upload(length, upload_metadata, expiration_hours))
+        .await {
+        Ok(Ok(info)) => {
+            tus_response(StatusCode::CREATED)
+                .insert_header((header::LOCATION, upload_url(&conf, &info.id)))
+                .insert_header((UPLOAD_EXPIRES, http_date(info.expires)))
+                .finish()
+        }
+
+        _ => tus_response(StatusCode::INTERNAL_SERVER_ERROR).finish(),
+    }
+}
+
+/// Returns the offset (number of bytes received) of an upload.
+#[head("/api/upload/{upload_id}")]
+pub async fn get_upload_offset(req: HttpRequest, _: AuthMiddleware) -> HttpResponse {
+    if let Err(resp) = check_version(&req) {
+        return resp;
+    }
+
+    match find_upload(&req) {
+        Ok(info) => {
+            tus_response(StatusCode::OK)
+                .insert_header((UPLOAD_OFFSET, info.offset().to_string()))
+                .insert_header((UPLOAD_LENGTH, info.length.to_string()))
+                .insert_header((UPLOAD_EXPIRES, http_date(info.expires)))
+                .insert_header((header::CACHE_CONTROL, "no-store"))
+                .finish()
+        }
+
+        Err(resp) => resp,
+    }
+}
+
+/// Appends the request body to an upload, at `Upload-Offset` (which must be
+/// the upload's current offset). The upload is completed when all its bytes
+/// are received.
+#[patch("/api/upload/{upload_id}")]
+pub async fn append_upload(
+    req: HttpRequest, payload: Payload, _: AuthMiddleware,
+    conf: Data<ServerConfig>, state: Data<ServerState>,
+) -> HttpResponse {
+    if let Err(resp) = check_version(&req) {
+        return resp;
+    }
+
+    if header_str(&req, header::CONTENT_TYPE.as_str()) != Some(OFFSET_CONTENT_TYPE) {
+        return tus_response(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+            .body(format!("Content-Type must be {}", OFFSET_CONTENT_TYPE));
+    }
+
+    let info = match find_upload(&req) {
+        Ok(info) => info,
+        Err(resp) => { return resp; }
+    };
+
+    // Concurrent chunks of the same upload would corrupt it.
+    let _lock = match state.lock_upload(&info.id) {
+        Some(lock) => lock,
+        None => {
+            return tus_response(StatusCode::LOCKED)
+                .body("The upload is receiving another chunk");
+        }
+    };
+
+    append_chunk(&req, payload, info, &conf, &state).await
+}
+
+async fn append_chunk(
+    req: &HttpRequest, mut payload: Payload, mut info: UploadInfo,
+    conf: &Data<ServerConfig>, state: &Data<ServerState>,
+) -> HttpResponse {
+    let offset = info.offset();
+
+    match header_str(req, UPLOAD_OFFSET).map(|o| o.parse::<u64>()) {
+        Some(Ok(o)) if o == offset => {}
+        Some(Ok(_)) => {
+            return tus_response(StatusCode::CONFLICT)
+                .insert_header((UPLOAD_OFFSET, offset.to_string()))
+                .body("Upload-Offset doesn't match the upload's offset");
+        }
+        _ => {
+            return tus_response(StatusCode::BAD_REQUEST)
+                .body("Missing or invalid Upload-Offset");
+        }
+    }
+
+    let remaining = info.length - offset;
+
+    if let Some(Ok(content_length)) = header_str(req, header::CONTENT_LENGTH.as_str())
+        .map(|l| l.parse::<u64>()) {
+        if content_length > remaining {
+            return tus_response(StatusCode::PAYLOAD_TOO_LARGE)
+                .body("The chunk exceeds the Upload-Length");
+        }
+    }
+
+    let mut file = match OpenOptions::new().append(true).open(part_path(&info.id)) {
+        Ok(f) => f,
+        Err(e) => {
+            error!("Error while opening upload {}: {}", info.id, e);
+            return tus_response(StatusCode::INTERNAL_SERVER_ERROR).finish();
+        }
+    };
+
+    let mut received: u64 = 0;
+    let mut too_large = false;
+
+    // The bytes are written as they arrive, whatever was received before
+    // the connection dropped is kept.
+    while let Some(chunk) = payload.next().await {
+        let data = match chunk {
+            Ok(d) => d,
+            Err(e) => {
+                debug!("Upload {} interrupted: {}", info.id, e);
+                break;
+            }
+        };
+
+        let mut data = data.to_vec();
+
+        if received + data.len() as u64 > remaining {
+            data.truncate((remaining - received) as usize);
+            too_large = true;
+        }
+
+        received += data.len() as u64;
+
+        file = match block(move || file.write_all(&data).map(|_| file)).await {
+            Ok(Ok(f)) => f,
+            _ => {
+                error!("Error while writing upload {}", info.id);
+                return tus_response(StatusCode::INTERNAL_SERVER_ERROR).finish();
+            }
+        };
+
+        if too_large { break; }
+    }
+
+    let new_offset = offset + received;
+
+    // The content is validated once all of it is received, rejected
+    // uploads are removed. A complete upload that failed to be accepted is
+    // retried by an empty chunk.
+    if new_offset == info.length && !info.is_accepted() {
+        let completed = info.clone();
+
+        let limits = upload_limits(conf, &info.metadata);
+
+        match block(move || complete_upload(&completed, &limits)).await {
+            Ok(Ok(uploaded)) => { add_pending_upload(state, &info.id, uploaded.size); }
+
+            Ok(Err(rejection)) => { return rejection_response(&rejection); }
+
+            Err(_) => {
+                return tus_response(StatusCode::INTERNAL_SERVER_ERROR).finish();
+            }
+        }
+    }
+
+    info.extend(conf.upload.expiration_hours);
+    let _ = save_upload(&info);
+
+    if too_large {
+        return tus_response(StatusCode::PAYLOAD_TOO_LARGE)
+            .insert_header((UPLOAD_OFFSET, new_offset.to_string()))
+            .body("The chunk exceeds the Upload-Length");
+    }
+
+    tus_response(StatusCode::NO_CONTENT)
+        .insert_header((UPLOAD_OFFSET, new_offset.to_string()))
+        .insert_header((UPLOAD_EXPIRES, http_date(info.expires)))
+        .finish()
+}
+
+/// Terminates an upload, removing the bytes received so far.
+#[delete("/api/upload/{upload_id}")]
+pub async fn terminate_upload(
+    req: HttpRequest, _: AuthMiddleware, state: Data<ServerState>,
+) -> HttpResponse {
+    if let Err(resp) = check_version(&req) {
+        return resp;
+    }
+
+    match find_upload(&req) {
+        Ok(info) => {
+            cancel_upload(&state, &info.id);
+            tus_response(StatusCode::NO_CONTENT).finish()
+        }
+
+        Err(resp) => resp,
+    }
+}
@@ -0,0 +1,118 @@
+//! Command line modes, run instead of the server.
+//!
+//! - `import <directory> --project <slug> [--folder-id <id>]` - imports a
+//!   local directory tree (see `service::import`), printing a line per
+//!   directory and file.
+
+use std::{ io::{ Error, ErrorKind }, path::Path };
+
+use actix_web::web::Data;
+use log::error;
+
+use crate::{
+    api::service::import::{ check_target, Importer, ImportStatus },
+    repository::Repository, server::config::ServerConfig,
+    server_state::ServerState, storage::Storages,
+};
+
+const IMPORT_USAGE: &str =
+    "Usage: image-api import <directory> --project <slug> [--folder-id <id>]";
+
+/// Returns whether the command line asks for a CLI mode.
+pub fn is_cli_mode(args: &[String]) -> bool {
+    args.get(1).map_or(false, |mode| mode == "import")
+}
+
+/// Runs the CLI mode of the command line.
+pub fn run(
+    args: &[String], repo: Data<dyn Repository + Sync + Send>,
+    conf: &ServerConfig, storage: &Storages,
+) -> std::io::Result<()> {
+    match args.get(1).map(|a| a.as_str()) {
+        Some("import") => import(&args[2..], repo, conf, storage),
+        _ => Err(Error::new(ErrorKind::InvalidInput, IMPORT_USAGE)),
+    }
+}
+
+fn import(
+    args: &[String], repo: Data<dyn Repository + Sync + Send>,
+    conf: &ServerConfig, storage: &Storages,
+) -> std::io::Result<()> {
+    let usage = || Error::new(ErrorKind::InvalidInput, IMPORT_USAGE);
+
+    let mut directory: Option<&str> = None;
+    let mut project: Option<&str> = None;
+    let mut folder_id: u32 = 0;
+    let mut i = 0;
+
+    while i < args.len() {
+        match args[i].as_str() {
+            "--project" => {
+                project = args.get(i + 1).map(|p| p.as_str());
+                i += 1;
+            }
+
+            "--folder-id" => {
+                folder_id = args.get(i + 1)
+                    .and_then(|f| f.parse().ok())
+                    .ok_or_else(usage)?;
+                i += 1;
+            }
+
+            dir if directory.is_none() => { directory = Some(dir); }
+
+            _ => { return Err(usage()); }
+        }
+
+        i += 1;
+    }
+
+    let (directory, project) = match (directory, project) {
+        (Some(d), Some(p)) => (d, p),
+        _ => { return Err(usage()); }
+    };
+
+    let project_id = match repo.get_project_repo()
+        .map(|mut proj_repo| proj_repo.is_valid_slug(project.to_string())) {
+        Ok(Ok(Some(id))) => id,
+        _ => {
+            let msg = format!("Project '{}' doesn't exist", project);
+            error!("{}", msg);
+            return Err(Error::new(ErrorKind::NotFound, msg));
+        }
+    };
+
+    check_target(&repo, project_id, folder_id)
+        .map_err(|msg| Error::new(ErrorKind::NotFound, msg))?;
+
+    let state = ServerState::default();
+    let mut importer = Importer::new(
+        &repo, conf, storage, &state, project_id, folder_id,
+    );
+
+    importer.import_directory(Path::new(directory))
+        .map_err(|msg| Error::new(ErrorKind::Other, msg))?;
+
+    for entry in importer.entries.iter() {
+        let status = match entry.status {
+            ImportStatus::Imported => "imported",
+            ImportStatus::Skipped => "skipped",
+            ImportStatus::Failed => "FAILED",
+        };
+
+        println!("{:>8}  {}  ({})", status, entry.path, entry.message);
+    }
+
+    println!(
+        "\n{} imported, {} skipped, {} failed",
+        importer.count(ImportStatus::Imported),
+        importer.count(ImportStatus::Skipped),
+        importer.count(ImportStatus::Failed),
+    );
+
+    if importer.count(ImportStatus::Failed) > 0 {
+        return Err(Error::new(ErrorKind::Other, "Some files couldn't be imported"));
+    }
+
+    Ok(())
+}
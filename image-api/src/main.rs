@@ -6,6 +6,8 @@ mod model;
 mod server_state;
 mod log_config;
 mod server;
+mod storage;
+mod cli;
 
 use std::{ env, sync::Arc, io::{ Error, ErrorKind } };
 
@@ -14,11 +16,12 @@ use actix_web::{
 };
 use actix_cors::Cors;
 use actix_web_static_files::ResourceFiles;
-use log::{ info, error };
+use log::{ info, error, warn };
 
 use server_state::ServerState;
-use server::config::ServerConfig;
+use server::config::{ ServerConfig, StorageBackend };
 use repository::{ Repository, MySQLRepository };
+use storage::Storages;
 
 include!(concat!(env!("OUT_DIR"), "/generated.rs"));
 
@@ -59,8 +62,56 @@ async fn main() -> std::io::Result<()> {
         }
     }
 
+    let storage_data = match Storages::new(&server_config) {
+        Ok(s) => Data::new(s),
+        Err(e) => {
+            let error_msg = "Could not initialize storage";
+            error!("{}: {}", error_msg, e);
+            return Err(Error::new(ErrorKind::InvalidData, error_msg));
+        }
+    };
+
+    // The S3 backend is shared by several nodes, whose resumable uploads
+    // are only found on the node that created them.
+    if matches!(server_config.storage.backend, StorageBackend::S3)
+        && server_config.upload.node_url.is_empty() {
+        warn!(
+            "upload.nodeUrl is not set, the requests of a resumable upload \
+            must all be routed to the same node"
+        );
+    }
+
+    let args: Vec<String> = env::args().collect();
+
+    if cli::is_cli_mode(&args) {
+        return cli::run(
+            &args, Data::from(repository_arc.clone()), &server_config,
+            &storage_data,
+        );
+    }
+
+    if server_config.url_signing_secret.is_empty() {
+        let error_msg = "No URL signing secret (urlSigningSecret) configured";
+        error!("{}", error_msg);
+        return Err(Error::new(ErrorKind::InvalidData, error_msg));
+    }
+
     let server_state_data = Data::new(ServerState::default());
 
+    api::service::cleanup::start_cleanup(
+        server_state_data.clone(), server_config.clone()
+    );
+
+    api::service::similarity::start_hash_backfill(
+        Data::from(repository_arc.clone()), storage_data.clone(),
+        server_config.clone(),
+    );
+
+    api::service::hotfolder::start_hot_folders(
+        Data::from(repository_arc.clone()), storage_data.clone(),
+        server_state_data.clone(), server_config.clone(),
+    );
+
     HttpServer::new(move || {
         let repository_data: Data<dyn Repository + Sync + Send> = Data::from(
             repository_arc.clone()
@@ -88,7 +139,8 @@ async fn main() -> std::io::Result<()> {
             .app_data(server_state_data.clone())
             .app_data(Data::new(server_config.clone()))
             .app_data(repository_data)
-            .app_data(PayloadConfig::new(1000000 * 250))
+            .app_data(storage_data.clone())
+            .app_data(PayloadConfig::new(server_config.upload.max_size as usize))
             .service(api::echo)
             .service(api::am_i_logged_in)
             .service(api::admin::get_children)
@@ -117,16 +169,33 @@ async fn main() -> std::io::Result<()> {
             .service(api::admin::image::add_image)
             .service(api::admin::image::remove_image)
             .service(api::admin::image::update)
+            .service(api::admin::image::set_focal_point)
+            .service(api::admin::image::get_image_metadata)
+            .service(api::admin::image::get_duplicates)
+            .service(api::admin::image::set_edits)
+            .service(api::admin::image::revert_edits)
             .service(api::admin::folder::get_folder)
             .service(api::admin::folder::add_folder)
             .service(api::admin::folder::update_folder)
             .service(api::admin::folder::remove_folder)
             .service(api::admin::rendition::get_renditions_for_image)
             .service(api::admin::rendition::get_rendition)
+            .service(api::admin::rendition::preview_rendition)
             .service(api::admin::rendition::set_rendition)
             .service(api::admin::rendition::delete_rendition)
+            .service(api::admin::signed_url::create_signed_url)
+            .service(api::admin::upload::get_upload_stats)
+            .service(api::admin::upload::cancel_upload)
+            .service(api::admin::import::import_archive)
             .service(api::image::upload)
+            .service(api::upload::upload_options)
+            .service(api::upload::create_upload)
+            .service(api::upload::get_upload_offset)
+            .service(api::upload::append_upload)
+            .service(api::upload::terminate_upload)
             .service(api::image::download)
+            .service(api::image::get_placeholder)
+            .service(api::image::get_manifest)
             .service(ResourceFiles::new("/", generated))
     })
     .bind(("127.0.0.1", 8080))?
@@ -0,0 +1,140 @@
+use serde::{ Serialize, Deserialize };
+
+use crate::model::transform::CropRect;
+
+/// An operation of an image's edit stack. The operations are applied in
+/// order to the original image before the renditions are generated.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "op", rename_all = "camelCase")]
+pub enum EditOperation {
+    /// Rotates clockwise by a multiple of 90 degrees.
+    Rotate { degrees: i32 },
+    Flip { direction: FlipDirection },
+    /// Crops the image, in the coordinates of the image edited so far.
+    Crop(CropRect),
+    /// Multiplies the brightness, `1.0` leaves the image unchanged.
+    Brightness { factor: f32 },
+    /// Multiplies the contrast, `1.0` leaves the image unchanged.
+    Contrast { factor: f32 },
+    Grayscale,
+    Sharpen,
+    Blur,
+}
+
+#[derive(Copy, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FlipDirection {
+    Horizontal,
+    Vertical,
+}
+
+/// Highest brightness and contrast factor.
+pub const MAX_FACTOR: f32 = 4.0;
+
+impl EditOperation {
+    /// Returns the rotation normalized to 0, 90, 180 or 270 degrees.
+    pub fn normalized_degrees(degrees: i32) -> i32 {
+        degrees.rem_euclid(360)
+    }
+}
+
+/// Validates an edit stack applied to an image of the given dimensions.
+///
+/// Returns the dimensions of the edited image, or the error messages.
+pub fn validate_edits(edits: &[EditOperation], width: u16, height: u16)
+    -> Result<(u16, u16), Vec<String>> {
+    let mut error_msgs: Vec<String> = vec![];
+    let (mut width, mut height) = (width, height);
+
+    for (i, edit) in edits.iter().enumerate() {
+        match edit {
+            EditOperation::Rotate { degrees } => {
+                match EditOperation::normalized_degrees(*degrees) {
+                    90 | 270 => { (width, height) = (height, width); }
+                    0 | 180 => {}
+                    _ => {
+                        error_msgs.push(format!(
+                            "Edit {}: rotation must be a multiple of 90 degrees",
+                            i + 1
+                        ));
+                    }
+                }
+            }
+
+            EditOperation::Crop(rect) => {
+                if rect.is_within(width, height) {
+                    (width, height) = (rect.width, rect.height);
+                } else {
+                    error_msgs.push(format!(
+                        "Edit {}: crop rectangle must lie inside the image ({}x{})",
+                        i + 1, width, height
+                    ));
+                }
+            }
+
+            EditOperation::Brightness { factor }
+                | EditOperation::Contrast { factor } => {
+                if !(0.0..=MAX_FACTOR).contains(factor) {
+                    error_msgs.push(format!(
+                        "Edit {}: factor must be between 0 and {}",
+                        i + 1, MAX_FACTOR
+                    ));
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    if error_msgs.is_empty() {
+        Ok((width, height))
+    } else {
+        Err(error_msgs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crop(x: u16, y: u16, width: u16, height: u16) -> EditOperation {
+        EditOperation::Crop(CropRect { x, y, width, height })
+    }
+
+    #[test]
+    fn returns_the_dimensions_of_the_edited_image() {
+        assert_eq!(validate_edits(&[], 800, 600), Ok((800, 600)));
+
+        let edits = [
+            EditOperation::Rotate { degrees: -90 },
+            crop(100, 200, 500, 400),
+            EditOperation::Rotate { degrees: 180 },
+            EditOperation::Brightness { factor: 1.5 },
+            EditOperation::Grayscale,
+        ];
+
+        assert_eq!(validate_edits(&edits, 800, 600), Ok((500, 400)));
+    }
+
+    #[test]
+    fn checks_crops_against_the_image_edited_so_far() {
+        // After the rotation the image is 600x800.
+        let edits = [ EditOperation::Rotate { degrees: 90 }, crop(0, 0, 600, 800) ];
+        assert_eq!(validate_edits(&edits, 800, 600), Ok((600, 800)));
+
+        let edits = [ EditOperation::Rotate { degrees: 90 }, crop(0, 0, 800, 600) ];
+        assert!(validate_edits(&edits, 800, 600).is_err());
+    }
+
+    #[test]
+    fn reports_every_invalid_edit() {
+        let edits = [
+            EditOperation::Rotate { degrees: 45 },
+            crop(0, 0, 0, 10),
+            EditOperation::Contrast { factor: MAX_FACTOR + 1.0 },
+            EditOperation::Brightness { factor: -0.5 },
+        ];
+
+        assert_eq!(validate_edits(&edits, 800, 600).map_err(|e| e.len()), Err(4));
+    }
+}
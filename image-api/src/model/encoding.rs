@@ -5,7 +5,7 @@ use lazy_static::lazy_static;
 
 lazy_static! {
     pub static ref RE: Regex = Regex::new(
-        r"\.(png|gif|jpg|jpeg|webp|tif|bmp|raw|cr2|nef|orf|sr2|eps|svg)$"
+        r"\.(png|gif|jpg|jpeg|webp|avif|tif|bmp|raw|cr2|nef|orf|sr2|eps|svg)$"
     ).unwrap();
 }
 
@@ -16,7 +16,7 @@ lazy_static! {
 /// 40 - 59: Images with Lossy compression
 /// 60 - 79: Images that can have both lossy and lossless compression
 /// 80+: Vector graphics images
-#[derive(Copy, Clone, Serialize, Deserialize)]
+#[derive(Copy, Clone, Serialize, Deserialize, PartialEq)]
 pub enum Encoding {
     NONE = 0,
     TIF = 1,
@@ -33,6 +33,7 @@ pub enum Encoding {
     JPG = 40,
 
     WEBP = 60,
+    AVIF = 61,
 
     EPS = 80,
     SVG = 81
@@ -64,6 +65,7 @@ impl Encoding {
                             ".gif" => Encoding::GIF,
                             ".jpg" => Encoding::JPG,
                             ".webp" => Encoding::WEBP,
+                            ".avif" => Encoding::AVIF,
                             ".eps" => Encoding::EPS,
                             ".svg" => Encoding::SVG,
                             &_ => Encoding::NONE,
@@ -78,6 +80,13 @@ impl Encoding {
         }
     }
 
+    /// Inits Encoding instance from its integer value, as stored in the
+    /// database. Unknown values are `Encoding::NONE`.
+    pub fn from_id(id: u8) -> Self {
+        Encoding::iter().find(|e| **e as u8 == id).copied()
+            .unwrap_or(Encoding::NONE)
+    }
+
     /// Returns `true` if the supplied text contains a valid extension.
     /// Returns `false' otherwise.
     pub fn match_extension(text: &str) -> bool {
@@ -88,7 +97,7 @@ impl Encoding {
 
     /// Returns an iterator to the enum variants.
     pub fn iter() -> Iter<'static, Encoding> {
-        static ENCODINGS: [Encoding; 14] = [
+        static ENCODINGS: [Encoding; 15] = [
             Encoding::TIF,
             Encoding::BMP,
             Encoding::RAW,
@@ -100,6 +109,7 @@ impl Encoding {
             Encoding::GIF,
             Encoding::JPG,
             Encoding::WEBP,
+            Encoding::AVIF,
             Encoding::EPS,
             Encoding::SVG,
             Encoding::NONE,
@@ -125,6 +135,7 @@ impl Encoding {
             GIF => String::from(".gif"),
             JPG => String::from(".jpg"),
             WEBP => String::from(".webp"),
+            AVIF => String::from(".avif"),
             EPS => String::from(".eps"),
             SVG => String::from(".svg"),
         }
@@ -147,6 +158,7 @@ impl Encoding {
             GIF => String::from("image/gif"),
             JPG => String::from("image/jpeg"),
             WEBP => String::from("image/webp"),
+            AVIF => String::from("image/avif"),
             EPS => String::from("image/eps"),
             SVG => String::from("image/svg"),
         }
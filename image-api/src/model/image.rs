@@ -1,7 +1,7 @@
 use serde::{ Serialize, Deserialize};
 use serde_json;
 use chrono::{ DateTime, Utc };
-use crate::model::encoding::Encoding;
+use crate::model::{ encoding::Encoding, edit::EditOperation };
 
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -16,12 +16,51 @@ pub struct Image {
     pub is_published: bool,
     pub project_id: u32,
     pub folder_id: u32,
+    /// Point of interest that is kept in frame by the cropping renditions.
+    #[serde(default)]
+    pub focal_point: Option<FocalPoint>,
+    /// Generated when the image is added.
+    #[serde(default)]
+    pub placeholder: Option<Placeholder>,
+    /// Edits applied to the original image, in order. `width` and `height`
+    /// are the dimensions of the edited image.
+    #[serde(default)]
+    pub edits: Vec<EditOperation>,
+    /// SHA-256 of the original file, which is stored once per content and
+    /// shared by identical uploads. `None` for images stored by their ID.
+    #[serde(default)]
+    pub content_hash: Option<String>,
     pub created_on: DateTime<Utc>,
     pub created_by: u16,
     pub modified_on: DateTime<Utc>,
     pub modified_by: u16,
 }
 
+/// A point on the image, the coordinates are fractions (0.0 - 1.0) of the
+/// image's width and height.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct FocalPoint {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl FocalPoint {
+    pub fn is_valid(&self) -> bool {
+        (0.0..=1.0).contains(&self.x) && (0.0..=1.0).contains(&self.y)
+    }
+}
+
+/// Low-quality placeholders shown while the actual image is loading.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Placeholder {
+    pub blurhash: String,
+    /// Tiny JPEG, as a `data:` URI.
+    pub lqip: String,
+    /// Hex color (`#rrggbb`).
+    pub dominant_color: String,
+}
+
 impl std::fmt::Display for Image {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "Image {}", serde_json::to_string(&self).unwrap())
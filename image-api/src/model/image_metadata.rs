@@ -0,0 +1,35 @@
+use serde::{ Serialize, Deserialize };
+use serde_json;
+use chrono::NaiveDateTime;
+
+/// Descriptive metadata embedded in an image file (EXIF, IPTC and XMP).
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageMetadata {
+    pub image_id: u32,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub lens: Option<String>,
+    /// Date and time the photo was taken, in the camera's local time.
+    pub capture_date: Option<NaiveDateTime>,
+    pub gps_latitude: Option<f64>,
+    pub gps_longitude: Option<f64>,
+    /// Altitude in meters above sea level.
+    pub gps_altitude: Option<f64>,
+    pub copyright: Option<String>,
+    pub creator: Option<String>,
+    pub caption: Option<String>,
+    pub keywords: Vec<String>,
+    /// `xmpRights:UsageTerms`
+    pub usage_terms: Option<String>,
+    /// `xmpRights:WebStatement`
+    pub web_statement: Option<String>,
+    /// `xmpRights:Marked`, `true` for rights-managed images.
+    pub rights_marked: Option<bool>,
+}
+
+impl std::fmt::Display for ImageMetadata {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "ImageMetadata {}", serde_json::to_string(&self).unwrap())
+    }
+}
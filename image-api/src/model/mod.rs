@@ -10,4 +10,8 @@ pub mod user_listing;
 pub mod upload_image;
 pub mod role;
 pub mod error;
+pub mod transform;
+pub mod image_metadata;
+pub mod watermark;
+pub mod edit;
 
@@ -2,7 +2,10 @@ use serde::{ Serialize, Deserialize };
 use serde_json;
 use chrono::{ DateTime, Utc };
 
-use crate::model::encoding::Encoding;
+use crate::model::{
+    encoding::Encoding, image::Image,
+    transform::{ Fit, Gravity, CropRect }, watermark::Watermark,
+};
 
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -15,6 +18,29 @@ pub struct Rendition {
     pub slug: String,
     pub is_published: bool,
     pub encoding: Encoding,
+    /// How the (cropped) source image is fitted into the dimensions.
+    #[serde(default)]
+    pub fit: Fit,
+    /// Part of the image that is kept by the `cover` and `crop` fit modes.
+    #[serde(default)]
+    pub gravity: Gravity,
+    /// Rectangle of the source image that the rendition is made from.
+    #[serde(default)]
+    pub crop: Option<CropRect>,
+    /// Encoding quality (1 - 100), the project default is used when `None`.
+    #[serde(default)]
+    pub quality: Option<u8>,
+    /// Progressive JPEG, the project default is used when `None`.
+    #[serde(default)]
+    pub progressive: Option<bool>,
+    /// Removes the original's metadata, the project default is used when
+    /// `None`.
+    #[serde(default)]
+    pub strip_metadata: Option<bool>,
+    /// Overrides the project's watermark, the project's watermark is used
+    /// when `None`.
+    #[serde(default)]
+    pub watermark: Option<Watermark>,
     pub created_on: DateTime<Utc>,
     pub created_by: u16,
     pub modified_on: DateTime<Utc>,
@@ -38,6 +64,13 @@ impl std::default::Default for Rendition {
             slug: String::from(""),
             is_published: true,
             encoding: Encoding::JPG,
+            fit: Fit::default(),
+            gravity: Gravity::default(),
+            crop: None,
+            quality: None,
+            progressive: None,
+            strip_metadata: None,
+            watermark: None,
             created_on: Utc::now(),
             created_by: 0,
             modified_on: Utc::now(),
@@ -45,3 +78,66 @@ impl std::default::Default for Rendition {
         }
     }
 }
+
+pub fn validate_rendition(rendition: &Rendition, image: &Image)
+    -> (bool, Vec<String>) {
+    let mut valid: bool = true;
+    let mut error_msgs: Vec<String> = vec![];
+
+    if rendition.slug.eq("") {
+        valid = false;
+        error_msgs.push(String::from("Slug cannot be empty"));
+    }
+
+    if let Some(quality) = rendition.quality {
+        if quality < 1 || quality > 100 {
+            valid = false;
+            error_msgs.push(String::from("Quality must be between 1 and 100"));
+        }
+    }
+
+    if let Some(watermark) = &rendition.watermark {
+        let watermark_errors = watermark.validate();
+
+        if !watermark_errors.is_empty() {
+            valid = false;
+            error_msgs.extend(watermark_errors);
+        }
+    }
+
+    // The default rendition always has the dimensions of the image.
+    if rendition.slug.eq("default") {
+        return (valid, error_msgs);
+    }
+
+    if rendition.width == 0 || rendition.height == 0 {
+        valid = false;
+        error_msgs.push(String::from("Width and height must be greater than 0"));
+    }
+
+    let (mut src_width, mut src_height) = (image.width, image.height);
+
+    if let Some(crop) = rendition.crop {
+        if crop.is_within(image.width, image.height) {
+            src_width = crop.width;
+            src_height = crop.height;
+        } else {
+            valid = false;
+            error_msgs.push(format!(
+                "Crop rectangle must lie inside the image ({}x{})",
+                image.width, image.height
+            ));
+        }
+    }
+
+    if rendition.fit == Fit::Crop
+        && (rendition.width > src_width || rendition.height > src_height) {
+        valid = false;
+        error_msgs.push(format!(
+            "Crop dimensions cannot be larger than the source ({}x{})",
+            src_width, src_height
+        ));
+    }
+
+    (valid, error_msgs)
+}
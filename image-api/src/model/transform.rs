@@ -0,0 +1,314 @@
+use serde::{ Serialize, Deserialize };
+use qstring::QString;
+
+use crate::model::encoding::Encoding;
+
+/// Largest width or height that can be requested through the transformation
+/// parameters.
+pub const MAX_TRANSFORM_DIMENSION: u16 = 5000;
+
+/// How an image is fitted into the requested dimensions.
+#[derive(Copy, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    /// Scales the image to fit inside the dimensions, keeping aspect ratio.
+    #[serde(alias = "contain")]
+    Fit,
+
+    /// Scales the image to cover the dimensions, cropping the excess.
+    #[serde(alias = "fill")]
+    Cover,
+
+    /// Scales the image to the exact dimensions, ignoring aspect ratio.
+    Exact,
+
+    /// Cuts the dimensions out of the image without scaling it.
+    Crop,
+}
+
+impl Default for Fit {
+    fn default() -> Self { Fit::Fit }
+}
+
+impl Fit {
+    pub fn from(text: &str) -> Option<Self> {
+        match text.to_lowercase().as_str() {
+            "fit" | "contain" => Some(Fit::Fit),
+            "cover" | "fill" => Some(Fit::Cover),
+            "exact" => Some(Fit::Exact),
+            "crop" => Some(Fit::Crop),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Fit::Fit => "fit",
+            Fit::Cover => "cover",
+            Fit::Exact => "exact",
+            Fit::Crop => "crop",
+        }
+    }
+}
+
+/// The part of the image that is kept when it is cropped (`Fit::Cover` and
+/// `Fit::Crop`).
+#[derive(Copy, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Gravity {
+    Center,
+    North,
+    South,
+    East,
+    West,
+    NorthEast,
+    NorthWest,
+    SouthEast,
+    SouthWest,
+}
+
+impl Default for Gravity {
+    fn default() -> Self { Gravity::Center }
+}
+
+impl Gravity {
+    pub fn from(text: &str) -> Option<Self> {
+        match text.to_lowercase().as_str() {
+            "center" | "centre" => Some(Gravity::Center),
+            "north" | "n" => Some(Gravity::North),
+            "south" | "s" => Some(Gravity::South),
+            "east" | "e" => Some(Gravity::East),
+            "west" | "w" => Some(Gravity::West),
+            "northeast" | "ne" => Some(Gravity::NorthEast),
+            "northwest" | "nw" => Some(Gravity::NorthWest),
+            "southeast" | "se" => Some(Gravity::SouthEast),
+            "southwest" | "sw" => Some(Gravity::SouthWest),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Gravity::Center => "center",
+            Gravity::North => "north",
+            Gravity::South => "south",
+            Gravity::East => "east",
+            Gravity::West => "west",
+            Gravity::NorthEast => "northeast",
+            Gravity::NorthWest => "northwest",
+            Gravity::SouthEast => "southeast",
+            Gravity::SouthWest => "southwest",
+        }
+    }
+}
+
+/// A rectangle of the source image (in pixels) that is cut out before the
+/// image is resized.
+#[derive(Copy, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CropRect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl CropRect {
+    /// Returns `true` if the rectangle is not empty and lies inside an image
+    /// of the given dimensions.
+    pub fn is_within(&self, width: u16, height: u16) -> bool {
+        self.width > 0 && self.height > 0
+            && self.x as u32 + self.width as u32 <= width as u32
+            && self.y as u32 + self.height as u32 <= height as u32
+    }
+
+    /// Returns the rectangle in the `x,y,width,height` notation.
+    pub fn key(&self) -> String {
+        format!("{}-{}-{}-{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+/// Ad-hoc transformation requested through the query string of the public
+/// image URL, e.g.: `?w=640&h=360&fit=cover&g=north&q=75&fmt=webp`.
+#[derive(Clone)]
+pub struct TransformParams {
+    pub width: Option<u16>,
+    pub height: Option<u16>,
+    pub fit: Fit,
+    pub gravity: Gravity,
+    pub quality: Option<u8>,
+    pub encoding: Option<Encoding>,
+}
+
+impl TransformParams {
+    /// Parses the transformation parameters from a query string.
+    ///
+    /// Returns `Ok(None)` when the query string has no transformation
+    /// parameters and `Err` with a message when a parameter is invalid.
+    pub fn from_query(query_string: &str) -> Result<Option<Self>, String> {
+        let qs = QString::from(query_string);
+
+        let w = qs.get("w");
+        let h = qs.get("h");
+        let fit = qs.get("fit");
+        let g = qs.get("g");
+        let q = qs.get("q");
+        let fmt = qs.get("fmt");
+
+        if w.is_none() && h.is_none() && fit.is_none() && g.is_none()
+            && q.is_none() && fmt.is_none() {
+            return Ok(None);
+        }
+
+        let mut params = TransformParams {
+            width: None,
+            height: None,
+            fit: Fit::default(),
+            gravity: Gravity::default(),
+            quality: None,
+            encoding: None,
+        };
+
+        if let Some(w_str) = w {
+            params.width = Some(Self::parse_dimension("w", w_str)?);
+        }
+
+        if let Some(h_str) = h {
+            params.height = Some(Self::parse_dimension("h", h_str)?);
+        }
+
+        if let Some(fit_str) = fit {
+            match Fit::from(fit_str) {
+                Some(f) => { params.fit = f; }
+                None => {
+                    return Err(format!("Invalid fit: \"{}\"", fit_str));
+                }
+            }
+        }
+
+        if let Some(g_str) = g {
+            match Gravity::from(g_str) {
+                Some(gravity) => { params.gravity = gravity; }
+                None => {
+                    return Err(format!("Invalid gravity: \"{}\"", g_str));
+                }
+            }
+        }
+
+        if params.fit == Fit::Crop
+            && (params.width.is_none() || params.height.is_none()) {
+            return Err(String::from("Crop requires both \"w\" and \"h\""));
+        }
+
+        if let Some(q_str) = q {
+            match q_str.parse::<u8>() {
+                Ok(quality) if quality >= 1 && quality <= 100 => {
+                    params.quality = Some(quality);
+                }
+
+                _ => {
+                    return Err(String::from(
+                        "Quality must be a number between 1 and 100"
+                    ));
+                }
+            }
+        }
+
+        if let Some(fmt_str) = fmt {
+            match Encoding::from(format!(".{}", fmt_str.to_lowercase()).as_str()) {
+                encoding @ (Encoding::JPG | Encoding::PNG | Encoding::GIF
+                    | Encoding::WEBP | Encoding::AVIF) => {
+                    params.encoding = Some(encoding);
+                }
+
+                _ => {
+                    return Err(format!("Unsupported format: \"{}\"", fmt_str));
+                }
+            }
+        }
+
+        Ok(Some(params))
+    }
+
+    fn parse_dimension(name: &str, value: &str) -> Result<u16, String> {
+        match value.parse::<u16>() {
+            Ok(d) if d > 0 && d <= MAX_TRANSFORM_DIMENSION => Ok(d),
+            _ => Err(format!(
+                "\"{}\" must be a number between 1 and {}",
+                name, MAX_TRANSFORM_DIMENSION
+            )),
+        }
+    }
+
+    /// Returns the size in the `WIDTHxHEIGHT` notation used by the
+    /// `allowedSizes` project config. A missing dimension is left empty.
+    pub fn size(&self) -> String {
+        format!(
+            "{}x{}",
+            self.width.map(|w| w.to_string()).unwrap_or_default(),
+            self.height.map(|h| h.to_string()).unwrap_or_default(),
+        )
+    }
+
+    /// Returns the normalized parameters. Used as the rendition cache key and
+    /// as the message for URL signatures.
+    pub fn normalized(&self) -> String {
+        let mut key = format!(
+            "w{}_h{}_{}",
+            self.width.unwrap_or(0),
+            self.height.unwrap_or(0),
+            self.fit.name(),
+        );
+
+        // Left out for the default gravity to keep the existing keys.
+        if self.gravity != Gravity::Center {
+            key.push_str(format!("_g{}", self.gravity.name()).as_str());
+        }
+
+        if let Some(q) = self.quality {
+            key.push_str(format!("_q{}", q).as_str());
+        }
+
+        if let Some(encoding) = self.encoding {
+            key.push_str(format!("_{}", &encoding.extension()[1..]).as_str());
+        }
+
+        key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(query_string: &str) -> TransformParams {
+        TransformParams::from_query(query_string).unwrap().unwrap()
+    }
+
+    #[test]
+    fn ignores_queries_without_transformations() {
+        assert!(TransformParams::from_query("").unwrap().is_none());
+        assert!(TransformParams::from_query("sig=ab&exp=1").unwrap().is_none());
+    }
+
+    #[test]
+    fn parses_and_normalizes_the_parameters() {
+        assert_eq!(parse("w=300").normalized(), "w300_h0_fit");
+        assert_eq!(
+            parse("fmt=WEBP&q=80&g=ne&fit=crop&h=200&w=300").normalized(),
+            "w300_h200_crop_gnortheast_q80_webp"
+        );
+        assert_eq!(parse("h=200&fit=cover").size(), "x200");
+    }
+
+    #[test]
+    fn rejects_invalid_parameters() {
+        let max = format!("w={}", MAX_TRANSFORM_DIMENSION as u32 + 1);
+
+        for query_string in [
+            "w=0", "w=abc", max.as_str(), "h=-1", "w=1&fit=stretch", "w=1&g=up",
+            "w=1&q=0", "w=1&q=101", "w=1&fmt=bmp", "w=100&fit=crop",
+        ] {
+            assert!(TransformParams::from_query(query_string).is_err(), "{}", query_string);
+        }
+    }
+}
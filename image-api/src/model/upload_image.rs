@@ -1,7 +1,5 @@
 use serde::Deserialize;
 
-use crate::model::encoding::Encoding;
-
 /**
  * Represents data from the "New Image" form after the iamge has been uploaded.
  */
@@ -12,7 +10,6 @@ pub struct UploadImage {
     pub name: String,
     pub title: String,
     pub slug: String,
-    pub encoding: Encoding,
     pub project_id: u32,
     pub folder_id: u32,
 }
@@ -0,0 +1,74 @@
+use serde::{ Serialize, Deserialize };
+
+use crate::model::transform::Gravity;
+
+/// Image composited over the renditions, configured per project (see
+/// `ProjectConfig::watermark`) and overridable per rendition.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Watermark {
+    /// `false` disables the project's watermark for a rendition.
+    pub enabled: bool,
+    /// ID of the image (e.g. a PNG with transparency) used as watermark.
+    /// Rendition watermarks fall back to the project's image when 0.
+    pub image_id: u32,
+    /// Where the watermark is placed, ignored when tiled.
+    pub gravity: Gravity,
+    /// Opacity of the watermark (0.0 - 1.0).
+    pub opacity: f32,
+    /// Width of the watermark, as a fraction (0.0 - 1.0) of the rendition's
+    /// width.
+    pub scale: f32,
+    /// Repeats the watermark over the whole rendition.
+    pub tile: bool,
+}
+
+impl Default for Watermark {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            image_id: 0,
+            gravity: Gravity::SouthEast,
+            opacity: 0.5,
+            scale: 0.25,
+            tile: false,
+        }
+    }
+}
+
+impl Watermark {
+    /// Returns the watermark that applies to a rendition, the rendition's
+    /// watermark overrides the project's.
+    pub fn resolve(rendition: Option<&Watermark>, project: Option<&Watermark>)
+        -> Option<Watermark> {
+        let mut watermark = rendition.or(project)?.clone();
+
+        if watermark.image_id == 0 {
+            watermark.image_id = project.map(|w| w.image_id).unwrap_or_default();
+        }
+
+        if !watermark.enabled || watermark.image_id == 0 {
+            return None;
+        }
+
+        Some(watermark)
+    }
+
+    pub fn validate(&self) -> Vec<String> {
+        let mut error_msgs: Vec<String> = vec![];
+
+        if !(0.0..=1.0).contains(&self.opacity) {
+            error_msgs.push(String::from(
+                "Watermark opacity must be between 0 and 1"
+            ));
+        }
+
+        if self.scale <= 0.0 || self.scale > 1.0 {
+            error_msgs.push(String::from(
+                "Watermark scale must be greater than 0 and at most 1"
+            ));
+        }
+
+        error_msgs
+    }
+}
@@ -1,7 +1,11 @@
 pub mod db;
 
 use crate::{
-    server::db::DBError, model::{ encoding::Encoding, image::Image },
+    server::db::DBError,
+    model::{
+        encoding::Encoding, image::{ Image, FocalPoint, Placeholder },
+        edit::EditOperation,
+    },
 };
 
 pub trait ImageRepository {
@@ -13,6 +17,10 @@ pub trait ImageRepository {
     fn get_all_paged(&mut self, page: u32, page_length: u32)
         -> Result<Vec<Image>, DBError>;
 
+    /// Returns the images with the given IDs, in no particular order. IDs of
+    /// images that don't exist are ignored.
+    fn get_all_by_ids(&mut self, ids: &[u32]) -> Result<Vec<Image>, DBError>;
+
     /// Returns images inside a folder from folder id.
     /// 
     /// # Arguments
@@ -67,6 +75,42 @@ pub trait ImageRepository {
         Result<Option<u32>, DBError>;
 
     fn update(&mut self, image: Image) -> Result<String, String>;
+
+    /// Sets (or clears, with `None`) the focal point of an image.
+    fn set_focal_point(&mut self, id: u32, focal_point: Option<FocalPoint>)
+        -> Result<String, String>;
+
+    /// Stores the generated placeholders of an image.
+    fn set_placeholder(&mut self, id: u32, placeholder: &Placeholder)
+        -> Result<String, String>;
+
+    /// Replaces the edit stack of an image along with the dimensions of the
+    /// edited image, and clears its (outdated) placeholders.
+    fn set_edits(
+        &mut self, id: u32, edits: &Vec<EditOperation>, width: u16, height: u16
+    ) -> Result<String, String>;
+
+    /// Stores the perceptual hash (see `service::similarity`) of an image.
+    fn set_perceptual_hash(&mut self, id: u32, hash: u64)
+        -> Result<String, String>;
+
+    /// Returns the IDs and perceptual hashes of the images in a project, or
+    /// of all the images when `project_id` is `None`. The hash is `None` for
+    /// images it was not computed for.
+    fn get_perceptual_hashes(&mut self, project_id: Option<u32>)
+        -> Result<Vec<(u32, Option<u64>)>, DBError>;
+
+    /// Returns the number of images whose original file has the given
+    /// content hash (see `Image::content_hash`).
+    fn count_content_references(&mut self, content_hash: &str)
+        -> Result<u32, DBError>;
+
+    /// Takes the lock of a content hash, shared by all the servers using the
+    /// database, waiting for it if it's taken. The lock is held until
+    /// `unlock_content` is called on the same repository.
+    fn lock_content(&mut self, content_hash: &str) -> Result<(), DBError>;
+    fn unlock_content(&mut self, content_hash: &str);
+
     fn remove(&mut self, id: Image) -> Result<String, String>;
     fn remove_item(&mut self, id: u32) -> Result<String, String>;
 }
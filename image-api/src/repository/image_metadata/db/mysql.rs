@@ -0,0 +1,143 @@
+use std::result::Result;
+
+use log::{ info, debug, error };
+use mysql::*;
+use mysql::prelude::*;
+use chrono::NaiveDateTime;
+
+use crate::{
+    db::utils::mysql::{ get_row_from_query, get_rows_from_query },
+    model::image_metadata::ImageMetadata,
+    repository::image_metadata::ImageMetadataRepository, server::db::DBError,
+};
+
+pub struct MySQLImageMetadataRepository {
+    pub connection: PooledConn,
+}
+
+fn get_metadata_from_row(mut row: Row) -> ImageMetadata {
+    let keywords: Vec<String> = row.take::<Option<String>, _>("KEYWORDS")
+        .flatten()
+        .and_then(|k| serde_json::from_str(k.as_str()).ok())
+        .unwrap_or_default();
+
+    ImageMetadata {
+        image_id: row.take("IMAGE_ID").unwrap(),
+        camera_make: row.take("CAMERA_MAKE").unwrap_or_default(),
+        camera_model: row.take("CAMERA_MODEL").unwrap_or_default(),
+        lens: row.take("LENS").unwrap_or_default(),
+        capture_date: row.take("CAPTURE_DATE").unwrap_or_default(),
+        gps_latitude: row.take("GPS_LATITUDE").unwrap_or_default(),
+        gps_longitude: row.take("GPS_LONGITUDE").unwrap_or_default(),
+        gps_altitude: row.take("GPS_ALTITUDE").unwrap_or_default(),
+        copyright: row.take("COPYRIGHT").unwrap_or_default(),
+        creator: row.take("CREATOR").unwrap_or_default(),
+        caption: row.take("CAPTION").unwrap_or_default(),
+        keywords,
+        usage_terms: row.take("USAGE_TERMS").unwrap_or_default(),
+        web_statement: row.take("WEB_STATEMENT").unwrap_or_default(),
+        rights_marked: row.take("RIGHTS_MARKED").unwrap_or_default(),
+    }
+}
+
+impl ImageMetadataRepository for MySQLImageMetadataRepository {
+    fn get(&mut self, image_id: u32) -> Result<ImageMetadata, DBError> {
+        match get_row_from_query(
+            &mut self.connection,
+            r"SELECT
+                IMAGE_ID, CAMERA_MAKE, CAMERA_MODEL, LENS, CAPTURE_DATE,
+                GPS_LATITUDE, GPS_LONGITUDE, GPS_ALTITUDE, COPYRIGHT, CREATOR,
+                CAPTION, KEYWORDS, USAGE_TERMS, WEB_STATEMENT, RIGHTS_MARKED
+            FROM IMAGE_METADATA WHERE IMAGE_ID = :image_id",
+            params! { "image_id" => image_id },
+        ) {
+            Ok(Some(row)) => Ok(get_metadata_from_row(row)),
+            Ok(None) => Err(DBError::NotFound),
+            Err(e) => {
+                error!("Error while getting image metadata: {}", e);
+
+                Err(DBError::OtherError)
+            }
+        }
+    }
+
+    fn save(&mut self, metadata: ImageMetadata) -> Result<String, String> {
+        debug!("Saving metadata of image: {}", metadata.image_id);
+
+        let keywords: Option<String> = if metadata.keywords.is_empty() {
+            None
+        } else {
+            serde_json::to_string(&metadata.keywords).ok()
+        };
+
+        match self.connection.exec_drop(
+            r"REPLACE INTO IMAGE_METADATA (
+                IMAGE_ID, CAMERA_MAKE, CAMERA_MODEL, LENS, CAPTURE_DATE,
+                GPS_LATITUDE, GPS_LONGITUDE, GPS_ALTITUDE, COPYRIGHT, CREATOR,
+                CAPTION, KEYWORDS, USAGE_TERMS, WEB_STATEMENT, RIGHTS_MARKED
+            ) VALUES (
+                :image_id, :camera_make, :camera_model, :lens, :capture_date,
+                :gps_latitude, :gps_longitude, :gps_altitude, :copyright,
+                :creator, :caption, :keywords, :usage_terms, :web_statement,
+                :rights_marked
+            )",
+            params! {
+                "image_id" => metadata.image_id,
+                "camera_make" => &metadata.camera_make,
+                "camera_model" => &metadata.camera_model,
+                "lens" => &metadata.lens,
+                "capture_date" => &metadata.capture_date,
+                "gps_latitude" => metadata.gps_latitude,
+                "gps_longitude" => metadata.gps_longitude,
+                "gps_altitude" => metadata.gps_altitude,
+                "copyright" => &metadata.copyright,
+                "creator" => &metadata.creator,
+                "caption" => &metadata.caption,
+                "keywords" => keywords,
+                "usage_terms" => &metadata.usage_terms,
+                "web_statement" => &metadata.web_statement,
+                "rights_marked" => metadata.rights_marked,
+            }
+        ) {
+            Ok(_) => {
+                info!("Image metadata saved (ID: {})", metadata.image_id);
+
+                Ok(String::from("Successfully saved image metadata."))
+            }
+
+            Err(e) => {
+                error!("Error while saving image metadata: {}", e);
+
+                Err(String::from("Unable to save image metadata."))
+            }
+        }
+    }
+
+    fn get_image_ids_captured_between(
+        &mut self, project_id: u32, from: Option<NaiveDateTime>,
+        to: Option<NaiveDateTime>,
+    ) -> Result<Vec<u32>, DBError> {
+        match get_rows_from_query(
+            &mut self.connection,
+            r"SELECT M.IMAGE_ID
+            FROM IMAGE_METADATA M, IMAGE I
+            WHERE M.IMAGE_ID = I.ID AND I.PROJECT_ID = :project_id
+                AND M.CAPTURE_DATE IS NOT NULL
+                AND (:from IS NULL OR M.CAPTURE_DATE >= :from)
+                AND (:to IS NULL OR M.CAPTURE_DATE <= :to)",
+            params! { "project_id" => project_id, "from" => from, "to" => to },
+        ) {
+            Ok(rows) => Ok(
+                rows.into_iter()
+                    .filter_map(|mut row| row.take("IMAGE_ID"))
+                    .collect()
+            ),
+
+            Err(e) => {
+                error!("Error while getting images by capture date: {}", e);
+
+                Err(DBError::OtherError)
+            }
+        }
+    }
+}
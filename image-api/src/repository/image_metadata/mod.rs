@@ -0,0 +1,19 @@
+pub mod db;
+
+use chrono::NaiveDateTime;
+
+use crate::{ server::db::DBError, model::image_metadata::ImageMetadata };
+
+pub trait ImageMetadataRepository {
+    fn get(&mut self, image_id: u32) -> Result<ImageMetadata, DBError>;
+
+    /// Inserts the metadata, replacing the existing metadata of the image.
+    fn save(&mut self, metadata: ImageMetadata) -> Result<String, String>;
+
+    /// Returns the ids of the project's images captured between the dates
+    /// (both inclusive). A missing date leaves the range open on that side.
+    fn get_image_ids_captured_between(
+        &mut self, project_id: u32, from: Option<NaiveDateTime>,
+        to: Option<NaiveDateTime>,
+    ) -> Result<Vec<u32>, DBError>;
+}
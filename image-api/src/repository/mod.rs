@@ -5,6 +5,7 @@ pub mod rendition;
 pub mod project;
 pub mod folder;
 pub mod role;
+pub mod image_metadata;
 
 use mysql::Pool;
 
@@ -13,6 +14,9 @@ use self::{
     folder::{ FolderRepository, db::mysql::MySQLFolderRepository },
     role::{ RoleRepository, db::mysql::MySQLRoleRepository },
     image::{ ImageRepository, db::mysql::MySQLImageRepository },
+    image_metadata::{
+        ImageMetadataRepository, db::mysql::MySQLImageMetadataRepository,
+    },
     project::{ ProjectRepository, db::mysql::MySQLProjectRepository },
     rendition::{ RenditionRepository, db::mysql::MySQLRenditionRepository },
     user::{ UserRepository, db::mysql::MySQLUserRepository },
@@ -22,6 +26,8 @@ pub trait Repository {
     fn get_role_repo(&self) -> Result<Box::<dyn RoleRepository>, DBError>;
     fn get_folder_repo(&self) -> Result<Box::<dyn FolderRepository>, DBError>;
     fn get_image_repo(&self) -> Result<Box::<dyn ImageRepository>, DBError>;
+    fn get_image_metadata_repo(&self)
+        -> Result<Box::<dyn ImageMetadataRepository>, DBError>;
     fn get_project_repo(&self) -> Result<Box::<dyn ProjectRepository>, DBError>;
     fn get_rendition_repo(&self) -> Result<Box::<dyn RenditionRepository>, DBError>;
     fn get_user_repo(&self) -> Result<Box::<dyn UserRepository>, DBError>;
@@ -60,6 +66,18 @@ impl Repository for MySQLRepository {
         }
     }
 
+    fn get_image_metadata_repo(&self)
+        -> Result<Box::<dyn ImageMetadataRepository>, DBError> {
+        match self.connection_pool.get_conn() {
+            Ok(connection) => Ok(
+                Box::new(MySQLImageMetadataRepository { connection })
+            ),
+            Err(e) => Err(
+                mysql_to_db_error("Error while creating connection", e)
+            ),
+        }
+    }
+
     fn get_project_repo(&self) -> Result<Box::<dyn ProjectRepository>, DBError> {
         match self.connection_pool.get_conn() {
             Ok(connection) => Ok(Box::new(MySQLProjectRepository { connection })),
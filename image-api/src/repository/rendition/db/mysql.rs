@@ -4,6 +4,7 @@ use crate::repository::rendition::{
 };
 use crate::{
     server::db::DBError,
+    model::{ transform::{ Fit, Gravity, CropRect }, watermark::Watermark },
     db::utils::mysql::{ get_rows_from_query, get_row_from_query },
 };
 use chrono::{ Local, TimeZone };
@@ -11,6 +12,40 @@ use mysql::*;
 use mysql::prelude::*;
 use log::{ info, debug, error };
 
+/// Reads the fit mode, gravity and crop rectangle of a rendition row.
+fn get_geometry_from_row(row: &mut Row) -> (Fit, Gravity, Option<CropRect>) {
+    let fit = row.take::<Option<String>, _>("FIT").flatten()
+        .and_then(|f| Fit::from(f.as_str()))
+        .unwrap_or_default();
+
+    let gravity = row.take::<Option<String>, _>("GRAVITY").flatten()
+        .and_then(|g| Gravity::from(g.as_str()))
+        .unwrap_or_default();
+
+    let mut crop_value = |name: &str| -> Option<u16> {
+        row.take::<Option<u16>, _>(name).flatten()
+    };
+
+    let crop = match (
+        crop_value("CROP_X"), crop_value("CROP_Y"),
+        crop_value("CROP_WIDTH"), crop_value("CROP_HEIGHT"),
+    ) {
+        (Some(x), Some(y), Some(width), Some(height)) => {
+            Some(CropRect { x, y, width, height })
+        }
+
+        _ => None,
+    };
+
+    (fit, gravity, crop)
+}
+
+/// Reads the watermark override (stored as JSON) of a rendition row.
+fn get_watermark_from_row(row: &mut Row) -> Option<Watermark> {
+    row.take::<Option<String>, _>("WATERMARK").flatten()
+        .and_then(|w| serde_json::from_str(&w).ok())
+}
+
 fn get_rendition_from_row(row_wrapped: Result<Option<Row>, Error>) -> Result<Rendition, DBError> {
     match row_wrapped {
         Ok (row_option) => {
@@ -20,6 +55,7 @@ fn get_rendition_from_row(row_wrapped: Result<Option<Row>, Error>) -> Result<Ren
 
                     let created_on = row.take("CREATED_ON").unwrap();
                     let updated_on = row.take("MODIFIED_ON").unwrap();
+                    let (fit, gravity, crop) = get_geometry_from_row(&mut row);
 
                     Ok(Rendition {
                         id: row.take("ID").unwrap(),
@@ -28,8 +64,18 @@ fn get_rendition_from_row(row_wrapped: Result<Option<Row>, Error>) -> Result<Ren
                         width: row.take("WIDTH").unwrap(),
                         target_device: row.take("TARGET_DEVICE").unwrap(),
                         slug: row.take("SLUG").unwrap(),
-                        is_published: true,
+                        is_published: row.take::<Option<bool>, _>("PUBLISHED")
+                            .flatten().unwrap_or(true),
                         encoding: Encoding::JPG,
+                        fit,
+                        gravity,
+                        crop,
+                        quality: row.take::<Option<u8>, _>("QUALITY").flatten(),
+                        progressive: row.take::<Option<bool>, _>("PROGRESSIVE")
+                            .flatten(),
+                        strip_metadata: row
+                            .take::<Option<bool>, _>("STRIP_METADATA").flatten(),
+                        watermark: get_watermark_from_row(&mut row),
                         created_on: Local.from_utc_datetime(&created_on).into(),
                         created_by: row.take("CREATED_BY").unwrap(),
                         modified_on: Local.from_utc_datetime(&updated_on).into(),
@@ -63,6 +109,7 @@ fn get_renditions_from_row(row_wrapped: Result<Vec::<Row>, Error>)
 
                 let created_on = row.take("CREATED_ON").unwrap();
                 let updated_on = row.take("MODIFIED_ON").unwrap();
+                let (fit, gravity, crop) = get_geometry_from_row(&mut row);
 
                 renditions.push(Rendition {
                     id: row.take("ID").unwrap(),
@@ -71,8 +118,18 @@ fn get_renditions_from_row(row_wrapped: Result<Vec::<Row>, Error>)
                     width: row.take("WIDTH").unwrap(),
                     target_device: row.take("TARGET_DEVICE").unwrap(),
                     slug: row.take("SLUG").unwrap(),
-                    is_published: true,
+                    is_published: row.take::<Option<bool>, _>("PUBLISHED")
+                        .flatten().unwrap_or(true),
                     encoding: Encoding::JPG,
+                    fit,
+                    gravity,
+                    crop,
+                    quality: row.take::<Option<u8>, _>("QUALITY").flatten(),
+                    progressive: row.take::<Option<bool>, _>("PROGRESSIVE")
+                        .flatten(),
+                    strip_metadata: row.take::<Option<bool>, _>("STRIP_METADATA")
+                        .flatten(),
+                    watermark: get_watermark_from_row(&mut row),
                     created_on: Local.from_utc_datetime(&created_on).into(),
                     created_by: row.take("CREATED_BY").unwrap(),
                     modified_on: Local.from_utc_datetime(&updated_on).into(),
@@ -101,6 +158,8 @@ impl RenditionRepository for MySQLRenditionRepository {
             r"SELECT
                 ID, IMAGE_ID, HEIGHT, WIDTH, TARGET_DEVICE, SLUG,
                 PUBLISHED, CREATED_BY, MODIFIED_BY, CREATED_ON,
+                FIT, GRAVITY, CROP_X, CROP_Y, CROP_WIDTH, CROP_HEIGHT,
+                QUALITY, PROGRESSIVE, STRIP_METADATA, WATERMARK,
                 MODIFIED_ON
             FROM IMAGE_RENDITION WHERE ID = :id",
             params! { "id" => id }
@@ -113,6 +172,9 @@ impl RenditionRepository for MySQLRenditionRepository {
             r"SELECT
                 R.ID, R.IMAGE_ID, R.HEIGHT, R.WIDTH, R.TARGET_DEVICE,
                 R.SLUG, R.PUBLISHED, R.CREATED_BY, R.MODIFIED_BY,
+                R.FIT, R.GRAVITY, R.CROP_X, R.CROP_Y, R.CROP_WIDTH,
+                R.CROP_HEIGHT, R.QUALITY, R.PROGRESSIVE, R.STRIP_METADATA,
+                R.WATERMARK,
                 R.CREATED_ON, R.MODIFIED_ON
             FROM IMAGE I, IMAGE_RENDITION R, PROJECT P
             WHERE P.SLUG = :p_slug AND R.SLUG = :r_slug AND I.ID = R.IMAGE_ID
@@ -127,6 +189,9 @@ impl RenditionRepository for MySQLRenditionRepository {
             r"SELECT
                 IR.ID, IR.IMAGE_ID, IR.HEIGHT, IR.WIDTH, IR.TARGET_DEVICE,
                 IR.SLUG, IR.PUBLISHED, IR.CREATED_BY,
+                IR.FIT, IR.GRAVITY, IR.CROP_X, IR.CROP_Y, IR.CROP_WIDTH,
+                IR.CROP_HEIGHT, IR.QUALITY, IR.PROGRESSIVE, IR.STRIP_METADATA,
+                IR.WATERMARK,
                 IR.MODIFIED_BY, IR.CREATED_ON, IR.MODIFIED_ON, 
             FROM IMAGE_RENDITION IR, FOLDER F, IMAGE I
             WHERE F.SLUG = :p_slug AND I.SLUG = :r_slug AND I.ID = IR.IMAGE_ID
@@ -140,6 +205,8 @@ impl RenditionRepository for MySQLRenditionRepository {
         get_rendition_from_row(self.get_row(
             r"SELECT
                 ID, IMAGE_ID, HEIGHT, WIDTH, TARGET_DEVICE, SLUG, PUBLISHED,
+                FIT, GRAVITY, CROP_X, CROP_Y, CROP_WIDTH, CROP_HEIGHT,
+                QUALITY, PROGRESSIVE, STRIP_METADATA, WATERMARK,
                 CREATED_BY, MODIFIED_BY, CREATED_ON, MODIFIED_ON
             FROM IMAGE_RENDITION
             WHERE IMAGE_ID = :image_id AND SLUG = :slug",
@@ -151,6 +218,8 @@ impl RenditionRepository for MySQLRenditionRepository {
         get_renditions_from_row(self.get_rows(
             r"SELECT
                 ID, IMAGE_ID, HEIGHT, WIDTH, TARGET_DEVICE, SLUG, PUBLISHED,
+                FIT, GRAVITY, CROP_X, CROP_Y, CROP_WIDTH, CROP_HEIGHT,
+                QUALITY, PROGRESSIVE, STRIP_METADATA, WATERMARK,
                 CREATED_BY, MODIFIED_BY, CREATED_ON, MODIFIED_ON
             FROM IMAGE_RENDITION",
             Params::Empty,
@@ -162,6 +231,9 @@ impl RenditionRepository for MySQLRenditionRepository {
             r"SELECT
                 R.ID, R.IMAGE_ID, R.HEIGHT, R.WIDTH, R.TARGET_DEVICE,
                 R.SLUG, R.PUBLISHED, R.CREATED_BY, R.MODIFIED_BY,
+                R.FIT, R.GRAVITY, R.CROP_X, R.CROP_Y, R.CROP_WIDTH,
+                R.CROP_HEIGHT, R.QUALITY, R.PROGRESSIVE, R.STRIP_METADATA,
+                R.WATERMARK,
                 R.CREATED_ON, R.MODIFIED_ON 
             FROM IMAGE I, IMAGE_RENDITION R
             WHERE R.IMAGE_ID = I.ID AND I.ID = :image_id",
@@ -179,6 +251,9 @@ impl RenditionRepository for MySQLRenditionRepository {
             r"SELECT
                 R.ID, R.IMAGE_ID, R.HEIGHT, R.WIDTH, R.TARGET_DEVICE, R.SLUG,
                 R.PUBLISHED, R.CREATED_BY, R.MODIFIED_BY,
+                R.FIT, R.GRAVITY, R.CROP_X, R.CROP_Y, R.CROP_WIDTH,
+                R.CROP_HEIGHT, R.QUALITY, R.PROGRESSIVE, R.STRIP_METADATA,
+                R.WATERMARK,
                 R.CREATED_ON, R.MODIFIED_ON
             FROM IMAGE_RENDITION R, IMAGE I
             WHERE I.PROJECT_ID = :project_id AND R.IMAGE_ID = I.ID",
@@ -191,6 +266,9 @@ impl RenditionRepository for MySQLRenditionRepository {
             r"SELECT
                 R.ID, R.IMAGE_ID, R.HEIGHT, R.WIDTH, R.TARGET_DEVICE, R.SLUG,
                 R.PUBLISHED, R.CREATED_BY, R.MODIFIED_BY,
+                R.FIT, R.GRAVITY, R.CROP_X, R.CROP_Y, R.CROP_WIDTH,
+                R.CROP_HEIGHT, R.QUALITY, R.PROGRESSIVE, R.STRIP_METADATA,
+                R.WATERMARK,
                 R.CREATED_ON, R.MODIFIED_ON
             FROM IMAGE_RENDITION R, IMAGE I, PROJECT P
             WHERE I.PROJECT_ID = P.ID AND R.IMAGE_ID = I.ID
@@ -210,11 +288,15 @@ impl RenditionRepository for MySQLRenditionRepository {
                 let res = tx.exec_drop(
                     r"INSERT INTO IMAGE_RENDITION (
                         IMAGE_ID, HEIGHT, WIDTH, TARGET_DEVICE, SLUG,
-                        PUBLISHED, CREATED_BY, MODIFIED_BY, CREATED_ON,
+                        PUBLISHED, FIT, GRAVITY, CROP_X, CROP_Y, CROP_WIDTH,
+                        CROP_HEIGHT, QUALITY, PROGRESSIVE, STRIP_METADATA,
+                        WATERMARK, CREATED_BY, MODIFIED_BY, CREATED_ON,
                         MODIFIED_ON
                     ) VALUES (
                         :image_id, :height, :width, :target_device, :slug,
-                        :published, :created_by, :modified_by,
+                        :published, :fit, :gravity, :crop_x, :crop_y,
+                        :crop_width, :crop_height, :quality, :progressive,
+                        :strip_metadata, :watermark, :created_by, :modified_by,
                         current_timestamp(), current_timestamp()
                     )",
                     params! {
@@ -224,6 +306,17 @@ impl RenditionRepository for MySQLRenditionRepository {
                         "target_device" => &rendition.target_device,
                         "slug" => &rendition.slug,
                         "published" => &rendition.is_published,
+                        "fit" => rendition.fit.name(),
+                        "gravity" => rendition.gravity.name(),
+                        "crop_x" => rendition.crop.map(|c| c.x),
+                        "crop_y" => rendition.crop.map(|c| c.y),
+                        "crop_width" => rendition.crop.map(|c| c.width),
+                        "crop_height" => rendition.crop.map(|c| c.height),
+                        "quality" => rendition.quality,
+                        "progressive" => rendition.progressive,
+                        "strip_metadata" => rendition.strip_metadata,
+                        "watermark" => rendition.watermark.as_ref()
+                            .and_then(|w| serde_json::to_string(w).ok()),
                         "created_by" => &rendition.created_by,
                         "modified_by" => &rendition.modified_by,
                     }
@@ -1,12 +1,20 @@
 use std::{
-    convert::From, path::Path, fs::{ create_dir_all, read_to_string, write },
+    convert::From, path::Path, io::Write,
+    fs::{ create_dir_all, read_to_string, write, OpenOptions },
+    collections::HashMap,
 };
 
 use serde::{ Serialize, Deserialize };
 use serde_yaml;
 use dirs_next::{ data_local_dir, config_dir };
+use rand::{ rngs::OsRng, distributions::{ Alphanumeric, DistString } };
 use log::{ info, debug, error, warn };
 
+use crate::{
+    api::service::{ encode::DEFAULT_QUALITY, sniff::SUPPORTED_ENCODINGS },
+    model::{ watermark::Watermark, encoding::Encoding },
+};
+
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct ServerConfig {
@@ -15,6 +23,290 @@ pub struct ServerConfig {
     pub upload_dir: String,
     pub rendition_cache_dir: String,
     pub db: DBConfig,
+
+    /// Default value of the `Cache-Control` header sent with renditions.
+    #[serde(default = "default_cache_control")]
+    pub cache_control: String,
+
+    /// Project specific configurations, keyed by the project slug.
+    #[serde(default)]
+    pub projects: HashMap<String, ProjectConfig>,
+
+    /// Secret used to sign image URLs. Generated and saved to the config
+    /// file when missing, the server doesn't start without it.
+    #[serde(default)]
+    pub url_signing_secret: String,
+
+    /// Where the original images and the renditions are stored.
+    #[serde(default)]
+    pub storage: StorageConfig,
+
+    /// Limits of the image uploads.
+    #[serde(default)]
+    pub upload: UploadConfig,
+
+    /// Directories watched for new images, e.g. network shares.
+    #[serde(default)]
+    pub hot_folders: Vec<HotFolderConfig>,
+
+    /// Public base URL of the server (e.g. `https://images.example.com`),
+    /// used to build absolute URLs. Falls back to `http://{hostname}:{port}`.
+    #[serde(default)]
+    pub public_url: String,
+}
+
+/// Configurations that can be overridden for a single project.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ProjectConfig {
+    /// Value of the `Cache-Control` header sent with the renditions of this
+    /// project. Falls back to `ServerConfig::cache_control` when `None`.
+    pub cache_control: Option<String>,
+
+    /// On-the-fly image transformations through the public image URL.
+    pub transform: TransformConfig,
+
+    /// Serves AVIF/WebP variants of JPG/PNG renditions to clients that
+    /// accept them. Enabled by default.
+    pub negotiate_format: bool,
+
+    /// Encoding settings of the renditions that don't set their own.
+    pub output: OutputConfig,
+
+    /// Media queries (e.g. `(max-width: 600px)`) of the `<source>` elements
+    /// in the image manifest's `<picture>` snippet, keyed by the renditions'
+    /// target device.
+    pub media_queries: HashMap<String, String>,
+
+    /// Serves the rendition matching the requesting device (see
+    /// `Rendition::target_device`) for the image's `default` rendition.
+    pub device_selection: bool,
+
+    /// Watermark composited over the renditions, renditions can override
+    /// or disable it.
+    pub watermark: Option<Watermark>,
+
+    /// Upload limits of this project, on top of the server's.
+    pub upload: ProjectUploadConfig,
+}
+
+impl Default for ProjectConfig {
+    fn default() -> Self {
+        Self {
+            cache_control: None,
+            transform: TransformConfig::default(),
+            negotiate_format: true,
+            output: OutputConfig::default(),
+            media_queries: HashMap::new(),
+            device_selection: false,
+            watermark: None,
+            upload: ProjectUploadConfig::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct OutputConfig {
+    /// JPEG/WebP/AVIF quality (1 - 100).
+    pub quality: u8,
+
+    /// Writes progressive JPEGs.
+    pub progressive: bool,
+
+    /// Removes the EXIF data (e.g. GPS location) and ICC profile of the
+    /// original image from the renditions.
+    pub strip_metadata: bool,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            quality: DEFAULT_QUALITY,
+            progressive: false,
+            strip_metadata: true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TransformConfig {
+    /// Transformation parameters are rejected when `false`.
+    pub enabled: bool,
+
+    /// Sizes (`WIDTHxHEIGHT`, e.g.: `640x360` or `640x`) that can be
+    /// requested without a signature. Any size is allowed when empty.
+    pub allowed_sizes: Vec<String>,
+
+    /// Requires every transformation request to be signed.
+    pub require_signature: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UploadConfig {
+    /// Largest accepted upload, in bytes.
+    pub max_size: u64,
+
+    /// Largest accepted image, in pixels (width x height). Images are
+    /// decoded uncompressed (4 bytes per pixel), this keeps them from
+    /// exhausting the memory.
+    pub max_pixels: u64,
+
+    /// Encodings of the images that can be uploaded.
+    pub allowed_encodings: Vec<Encoding>,
+
+    /// Hours after which incomplete resumable uploads expire, counted from
+    /// their last received chunk.
+    pub expiration_hours: u32,
+
+    /// Hours after which accepted uploads that no image was created from
+    /// are removed.
+    pub pending_ttl_hours: u32,
+
+    /// Minutes between two removals of the expired uploads.
+    pub cleanup_interval_minutes: u32,
+
+    /// Largest accepted archive of the bulk imports, in bytes. The files of
+    /// the archive are subject to `max_size`.
+    pub max_archive_size: u64,
+
+    /// URL this node is reachable at directly, bypassing the load balancer,
+    /// e.g. `http://node-1:8080`. Resumable uploads are kept by the node
+    /// that created them, their URLs point at it when this is set. Required
+    /// when several nodes serve the API.
+    pub node_url: String,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        Self {
+            max_size: 250 * 1000 * 1000,
+            max_pixels: 100 * 1000 * 1000,
+            allowed_encodings: SUPPORTED_ENCODINGS.to_vec(),
+            expiration_hours: 24,
+            pending_ttl_hours: 24,
+            cleanup_interval_minutes: 15,
+            max_archive_size: 4 * 1000 * 1000 * 1000,
+            node_url: String::new(),
+        }
+    }
+}
+
+/// Upload limits of a project, the stricter of the project's and the
+/// server's limit applies. Falls back to the server's limits when `None`.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ProjectUploadConfig {
+    pub max_size: Option<u64>,
+    pub max_pixels: Option<u64>,
+    pub allowed_encodings: Option<Vec<Encoding>>,
+}
+
+/// A directory whose new files are imported as images. Imported files are
+/// moved to its `processed` subdirectory, the others to `failed`.
+/// Subdirectories are imported as folders.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HotFolderConfig {
+    pub path: String,
+
+    /// Slug of the project the files are imported into.
+    pub project: String,
+
+    /// The folder the files are imported into, `0` for the project's root.
+    pub folder_id: u32,
+
+    /// Seconds between two scans of the directory.
+    pub scan_interval_seconds: u32,
+
+    /// Seconds a file must go unmodified before it's imported, so files
+    /// still being copied are left alone.
+    pub settle_seconds: u32,
+}
+
+impl Default for HotFolderConfig {
+    fn default() -> Self {
+        Self {
+            path: String::new(),
+            project: String::new(),
+            folder_id: 0,
+            scan_interval_seconds: 30,
+            settle_seconds: 10,
+        }
+    }
+}
+
+/// Upload limits in effect for a project.
+#[derive(Clone)]
+pub struct UploadLimits {
+    pub max_size: u64,
+    pub max_pixels: u64,
+    pub allowed_encodings: Vec<Encoding>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+
+    /// Settings of the `s3` backend.
+    pub s3: Option<S3Config>,
+
+    /// Seconds a node's working copy is used without checking that the
+    /// stored object is unchanged. Objects replaced or deleted by another
+    /// node are noticed within this time.
+    pub revalidate_seconds: u64,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: StorageBackend::default(),
+            s3: None,
+            revalidate_seconds: 60,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// Keeps the files in `upload_dir` and `rendition_cache_dir`.
+    #[default]
+    Local,
+    /// Keeps the files in an S3 compatible object storage (e.g. MinIO), the
+    /// local directories only hold the node's working copies.
+    S3,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct S3Config {
+    /// URL of the S3 endpoint, e.g.: `http://localhost:9000`.
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    pub access_key: String,
+    pub secret_key: String,
+
+    /// Addresses the bucket in the path (`endpoint/bucket/key`) instead of
+    /// the host name (`bucket.endpoint/key`), as MinIO expects by default.
+    pub path_style: bool,
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        Self {
+            endpoint: String::new(),
+            region: String::from("us-east-1"),
+            bucket: String::new(),
+            access_key: String::new(),
+            secret_key: String::new(),
+            path_style: true,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Default)]
@@ -45,6 +337,51 @@ pub struct DBConnectionInfo {
 const DEF_ROOT_DIR_NAME: &str = "swms";
 const DEF_IMG_UPL_DIR_NAME: &str = "uploads";
 const DEF_REN_DIR_NAME: &str = "renditions";
+const DEF_CACHE_CONTROL: &str = "public, max-age=86400";
+const DEF_SECRET_LENGTH: usize = 48;
+
+fn default_cache_control() -> String { String::from(DEF_CACHE_CONTROL) }
+
+fn generate_secret() -> String {
+    Alphanumeric.sample_string(&mut OsRng, DEF_SECRET_LENGTH)
+}
+
+/// Adds a generated URL signing secret to a config file that has none.
+///
+/// Returns the secret, or an empty string if it couldn't be saved (a secret
+/// that changes on every start would break the signed URLs).
+fn persist_secret(config_file: &str, file_content: &str) -> String {
+    if file_content.contains("urlSigningSecret:") {
+        error!("The URL signing secret in {} is empty", config_file);
+        return String::new();
+    }
+
+    let secret = generate_secret();
+    let separator = if file_content.is_empty() || file_content.ends_with('\n') {
+        ""
+    } else {
+        "\n"
+    };
+
+    let appended = OpenOptions::new().append(true).open(config_file)
+        .and_then(|mut file| {
+            write!(file, "{}urlSigningSecret: {}\n", separator, secret)
+        });
+
+    match appended {
+        Ok(_) => {
+            info!("Generated a URL signing secret, saved to {}", config_file);
+            secret
+        }
+
+        Err(e) => {
+            error!(
+                "Could not save the URL signing secret to {}: {}", config_file, e
+            );
+            String::new()
+        }
+    }
+}
 
 impl Default for ServerConfig {
     fn default() -> Self {
@@ -56,6 +393,9 @@ impl Default for ServerConfig {
         let hostname = String::from("localhost");
         let port: u16 = 5421;
 
+        // Only kept when saved to a new config file.
+        let mut url_signing_secret = String::new();
+
         if let Some(data_dir) = data_local_dir() {
             let dir = data_dir.display();
 
@@ -76,8 +416,16 @@ impl Default for ServerConfig {
         if Path::new(config_file_str).exists() {
             match read_to_string(config_file_str) {
                 Ok(file_content) => {
-                    match serde_yaml::from_str(&file_content) {
-                        Ok(s_conf) => { return s_conf; }
+                    match serde_yaml::from_str::<Self>(&file_content) {
+                        Ok(mut s_conf) => {
+                            if s_conf.url_signing_secret.is_empty() {
+                                s_conf.url_signing_secret = persist_secret(
+                                    config_file_str, &file_content
+                                );
+                            }
+
+                            return s_conf;
+                        }
                         Err(e) => {
                             error!("An error occured while parsing config file \
                                 ({}): {}", config_file, e);
@@ -113,6 +461,13 @@ impl Default for ServerConfig {
                         upload_dir: upload_dir.clone(),
                         rendition_cache_dir: rendition_cache_dir.clone(),
                         db: DBConfig::default(),
+                        cache_control: default_cache_control(),
+                        projects: HashMap::new(),
+                        url_signing_secret: generate_secret(),
+                        storage: StorageConfig::default(),
+                        upload: UploadConfig::default(),
+                        hot_folders: vec![],
+                        public_url: String::default(),
                     };
 
                     match serde_yaml::to_string(&temp_config) {
@@ -126,6 +481,9 @@ impl Default for ServerConfig {
                                         "config file written at: {}",
                                         config_file
                                     );
+
+                                    url_signing_secret =
+                                        temp_config.url_signing_secret.clone();
                                 }
 
                                 Err(e) => {
@@ -166,6 +524,13 @@ impl Default for ServerConfig {
             upload_dir,
             rendition_cache_dir,
             db: DBConfig::default(),
+            cache_control: default_cache_control(),
+            projects: HashMap::new(),
+            url_signing_secret,
+            storage: StorageConfig::default(),
+            upload: UploadConfig::default(),
+            hot_folders: vec![],
+            public_url: String::default(),
         }
     }
 }
@@ -184,6 +549,64 @@ impl ServerConfig {
         );
     }
 
+    /// Returns the configuration of a project, or the defaults if the project
+    /// has no configuration.
+    pub fn get_project_config(&self, project_slug: &str) -> ProjectConfig {
+        match self.projects.get(project_slug) {
+            Some(project_config) => project_config.clone(),
+            None => ProjectConfig::default(),
+        }
+    }
+
+    /// Returns the upload limits of a project (the server's limits when
+    /// `project_slug` is `None`).
+    pub fn get_upload_limits(&self, project_slug: Option<&str>) -> UploadLimits {
+        let mut limits = UploadLimits {
+            max_size: self.upload.max_size,
+            max_pixels: self.upload.max_pixels,
+            allowed_encodings: self.upload.allowed_encodings.clone(),
+        };
+
+        let project_upload = match project_slug.and_then(|s| self.projects.get(s)) {
+            Some(project_config) => &project_config.upload,
+            None => { return limits; }
+        };
+
+        if let Some(max_size) = project_upload.max_size {
+            limits.max_size = limits.max_size.min(max_size);
+        }
+
+        if let Some(max_pixels) = project_upload.max_pixels {
+            limits.max_pixels = limits.max_pixels.min(max_pixels);
+        }
+
+        if let Some(allowed_encodings) = &project_upload.allowed_encodings {
+            limits.allowed_encodings.retain(|e| allowed_encodings.contains(e));
+        }
+
+        limits
+    }
+
+    /// Returns the public base URL of the server, without a trailing slash.
+    pub fn get_public_url(&self) -> String {
+        if self.public_url.is_empty() {
+            return format!("http://{}:{}", self.hostname, self.port);
+        }
+
+        self.public_url.trim_end_matches('/').to_string()
+    }
+
+    /// Returns the `Cache-Control` header value for renditions of a project.
+    pub fn get_cache_control(&self, project_slug: &str) -> String {
+        if let Some(project_config) = self.projects.get(project_slug) {
+            if let Some(cache_control) = &project_config.cache_control {
+                return cache_control.clone();
+            }
+        }
+
+        self.cache_control.clone()
+    }
+
     fn get_config_path() -> String {
         let config_dir_path = Self::get_config_dir_path();
 
@@ -1,11 +1,46 @@
-use std::{ collections::HashMap, sync::Mutex };
+use std::{ collections::{ HashMap, HashSet }, sync::Mutex };
+
+use chrono::{ DateTime, Utc };
 
 use crate::auth::token::{ RefreshTokenData, get_expiry_from_now };
 
+/// An accepted upload waiting for `add_image`.
+#[derive(Clone)]
+pub struct PendingUpload {
+    pub uploaded_on: DateTime<Utc>,
+    /// Size in bytes.
+    pub size: u64,
+}
+
+/// Marks a resumable upload as receiving a chunk while it's alive, the upload
+/// is unlocked even if the request is dropped or panics.
+pub struct UploadLock<'a> {
+    state: &'a ServerState,
+    upload_id: String,
+}
+
+impl Drop for UploadLock<'_> {
+    fn drop(&mut self) {
+        // Still unlock if a panic poisoned the mutex.
+        self.state.active_uploads.lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.upload_id);
+    }
+}
+
 #[derive(Default)]
 pub struct ServerState {
     /// HashMap that holds all the refresh tokens and their corresponsing data.
     refresh_map: Mutex<HashMap<String, RefreshTokenData>>,
+
+    /// IDs of the resumable uploads that are receiving a chunk. Uploads are
+    /// addressed to the node that created them (see `api::upload`), so the
+    /// lock is never needed by another node.
+    active_uploads: Mutex<HashSet<String>>,
+
+    /// Accepted uploads that no image was created from yet, keyed by the
+    /// upload ID.
+    pending_uploads: Mutex<HashMap<String, PendingUpload>>,
 }
 
 impl ServerState {
@@ -37,5 +72,74 @@ impl ServerState {
             None => { }
         };
     }
+
+    /// Marks a resumable upload as receiving a chunk, until the returned
+    /// lock is dropped.
+    ///
+    /// Returns `None` if the upload is receiving a chunk already.
+    pub fn lock_upload(&self, upload_id: &str) -> Option<UploadLock<'_>> {
+        if !self.active_uploads.lock().unwrap().insert(upload_id.to_string()) {
+            return None;
+        }
+
+        Some(UploadLock { state: self, upload_id: upload_id.to_string() })
+    }
+
+    pub fn add_pending_upload(&self, upload_id: &str, pending: PendingUpload) {
+        self.pending_uploads.lock().unwrap().insert(upload_id.to_string(), pending);
+    }
+
+    /// Removes an upload from the pending ones, once it's claimed or
+    /// cancelled.
+    pub fn remove_pending_upload(&self, upload_id: &str) -> Option<PendingUpload> {
+        self.pending_uploads.lock().unwrap().remove(upload_id)
+    }
+
+    /// Removes and returns the IDs of the uploads pending since before
+    /// `uploaded_before`.
+    pub fn take_expired_uploads(&self, uploaded_before: DateTime<Utc>)
+        -> Vec<String> {
+        let mut pending_uploads = self.pending_uploads.lock().unwrap();
+
+        let expired: Vec<String> = pending_uploads.iter()
+            .filter(|(_, pending)| pending.uploaded_on < uploaded_before)
+            .map(|(upload_id, _)| upload_id.clone())
+            .collect();
+
+        for upload_id in expired.iter() {
+            pending_uploads.remove(upload_id);
+        }
+
+        expired
+    }
+
+    /// Returns the number of pending uploads, their total size and the
+    /// upload time of the oldest one.
+    pub fn get_pending_upload_stats(&self) -> (usize, u64, Option<DateTime<Utc>>) {
+        let pending_uploads = self.pending_uploads.lock().unwrap();
+
+        (
+            pending_uploads.len(),
+            pending_uploads.values().map(|pending| pending.size).sum(),
+            pending_uploads.values().map(|pending| pending.uploaded_on).min(),
+        )
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upload_lock_is_released_when_dropped() {
+        let state = ServerState::default();
+
+        let lock = state.lock_upload("a");
+        assert!(lock.is_some());
+        assert!(state.lock_upload("a").is_none());
+        assert!(state.lock_upload("b").is_some());
+
+        drop(lock);
+        assert!(state.lock_upload("a").is_some());
+    }
+}
@@ -0,0 +1,149 @@
+use std::{
+    fs::{ copy, create_dir_all, read, read_dir, remove_file, write, File },
+    io::{ ErrorKind, Read, Seek, SeekFrom },
+    path::Path,
+};
+
+use log::error;
+
+use crate::storage::{ Storage, StorageError };
+
+/// Keeps the objects as files in a local directory, the keys are the paths
+/// relative to the directory.
+pub struct LocalStorage {
+    root: String,
+}
+
+impl LocalStorage {
+    pub fn new(root: &str) -> Self {
+        Self { root: root.to_string() }
+    }
+
+    fn path(&self, key: &str) -> String {
+        format!("{}/{}", self.root, key)
+    }
+
+    fn create_parent_dir(path: &str) -> Result<(), StorageError> {
+        if let Some(parent) = Path::new(path).parent() {
+            if let Err(e) = create_dir_all(parent) {
+                error!("Error while creating directory {}: {}", parent.display(), e);
+                return Err(StorageError::IOError);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Appends the keys of the files in `dir` (recursively) to `keys`.
+    fn list_dir(&self, dir: &Path, keys: &mut Vec<String>) {
+        let entries = match read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => { return; }
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+
+            if path.is_dir() {
+                self.list_dir(&path, keys);
+            } else if let Ok(relative) = path.strip_prefix(&self.root) {
+                keys.push(relative.to_string_lossy().replace('\\', "/"));
+            }
+        }
+    }
+}
+
+fn io_error(e: std::io::Error) -> StorageError {
+    match e.kind() {
+        ErrorKind::NotFound => StorageError::NotFound,
+        _ => StorageError::IOError,
+    }
+}
+
+impl Storage for LocalStorage {
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), StorageError> {
+        let path = self.path(key);
+
+        Self::create_parent_dir(&path)?;
+
+        write(&path, data).map_err(|e| {
+            error!("Error while writing {}: {}", path, e);
+            StorageError::IOError
+        })
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>, StorageError> {
+        read(self.path(key)).map_err(io_error)
+    }
+
+    fn stream(&self, key: &str, offset: u64, length: Option<u64>)
+        -> Result<Box<dyn Read + Send>, StorageError> {
+        let mut file = File::open(self.path(key)).map_err(io_error)?;
+
+        if offset > 0 {
+            file.seek(SeekFrom::Start(offset)).map_err(io_error)?;
+        }
+
+        match length {
+            Some(length) => Ok(Box::new(file.take(length))),
+            None => Ok(Box::new(file)),
+        }
+    }
+
+    fn delete(&self, key: &str) -> Result<(), StorageError> {
+        match remove_file(self.path(key)) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => {
+                error!("Error while deleting {}: {}", key, e);
+                Err(StorageError::IOError)
+            }
+        }
+    }
+
+    fn exists(&self, key: &str) -> Result<bool, StorageError> {
+        Ok(Path::new(&self.path(key)).is_file())
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, StorageError> {
+        // Only the directory the prefix points into has to be walked.
+        let dir = match prefix.rfind('/') {
+            Some(i) => self.path(&prefix[..i]),
+            None => self.root.clone(),
+        };
+
+        let mut keys: Vec<String> = vec![];
+        self.list_dir(Path::new(&dir), &mut keys);
+
+        keys.retain(|key| key.starts_with(prefix));
+
+        Ok(keys)
+    }
+
+    fn local_path(&self, key: &str) -> Option<String> {
+        Some(self.path(key))
+    }
+
+    fn put_file(&self, key: &str, path: &str) -> Result<(), StorageError> {
+        let dest_path = self.path(key);
+
+        // Files in the storage's directory are stored already.
+        if dest_path == path || (
+            Path::new(&dest_path).exists()
+                && Path::new(&dest_path).canonicalize().ok()
+                    == Path::new(path).canonicalize().ok()
+        ) {
+            return Ok(());
+        }
+
+        Self::create_parent_dir(&dest_path)?;
+
+        match copy(path, &dest_path) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("Error while copying {} to {}: {}", path, dest_path, e);
+                Err(io_error(e))
+            }
+        }
+    }
+}
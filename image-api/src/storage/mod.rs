@@ -0,0 +1,394 @@
+//! Storage backends
+//!
+//! The original images and the generated renditions are kept in a `Storage`,
+//! either the local file system (`LocalStorage`) or an S3 compatible object
+//! storage (`S3Storage`) that can be shared by several API nodes.
+//!
+//! Images are always processed and served from local files: `upload_dir` and
+//! `rendition_cache_dir` hold the node's working copies, which are fetched
+//! from the storage on demand (see `Storages`). With the local backend the
+//! working copies are the stored objects themselves.
+//!
+//! Other nodes may replace or delete the stored objects, so a working copy
+//! records the version (e.g. the S3 ETag) of the object it was copied from in
+//! a `{path}.version` file. The copy is used as is for
+//! `storage.revalidateSeconds`, then revalidated against the object before
+//! it's used again. Files replaced or removed by the node itself update or
+//! remove its working copies right away.
+
+pub mod local;
+pub mod s3;
+
+use std::{
+    fmt::{ Display, Formatter, Result as FmtResult },
+    fs::{
+        create_dir_all, metadata, File, read, read_to_string, remove_file, rename,
+        write,
+    },
+    io::{ Read, copy },
+    path::Path,
+    time::{ Duration, SystemTime },
+};
+
+use log::{ debug, error };
+
+use crate::{
+    server::config::{ ServerConfig, StorageBackend },
+    storage::{ local::LocalStorage, s3::S3Storage },
+};
+
+pub enum StorageError {
+    NotFound,
+    IOError,
+    ConnectionError,
+    OtherError,
+}
+
+impl Display for StorageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::NotFound => write!(f, "StorageError: NotFound"),
+            Self::IOError => write!(f, "StorageError: IOError"),
+            Self::ConnectionError => write!(f, "StorageError: ConnectionError"),
+            Self::OtherError => write!(f, "StorageError: OtherError"),
+        }
+    }
+}
+
+/// Object storage, objects are addressed by `/` separated keys.
+pub trait Storage {
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), StorageError>;
+
+    /// Returns the content of an object.
+    fn get(&self, key: &str) -> Result<Vec<u8>, StorageError>;
+
+    /// Returns a reader over (a range of) an object.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - Position of the first byte.
+    /// * `length` - Number of bytes, up to the end of the object when `None`.
+    fn stream(&self, key: &str, offset: u64, length: Option<u64>)
+        -> Result<Box<dyn Read + Send>, StorageError>;
+
+    /// Deletes an object, deleting a missing object is not an error.
+    fn delete(&self, key: &str) -> Result<(), StorageError>;
+
+    fn exists(&self, key: &str) -> Result<bool, StorageError>;
+
+    /// Returns a value that changes whenever the object is replaced (e.g.
+    /// its ETag), `StorageError::NotFound` if the object doesn't exist.
+    fn version(&self, key: &str) -> Result<String, StorageError> {
+        match self.exists(key)? {
+            true => Ok(String::new()),
+            false => Err(StorageError::NotFound),
+        }
+    }
+
+    /// Returns the keys of all the objects starting with `prefix`.
+    fn list(&self, prefix: &str) -> Result<Vec<String>, StorageError>;
+
+    /// Returns the local file of an object, if the storage keeps its objects
+    /// on the local file system.
+    fn local_path(&self, _key: &str) -> Option<String> { None }
+
+    /// Stores a local file.
+    fn put_file(&self, key: &str, path: &str) -> Result<(), StorageError> {
+        match read(path) {
+            Ok(data) => self.put(key, &data),
+            Err(e) => {
+                error!("Error while reading {}: {}", path, e);
+                Err(StorageError::IOError)
+            }
+        }
+    }
+
+    /// Copies an object to a local file, unless the file is an up to date
+    /// copy of the object already. A copy of a deleted object is removed.
+    ///
+    /// A copy validated less than `max_age` ago is used without contacting
+    /// the storage. When the storage can't be reached, an existing copy is
+    /// kept.
+    fn fetch_file(&self, key: &str, path: &str, max_age: Duration)
+        -> Result<(), StorageError> {
+        if self.local_path(key).as_deref() == Some(path) {
+            return match Path::new(path).exists() {
+                true => Ok(()),
+                false => Err(StorageError::NotFound),
+            };
+        }
+
+        let local_copy = Path::new(path).exists();
+
+        if local_copy && is_fresh(path, max_age) {
+            return Ok(());
+        }
+
+        let version = match self.version(key) {
+            Ok(v) => v,
+
+            Err(StorageError::NotFound) => {
+                if local_copy {
+                    debug!("{} was deleted, removing {}", key, path);
+                    remove_working_copy(path);
+                }
+
+                return Err(StorageError::NotFound);
+            }
+
+            Err(e) if local_copy => {
+                error!("Unable to revalidate {}, keeping {}: {}", key, path, e);
+                return Ok(());
+            }
+
+            Err(e) => { return Err(e); }
+        };
+
+        if local_copy && read_to_string(version_path(path)).ok() == Some(version.clone()) {
+            // Trusted for another `max_age`.
+            let _ = write(version_path(path), &version);
+            return Ok(());
+        }
+
+        let mut reader = self.stream(key, 0, None)?;
+
+        if let Some(parent) = Path::new(path).parent() {
+            let _ = create_dir_all(parent);
+        }
+
+        // Written to a temporary file first, so that a concurrent request
+        // never reads a partial file.
+        let tmp_path = format!("{}.part", path);
+
+        let result = File::create(&tmp_path)
+            .and_then(|mut file| copy(&mut reader, &mut file))
+            .and_then(|_| rename(&tmp_path, path))
+            .and_then(|_| write(version_path(path), &version));
+
+        match result {
+            Ok(_) => {
+                debug!("Fetched {} into {}", key, path);
+                Ok(())
+            }
+
+            Err(e) => {
+                error!("Error while fetching {} into {}: {}", key, path, e);
+                let _ = remove_file(&tmp_path);
+                Err(StorageError::IOError)
+            }
+        }
+    }
+
+    /// Stores a local file that becomes the working copy of the object.
+    fn store_file(&self, key: &str, path: &str) -> Result<(), StorageError> {
+        self.put_file(key, path)?;
+
+        if self.local_path(key).as_deref() != Some(path) {
+            // Written by this node, no need to fetch it again.
+            let _ = write(version_path(path), self.version(key)?);
+        }
+
+        Ok(())
+    }
+
+    /// Deletes all the objects starting with `prefix`.
+    fn delete_prefix(&self, prefix: &str) -> Result<(), StorageError> {
+        for key in self.list(prefix)? {
+            self.delete(&key)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The storages of the original images and of the renditions, along with
+/// the local directories their working copies are kept in.
+pub struct Storages {
+    pub originals: Box<dyn Storage + Sync + Send>,
+    pub renditions: Box<dyn Storage + Sync + Send>,
+    upload_dir: String,
+    rendition_cache_dir: String,
+    /// How long working copies are used without revalidation.
+    max_age: Duration,
+}
+
+impl Storages {
+    pub fn new(config: &ServerConfig) -> Result<Self, StorageError> {
+        let (originals, renditions): (
+            Box<dyn Storage + Sync + Send>, Box<dyn Storage + Sync + Send>
+        ) = match config.storage.backend {
+            StorageBackend::Local => (
+                Box::new(LocalStorage::new(&config.upload_dir)),
+                Box::new(LocalStorage::new(&config.rendition_cache_dir)),
+            ),
+
+            StorageBackend::S3 => {
+                let s3_config = match &config.storage.s3 {
+                    Some(c) => c,
+                    None => {
+                        error!("The S3 storage backend requires `storage.s3`");
+                        return Err(StorageError::OtherError);
+                    }
+                };
+
+                (
+                    Box::new(S3Storage::new(s3_config, "originals")),
+                    Box::new(S3Storage::new(s3_config, "renditions")),
+                )
+            }
+        };
+
+        Ok(Self {
+            originals,
+            renditions,
+            upload_dir: config.upload_dir.clone(),
+            rendition_cache_dir: config.rendition_cache_dir.clone(),
+            max_age: Duration::from_secs(config.storage.revalidate_seconds),
+        })
+    }
+
+    /// Makes sure the original image file (in the upload directory) exists
+    /// locally.
+    pub fn fetch_original(&self, path: &str) -> Result<(), StorageError> {
+        self.originals.fetch_file(&key(&self.upload_dir, path), path, self.max_age)
+    }
+
+    /// Stores an original image file (in the upload directory).
+    pub fn store_original(&self, path: &str) -> Result<(), StorageError> {
+        self.originals.store_file(&key(&self.upload_dir, path), path)
+    }
+
+    pub fn has_original(&self, path: &str) -> Result<bool, StorageError> {
+        self.originals.exists(&key(&self.upload_dir, path))
+    }
+
+    pub fn remove_original(&self, path: &str) -> Result<(), StorageError> {
+        self.originals.delete(&key(&self.upload_dir, path))
+    }
+
+    /// Fetches a rendition file (in the rendition cache directory) that was
+    /// generated earlier, possibly by another node, or revalidates the local
+    /// copy once it's older than `storage.revalidateSeconds`.
+    ///
+    /// Returns `true` if an up to date file exists locally.
+    pub fn fetch_rendition(&self, path: &str) -> bool {
+        self.renditions.fetch_file(
+            &key(&self.rendition_cache_dir, path), path, self.max_age
+        ).is_ok()
+    }
+
+    /// Stores a generated rendition file (in the rendition cache directory).
+    pub fn store_rendition(&self, path: &str) {
+        if let Err(e) = self.renditions.store_file(
+            &key(&self.rendition_cache_dir, path), path
+        ) {
+            // The rendition is generated again when it's needed.
+            error!("Error while storing rendition {}: {}", path, e);
+        }
+    }
+
+    /// Removes the rendition files whose keys (paths relative to the
+    /// rendition cache directory) start with `prefix`.
+    pub fn remove_renditions(&self, prefix: &str) -> Result<(), StorageError> {
+        self.renditions.delete_prefix(prefix)
+    }
+
+    /// Removes the rendition files for which `filter` returns `true`, `prefix`
+    /// narrows the objects that are checked.
+    pub fn remove_renditions_matching(
+        &self, prefix: &str, filter: impl Fn(&str) -> bool
+    ) -> Result<(), StorageError> {
+        for key in self.renditions.list(prefix)? {
+            if filter(&key) {
+                self.renditions.delete(&key)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the path of the file holding the version of a working copy.
+pub fn version_path(path: &str) -> String {
+    format!("{}.version", path)
+}
+
+/// Returns `true` if a working copy was validated less than `max_age` ago.
+fn is_fresh(path: &str, max_age: Duration) -> bool {
+    metadata(version_path(path))
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|validated| SystemTime::now().duration_since(validated).ok())
+        .map(|age| age < max_age)
+        .unwrap_or(false)
+}
+
+/// Removes a working copy along with its version file.
+fn remove_working_copy(path: &str) {
+    let _ = remove_file(path);
+    let _ = remove_file(version_path(path));
+}
+
+/// Returns the key of a file inside a storage's local directory.
+fn key(dir: &str, path: &str) -> String {
+    let relative = path.strip_prefix(dir).unwrap_or(path);
+
+    relative.trim_start_matches(|c| c == '/' || c == '\\').replace('\\', "/")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ env, io::Cursor, sync::Mutex };
+
+    use uuid::Uuid;
+
+    use super::*;
+
+    /// A single object, counting the version checks.
+    struct CountingStorage {
+        data: Vec<u8>,
+        version_checks: Mutex<u32>,
+    }
+
+    impl Storage for CountingStorage {
+        fn put(&self, _key: &str, _data: &[u8]) -> Result<(), StorageError> { Ok(()) }
+
+        fn get(&self, _key: &str) -> Result<Vec<u8>, StorageError> { Ok(self.data.clone()) }
+
+        fn stream(&self, _key: &str, _offset: u64, _length: Option<u64>)
+            -> Result<Box<dyn Read + Send>, StorageError> {
+            Ok(Box::new(Cursor::new(self.data.clone())))
+        }
+
+        fn delete(&self, _key: &str) -> Result<(), StorageError> { Ok(()) }
+
+        fn exists(&self, _key: &str) -> Result<bool, StorageError> { Ok(true) }
+
+        fn version(&self, _key: &str) -> Result<String, StorageError> {
+            *self.version_checks.lock().unwrap() += 1;
+            Ok(String::from("v1"))
+        }
+
+        fn list(&self, _prefix: &str) -> Result<Vec<String>, StorageError> { Ok(vec![]) }
+    }
+
+    #[test]
+    fn fetch_file_trusts_recently_validated_copies() {
+        let storage = CountingStorage { data: b"image".to_vec(), version_checks: Mutex::new(0) };
+        let path = env::temp_dir().join(format!("storage-test-{}", Uuid::new_v4()))
+            .to_string_lossy().to_string();
+        let checks = || *storage.version_checks.lock().unwrap();
+
+        storage.fetch_file("a.jpg", &path, Duration::from_secs(60)).ok().unwrap();
+        assert_eq!(read(&path).unwrap(), b"image");
+        assert_eq!(checks(), 1);
+
+        storage.fetch_file("a.jpg", &path, Duration::from_secs(60)).ok().unwrap();
+        assert_eq!(checks(), 1);
+
+        storage.fetch_file("a.jpg", &path, Duration::ZERO).ok().unwrap();
+        assert_eq!(checks(), 2);
+
+        remove_working_copy(&path);
+    }
+}
@@ -0,0 +1,628 @@
+//! S3 compatible object storage (AWS S3, MinIO, ...)
+//!
+//! Requests are signed with AWS Signature Version 4. Files are uploaded from
+//! the disk without being read into memory, the large ones with a multipart
+//! upload.
+//!
+//! The tests run against a local MinIO (see `docker-compose.minio.yml`):
+//! `cargo test -- --ignored`.
+
+use std::{ fs::File, io::Read, time::Duration };
+
+use chrono::Utc;
+use hmac::{ Hmac, Mac };
+use lazy_static::lazy_static;
+use log::{ debug, error };
+use regex::Regex;
+use sha2::{ Digest, Sha256 };
+
+use crate::{
+    server::config::S3Config,
+    storage::{ Storage, StorageError },
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const TIMEOUT_SECS: u64 = 60;
+
+/// Files larger than this are uploaded in parts of (at least) this size.
+const PART_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Most parts a multipart upload can have.
+const MAX_PARTS: u64 = 10000;
+
+lazy_static! {
+    static ref KEY_RE: Regex = Regex::new(r"<Key>([^<]*)</Key>").unwrap();
+    static ref UPLOAD_ID_RE: Regex = Regex::new(r"<UploadId>([^<]*)</UploadId>")
+        .unwrap();
+    static ref TOKEN_RE: Regex = Regex::new(
+        r"<NextContinuationToken>([^<]*)</NextContinuationToken>"
+    ).unwrap();
+}
+
+/// Body of a request.
+enum Body<'a> {
+    Bytes(&'a [u8]),
+    /// A reader and the number of bytes it returns. The payload is not
+    /// signed, it would have to be read twice.
+    Stream(Box<dyn Read + 'a>, u64),
+}
+
+/// Keeps the objects in a bucket, under `{prefix}/`.
+pub struct S3Storage {
+    config: S3Config,
+    prefix: String,
+    /// Scheme and authority of the endpoint, e.g.: `http://localhost:9000`.
+    base_url: String,
+    host: String,
+    agent: ureq::Agent,
+    part_size: u64,
+}
+
+impl S3Storage {
+    pub fn new(config: &S3Config, prefix: &str) -> Self {
+        let endpoint = config.endpoint.trim_end_matches('/');
+
+        let (scheme, authority) = match endpoint.split_once("://") {
+            Some((scheme, authority)) => (scheme, authority),
+            None => ("https", endpoint),
+        };
+
+        let host = if config.path_style {
+            authority.to_string()
+        } else {
+            format!("{}.{}", config.bucket, authority)
+        };
+
+        Self {
+            config: config.clone(),
+            prefix: prefix.trim_matches('/').to_string(),
+            base_url: format!("{}://{}", scheme, host),
+            host,
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(TIMEOUT_SECS))
+                .build(),
+            part_size: PART_SIZE,
+        }
+    }
+
+    /// Returns the (URI encoded) path of an object, or of the bucket when
+    /// `key` is `None`.
+    fn object_path(&self, key: Option<&str>) -> String {
+        let mut path = String::new();
+
+        if self.config.path_style {
+            path.push('/');
+            path.push_str(&uri_encode(&self.config.bucket, true));
+        }
+
+        if let Some(key) = key {
+            path.push('/');
+            path.push_str(&uri_encode(&format!("{}/{}", self.prefix, key), false));
+        } else if path.is_empty() {
+            path.push('/');
+        }
+
+        path
+    }
+
+    /// Sends a signed request.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - Query parameters (not encoded).
+    /// * `headers` - Additional (unsigned) headers.
+    fn send(
+        &self, method: &str, path: &str, query: &[(&str, &str)],
+        headers: &[(&str, String)], body: Body,
+    ) -> Result<ureq::Response, StorageError> {
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+
+        let payload_hash = match &body {
+            Body::Bytes(data) => hex::encode(Sha256::digest(data)),
+            Body::Stream(_, _) => String::from("UNSIGNED-PAYLOAD"),
+        };
+
+        let mut query_pairs: Vec<(String, String)> = query.iter()
+            .map(|(k, v)| (uri_encode(k, true), uri_encode(v, true)))
+            .collect();
+        query_pairs.sort();
+
+        let canonical_query = query_pairs.iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<String>>()
+            .join("&");
+
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method, path, canonical_query, self.host, payload_hash, amz_date,
+            signed_headers, payload_hash
+        );
+
+        let scope = format!("{}/{}/s3/aws4_request", date, self.config.region);
+
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date, scope, hex::encode(Sha256::digest(canonical_request))
+        );
+
+        let signature = self.sign(&date, &string_to_sign)?;
+
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.config.access_key, scope, signed_headers, signature
+        );
+
+        let url = if canonical_query.is_empty() {
+            format!("{}{}", self.base_url, path)
+        } else {
+            format!("{}{}?{}", self.base_url, path, canonical_query)
+        };
+
+        let mut request = self.agent.request(method, &url)
+            .set("x-amz-content-sha256", &payload_hash)
+            .set("x-amz-date", &amz_date)
+            .set("authorization", &authorization);
+
+        for (name, value) in headers {
+            request = request.set(name, value);
+        }
+
+        debug!("S3 request: {} {}", method, url);
+
+        let result = match body {
+            Body::Bytes(data) => request.send_bytes(data),
+
+            // With a length, the body isn't sent chunked (S3 doesn't accept
+            // chunked uploads).
+            Body::Stream(reader, length) => request
+                .set("content-length", &length.to_string())
+                .send(reader),
+        };
+
+        match result {
+            Ok(response) => Ok(response),
+
+            Err(ureq::Error::Status(404, _)) => Err(StorageError::NotFound),
+
+            Err(ureq::Error::Status(status, response)) => {
+                error!(
+                    "S3 request {} {} failed ({}): {}",
+                    method, url, status,
+                    response.into_string().unwrap_or_default()
+                );
+                Err(StorageError::OtherError)
+            }
+
+            Err(e) => {
+                error!("S3 request {} {} failed: {}", method, url, e);
+                Err(StorageError::ConnectionError)
+            }
+        }
+    }
+
+    /// Returns the hex encoded signature of `string_to_sign`.
+    fn sign(&self, date: &str, string_to_sign: &str) -> Result<String, StorageError> {
+        let mut key = format!("AWS4{}", self.config.secret_key).into_bytes();
+
+        for part in [date, self.config.region.as_str(), "s3", "aws4_request",
+            string_to_sign] {
+            let mut mac = match HmacSha256::new_from_slice(&key) {
+                Ok(mac) => mac,
+                Err(e) => {
+                    error!("Error while signing S3 request: {}", e);
+                    return Err(StorageError::OtherError);
+                }
+            };
+
+            mac.update(part.as_bytes());
+            key = mac.finalize().into_bytes().to_vec();
+        }
+
+        Ok(hex::encode(key))
+    }
+
+    /// Uploads a file in parts, the upload is aborted on errors.
+    fn put_multipart(&self, key: &str, file: File, size: u64)
+        -> Result<(), StorageError> {
+        let path = self.object_path(Some(key));
+
+        let body = self.send(
+            "POST", &path, &[("uploads", "")], &[], Body::Bytes(&[])
+        )?.into_string().map_err(|_| StorageError::ConnectionError)?;
+
+        let upload_id = match UPLOAD_ID_RE.captures(&body) {
+            Some(c) => xml_unescape(&c[1]),
+            None => {
+                error!("S3 multipart upload of {} not created: {}", key, body);
+                return Err(StorageError::OtherError);
+            }
+        };
+
+        let result = self.upload_parts(&path, &upload_id, file, size);
+
+        if result.is_err() {
+            let _ = self.send(
+                "DELETE", &path, &[("uploadId", &upload_id)], &[], Body::Bytes(&[])
+            );
+        }
+
+        result
+    }
+
+    fn upload_parts(&self, path: &str, upload_id: &str, mut file: File, size: u64)
+        -> Result<(), StorageError> {
+        let part_size = self.part_size.max((size + MAX_PARTS - 1) / MAX_PARTS);
+        let mut parts = String::new();
+        let mut offset: u64 = 0;
+        let mut part_number: u64 = 1;
+
+        while offset < size {
+            let length = part_size.min(size - offset);
+            let number = part_number.to_string();
+
+            let response = self.send(
+                "PUT", path,
+                &[("partNumber", &number), ("uploadId", upload_id)], &[],
+                Body::Stream(Box::new((&mut file).take(length)), length),
+            )?;
+
+            let etag = match response.header("etag") {
+                Some(etag) => etag.to_string(),
+                None => {
+                    error!("S3 part {} of {} has no ETag", number, path);
+                    return Err(StorageError::OtherError);
+                }
+            };
+
+            parts.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                number, xml_escape(&etag)
+            ));
+
+            offset += length;
+            part_number += 1;
+        }
+
+        let complete = format!(
+            "<CompleteMultipartUpload>{}</CompleteMultipartUpload>", parts
+        );
+
+        let body = self.send(
+            "POST", path, &[("uploadId", upload_id)], &[],
+            Body::Bytes(complete.as_bytes()),
+        )?.into_string().map_err(|_| StorageError::ConnectionError)?;
+
+        // Completion errors can come with a `200 OK`.
+        if body.contains("<Error>") {
+            error!("S3 multipart upload of {} failed: {}", path, body);
+            return Err(StorageError::OtherError);
+        }
+
+        Ok(())
+    }
+}
+
+impl Storage for S3Storage {
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), StorageError> {
+        self.send("PUT", &self.object_path(Some(key)), &[], &[], Body::Bytes(data))?;
+
+        Ok(())
+    }
+
+    fn put_file(&self, key: &str, path: &str) -> Result<(), StorageError> {
+        let file = File::open(path).map_err(|e| {
+            error!("Error while opening {}: {}", path, e);
+            StorageError::IOError
+        })?;
+
+        let size = file.metadata().map_err(|_| StorageError::IOError)?.len();
+
+        if size > self.part_size {
+            return self.put_multipart(key, file, size);
+        }
+
+        self.send(
+            "PUT", &self.object_path(Some(key)), &[], &[],
+            Body::Stream(Box::new(file), size),
+        )?;
+
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>, StorageError> {
+        let mut data: Vec<u8> = vec![];
+
+        match self.stream(key, 0, None)?.read_to_end(&mut data) {
+            Ok(_) => Ok(data),
+            Err(e) => {
+                error!("Error while reading S3 object {}: {}", key, e);
+                Err(StorageError::ConnectionError)
+            }
+        }
+    }
+
+    fn stream(&self, key: &str, offset: u64, length: Option<u64>)
+        -> Result<Box<dyn Read + Send>, StorageError> {
+        let range = match length {
+            Some(0) => { return Ok(Box::new(std::io::empty())); }
+            Some(length) => Some(format!("bytes={}-{}", offset, offset + length - 1)),
+            None if offset > 0 => Some(format!("bytes={}-", offset)),
+            None => None,
+        };
+
+        let headers: Vec<(&str, String)> = match range {
+            Some(range) => vec![("range", range)],
+            None => vec![],
+        };
+
+        let response = self.send(
+            "GET", &self.object_path(Some(key)), &[], &headers, Body::Bytes(&[])
+        )?;
+
+        Ok(Box::new(response.into_reader()))
+    }
+
+    fn delete(&self, key: &str) -> Result<(), StorageError> {
+        match self.send(
+            "DELETE", &self.object_path(Some(key)), &[], &[], Body::Bytes(&[])
+        ) {
+            Ok(_) | Err(StorageError::NotFound) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn exists(&self, key: &str) -> Result<bool, StorageError> {
+        match self.send(
+            "HEAD", &self.object_path(Some(key)), &[], &[], Body::Bytes(&[])
+        ) {
+            Ok(_) => Ok(true),
+            Err(StorageError::NotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn version(&self, key: &str) -> Result<String, StorageError> {
+        let response = self.send(
+            "HEAD", &self.object_path(Some(key)), &[], &[], Body::Bytes(&[])
+        )?;
+
+        match response.header("etag") {
+            Some(etag) => Ok(etag.trim_matches('"').to_string()),
+            None => {
+                error!("S3 object {} has no ETag", key);
+                Err(StorageError::OtherError)
+            }
+        }
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, StorageError> {
+        let full_prefix = format!("{}/{}", self.prefix, prefix);
+        let object_prefix = format!("{}/", self.prefix);
+        let mut keys: Vec<String> = vec![];
+        let mut token: Option<String> = None;
+
+        loop {
+            let mut query: Vec<(&str, &str)> = vec![
+                ("list-type", "2"), ("prefix", full_prefix.as_str()),
+            ];
+
+            if let Some(token) = &token {
+                query.push(("continuation-token", token.as_str()));
+            }
+
+            let body = match self.send(
+                "GET", &self.object_path(None), &query, &[], Body::Bytes(&[])
+            )?.into_string() {
+                Ok(body) => body,
+                Err(e) => {
+                    error!("Error while reading S3 object list: {}", e);
+                    return Err(StorageError::ConnectionError);
+                }
+            };
+
+            for captures in KEY_RE.captures_iter(&body) {
+                let key = xml_unescape(&captures[1]);
+
+                if let Some(key) = key.strip_prefix(&object_prefix) {
+                    keys.push(key.to_string());
+                }
+            }
+
+            token = TOKEN_RE.captures(&body).map(|c| xml_unescape(&c[1]));
+
+            if token.is_none() {
+                break;
+            }
+        }
+
+        Ok(keys)
+    }
+}
+
+/// URI encodes a string as required by Signature Version 4, `/` is kept when
+/// `encode_slash` is `false`.
+fn uri_encode(value: &str, encode_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char);
+            }
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    encoded
+}
+
+fn xml_escape(value: &str) -> String {
+    value.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn xml_unescape(value: &str) -> String {
+    value.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Run against the MinIO of `docker-compose.minio.yml`, or the S3 storage set
+/// with the `S3_TEST_*` environment variables.
+#[cfg(test)]
+mod tests {
+    use std::{ env, fs::{ remove_file, write }, time::Duration };
+
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn test_storage() -> S3Storage {
+        let var = |name: &str, default: &str| {
+            env::var(name).unwrap_or_else(|_| default.to_string())
+        };
+
+        S3Storage::new(&S3Config {
+            endpoint: var("S3_TEST_ENDPOINT", "http://localhost:9000"),
+            region: var("S3_TEST_REGION", "us-east-1"),
+            bucket: var("S3_TEST_BUCKET", "swms-test"),
+            access_key: var("S3_TEST_ACCESS_KEY", "minioadmin"),
+            secret_key: var("S3_TEST_SECRET_KEY", "minioadmin"),
+            path_style: true,
+        }, &format!("test-{}", Uuid::new_v4()))
+    }
+
+    /// Returns `len` bytes that differ from part to part.
+    fn test_data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn temp_file(data: &[u8]) -> String {
+        let path = env::temp_dir().join(format!("s3-test-{}", Uuid::new_v4()));
+        write(&path, data).unwrap();
+
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn uri_encode_keeps_unreserved_characters() {
+        assert_eq!(uri_encode("a-b_c.d~e", true), "a-b_c.d~e");
+        assert_eq!(uri_encode("a b/c", true), "a%20b%2Fc");
+        assert_eq!(uri_encode("a b/c", false), "a%20b/c");
+    }
+
+    #[test]
+    fn xml_escape_round_trips() {
+        let value = "\"etag\" <&>";
+
+        assert_eq!(xml_unescape(&xml_escape(value)), value);
+    }
+
+    #[test]
+    #[ignore = "requires MinIO (docker-compose.minio.yml)"]
+    fn put_get_and_delete() {
+        let storage = test_storage();
+        let data = test_data(1000);
+
+        storage.put("a/b.jpg", &data).ok().unwrap();
+
+        assert!(storage.exists("a/b.jpg").ok().unwrap());
+        assert_eq!(storage.get("a/b.jpg").ok().unwrap(), data);
+
+        let mut range: Vec<u8> = vec![];
+        storage.stream("a/b.jpg", 10, Some(20)).ok().unwrap()
+            .read_to_end(&mut range).unwrap();
+        assert_eq!(range, data[10..30].to_vec());
+
+        assert_eq!(storage.list("a/").ok().unwrap(), vec![ String::from("a/b.jpg") ]);
+
+        storage.delete_prefix("a/").ok().unwrap();
+        assert!(!storage.exists("a/b.jpg").ok().unwrap());
+        assert!(matches!(storage.version("a/b.jpg"), Err(StorageError::NotFound)));
+    }
+
+    #[test]
+    #[ignore = "requires MinIO (docker-compose.minio.yml)"]
+    fn version_changes_when_replaced() {
+        let storage = test_storage();
+
+        storage.put("v.jpg", b"first").ok().unwrap();
+        let first = storage.version("v.jpg").ok().unwrap();
+
+        storage.put("v.jpg", b"second").ok().unwrap();
+        let second = storage.version("v.jpg").ok().unwrap();
+
+        assert_ne!(first, second);
+
+        storage.delete("v.jpg").ok().unwrap();
+    }
+
+    #[test]
+    #[ignore = "requires MinIO (docker-compose.minio.yml)"]
+    fn put_file_streams_small_files() {
+        let storage = test_storage();
+        let data = test_data(100_000);
+        let path = temp_file(&data);
+
+        storage.put_file("small.bin", &path).ok().unwrap();
+        assert_eq!(storage.get("small.bin").ok().unwrap(), data);
+
+        storage.delete("small.bin").ok().unwrap();
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    #[ignore = "requires MinIO (docker-compose.minio.yml)"]
+    fn put_file_uploads_large_files_in_parts() {
+        let mut storage = test_storage();
+        // The smallest part size S3 accepts.
+        storage.part_size = 5 * 1024 * 1024;
+
+        let data = test_data(11 * 1024 * 1024);
+        let path = temp_file(&data);
+
+        storage.put_file("large.bin", &path).ok().unwrap();
+        assert_eq!(storage.get("large.bin").ok().unwrap(), data);
+
+        // Multipart ETags are `{md5 of the parts' md5s}-{number of parts}`.
+        assert!(storage.version("large.bin").ok().unwrap().ends_with("-3"));
+
+        storage.delete("large.bin").ok().unwrap();
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    #[ignore = "requires MinIO (docker-compose.minio.yml)"]
+    fn fetch_file_revalidates_working_copies() {
+        let storage = test_storage();
+        let path = temp_file(b"");
+        remove_file(&path).unwrap();
+
+        storage.put("copy.jpg", b"first").ok().unwrap();
+        storage.fetch_file("copy.jpg", &path, Duration::ZERO).ok().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"first");
+
+        // Replaced by another node, the copy is trusted until it's too old
+        storage.put("copy.jpg", b"second").ok().unwrap();
+        storage.fetch_file("copy.jpg", &path, Duration::from_secs(60)).ok().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"first");
+
+        storage.fetch_file("copy.jpg", &path, Duration::ZERO).ok().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"second");
+
+        // Deleted by another node
+        storage.delete("copy.jpg").ok().unwrap();
+        assert!(storage.fetch_file("copy.jpg", &path, Duration::ZERO).is_err());
+        assert!(!std::path::Path::new(&path).exists());
+    }
+}